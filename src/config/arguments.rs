@@ -1,30 +1,338 @@
-use super::{SortBy, SortOrder};
-use clap::Parser;
+use super::schema::SchemaKind;
+use super::{DedupePolicy, SortBy, SortOrder};
+use crate::diagnostics::LogFormat;
+use clap::error::ErrorKind;
+use clap::{ArgAction, CommandFactory, Parser, Subcommand};
 use std::path::PathBuf;
 
-#[derive(Debug, Parser)]
-#[clap(author, version, about, long_about = None)]
+#[derive(Debug, Clone, Parser)]
+#[clap(
+    author,
+    version,
+    about,
+    long_about = None,
+    after_help = "Exit codes: 0 success, 1 other errors, 2 invalid arguments, 3 config errors, 4 input errors, 5 cancelled"
+)]
 pub struct Arguments {
-    #[clap(long, value_parser, value_name = "FILE")]
-    pub account_config_file: PathBuf,
-    #[clap(long, value_parser, value_name = "FILE")]
-    pub categories_config_file: PathBuf,
-    #[clap(long, value_parser, value_name = "FILE")]
-    pub src_format_config_file: PathBuf,
-    #[clap(long, value_parser, value_name = "FILE")]
-    pub dst_format_config_file: PathBuf,
-    #[clap(short = 'i', long, value_parser, value_name = "FILE")]
-    pub src_file: Option<PathBuf>,
-    #[clap(short = 'o', long, value_parser, value_name = "FILE")]
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+    /// Process every account listed in this file, instead of a single account
+    #[clap(long, global = true, alias = "workspace", value_parser, value_name = "FILE", conflicts_with_all = [
+        "account_config_file",
+        "categories_config_file",
+        "src_format_config_file",
+        "dst_format_config_file",
+        "src_format",
+        "dst_format",
+        "state_file",
+        "overrides_file",
+        "decisions_template",
+        "opening_balance",
+        "closing_balance",
+    ])]
+    pub manifest: Option<PathBuf>,
+    /// Only process the account with this ID in the manifest. Source files given on the command
+    /// line are imported into this account.
+    #[clap(long, global = true, value_name = "ID", requires = "manifest")]
+    pub account: Option<String>,
+    #[clap(long, global = true, value_parser, value_name = "FILE")]
+    pub account_config_file: Option<PathBuf>,
+    #[clap(long, global = true, value_parser, value_name = "FILE")]
+    pub categories_config_file: Option<PathBuf>,
+    /// Source formats to import with. Repeat to import files in different formats; each file is
+    /// imported with the format whose file name pattern or headers match it.
+    #[clap(long, global = true, value_parser, value_name = "FILE")]
+    pub src_format_config_file: Vec<PathBuf>,
+    /// Built-in source format presets to import with, by ID. See `presets` for the list.
+    #[clap(long, global = true, value_name = "ID")]
+    pub src_format: Vec<String>,
+    /// A directory of source formats. Each file is imported with the format that best matches
+    /// its headers and values.
+    #[clap(
+        long,
+        global = true,
+        value_parser,
+        value_name = "DIR",
+        conflicts_with = "manifest"
+    )]
+    pub src_format_dir: Option<PathBuf>,
+    #[clap(long, global = true, value_parser, value_name = "FILE")]
+    pub dst_format_config_file: Option<PathBuf>,
+    /// A built-in destination format preset, by ID
+    #[clap(
+        long,
+        global = true,
+        value_name = "ID",
+        conflicts_with = "dst_format_config_file"
+    )]
+    pub dst_format: Option<String>,
+    /// A file, glob pattern or directory to import. Repeat to import several. Reads stdin if not
+    /// given.
+    #[clap(short = 'i', long, global = true, value_name = "PATH", action = ArgAction::Append)]
+    pub src_file: Vec<String>,
+    /// The output file. With a manifest, all accounts are merged into this file.
+    #[clap(short = 'o', long, global = true, value_parser, value_name = "FILE")]
     pub dst_file: Option<PathBuf>,
-    #[clap(long, value_enum)]
+    #[clap(long, global = true, value_enum)]
     pub sort_by: Option<SortBy>,
-    #[clap(long, value_enum)]
+    #[clap(long, global = true, value_enum)]
     pub sort_order: Option<SortOrder>,
-    #[clap(long)]
+    #[clap(long, global = true)]
     pub include_header: Option<bool>,
-    #[clap(long)]
+    #[clap(long, global = true)]
     pub ignore_pending: Option<bool>,
-    #[clap(long)]
+    #[clap(long, global = true)]
     pub skip_prompts: Option<bool>,
+    /// Prompt to choose or create a payee for each raw payee that isn't normalized
+    #[clap(long, global = true)]
+    pub prompt_for_payees: Option<bool>,
+    /// How to handle duplicate transactions within the import. Overrides the policy in the
+    /// account config, and enables duplicate detection if the account doesn't configure it.
+    #[clap(long, global = true, value_enum)]
+    pub dedupe_policy: Option<DedupePolicy>,
+    /// Review and edit the transactions in a full screen view before exporting them. Replaces
+    /// the category and payee prompts.
+    #[clap(long, global = true)]
+    pub review: bool,
+    /// Answer category prompts using the decisions saved in this file
+    #[clap(long, global = true, value_parser, value_name = "FILE")]
+    pub decisions_file: Option<PathBuf>,
+    /// Write category prompts to this file instead of asking for them. Fill in the file and pass
+    /// it to `--decisions-file` to replay the run.
+    #[clap(long, global = true, value_parser, value_name = "FILE")]
+    pub decisions_template: Option<PathBuf>,
+    /// Skip transactions that were exported by a previous run, and remember the exported
+    /// transactions for the next run
+    #[clap(long, global = true)]
+    pub since_last_run: bool,
+    /// File where exported transactions are remembered. Defaults to the account config file name
+    /// with a `.state.json` extension
+    #[clap(long, global = true, value_parser, value_name = "FILE")]
+    pub state_file: Option<PathBuf>,
+    /// File where remembered category choices are saved. Defaults to the account config file
+    /// name with an `.overrides.json` extension
+    #[clap(long, global = true, value_parser, value_name = "FILE")]
+    pub overrides_file: Option<PathBuf>,
+    /// The balance of the account before the first imported transaction, used to compute the
+    /// running balance. Overrides the opening balance read from the statement preamble.
+    #[clap(long, global = true, value_name = "AMOUNT", allow_hyphen_values = true)]
+    pub opening_balance: Option<String>,
+    /// The balance of the account after the last imported transaction. Overrides the closing
+    /// balance read from the statement.
+    #[clap(long, global = true, value_name = "AMOUNT", allow_hyphen_values = true)]
+    pub closing_balance: Option<String>,
+    /// Check that the opening balance plus the imported transactions equals the closing balance
+    /// before exporting
+    #[clap(long, global = true)]
+    pub reconcile: bool,
+    /// Only report errors
+    #[clap(short = 'q', long, global = true, conflicts_with = "verbose")]
+    pub quiet: bool,
+    /// Also report details of how each transaction was processed
+    #[clap(short = 'v', long, global = true)]
+    pub verbose: bool,
+    /// How to write errors, warnings and other messages to stderr
+    #[clap(long, global = true, value_enum, default_value_t)]
+    pub log_format: LogFormat,
+}
+
+impl Arguments {
+    /// Check the arguments that clap can't check by itself. The file arguments are global, and
+    /// which of them are required depends on the subcommand.
+    pub fn check_required(&self) -> Result<(), clap::Error> {
+        let exports = match self.command {
+            Option::None | Option::Some(Command::Convert) => true,
+            Option::Some(Command::Schema { .. })
+            | Option::Some(Command::Presets { .. })
+            | Option::Some(Command::InferFormat { .. })
+            | Option::Some(Command::ConvertConfig { .. }) => {
+                return Ok(());
+            }
+            Option::Some(_) => false,
+        };
+        if self.manifest.is_some() {
+            return Ok(());
+        }
+        let mut missing = Vec::new();
+        if self.account_config_file.is_none() {
+            missing.push("--account-config-file <FILE>");
+        }
+        if self.categories_config_file.is_none() {
+            missing.push("--categories-config-file <FILE>");
+        }
+        if exports && self.dst_format_config_file.is_none() && self.dst_format.is_none() {
+            missing.push("--dst-format-config-file <FILE>|--dst-format <ID>");
+        }
+        if missing.is_empty() {
+            return Ok(());
+        }
+        Err(Arguments::command().error(
+            ErrorKind::MissingRequiredArgument,
+            format!(
+                "the following required arguments were not provided:\n  {}",
+                missing.join("\n  ")
+            ),
+        ))
+    }
+}
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum Command {
+    /// Import, categorize and export the source files. This is the default without a subcommand.
+    Convert,
+    /// Check that the config files are valid, and report every error
+    Validate,
+    /// Check the config files for errors and likely mistakes, with the location of each problem.
+    /// Source files are used as samples to find normalizers that never match.
+    Lint,
+    /// Work with the payees of the account, or of each account in the manifest
+    Payees {
+        #[clap(subcommand)]
+        command: Option<PayeesCommand>,
+    },
+    /// List the categories, with the number of payees in each
+    Categories,
+    /// Show the raw values of each imported transaction, how its amount and type were read, which
+    /// normalizers matched its payee and how its category was chosen. Nothing is exported.
+    Explain {
+        /// Only explain transactions with a raw value that contains this text, ignoring case
+        #[clap(long, value_name = "TEXT")]
+        filter: Option<String>,
+    },
+    /// Report the raw payees that aren't normalized and the payees that aren't categorized, with
+    /// suggested payees for the raw payees. Nothing is prompted for or exported.
+    Report {
+        /// Print the suggested payees as a JSON patch for the account config file instead
+        #[clap(long)]
+        json_patch: bool,
+    },
+    /// Print the JSON Schema of a config file type, or write the schemas to a directory
+    Schema {
+        #[clap(value_enum)]
+        kind: Option<SchemaKind>,
+        /// Write the schema of every type, or of the chosen type, to this directory
+        #[clap(long, value_parser, value_name = "DIR")]
+        out_dir: Option<PathBuf>,
+    },
+    /// List the built-in format presets, or print the format config of a preset
+    Presets {
+        id: Option<String>,
+        /// Print the sample file of the preset instead
+        #[clap(long, requires = "id")]
+        sample: bool,
+    },
+    /// Draft a source format config from a sample file. The draft is written to the output file,
+    /// or stdout.
+    InferFormat {
+        #[clap(value_parser, value_name = "SAMPLE")]
+        sample: PathBuf,
+    },
+    /// Convert a JSON, YAML or TOML config file to the format given by the destination file's
+    /// extension
+    ConvertConfig {
+        #[clap(value_parser, value_name = "SRC")]
+        src: PathBuf,
+        #[clap(value_parser, value_name = "DST")]
+        dst: PathBuf,
+    },
+}
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum PayeesCommand {
+    /// List the payees, with their categories. This is the default.
+    List,
+    /// Try a candidate normalizer on the source files, without saving it. Lists every raw payee
+    /// it matches and the payee that currently claims each one.
+    Test {
+        /// The matcher type of the normalizer
+        #[clap(long = "type", value_enum, value_name = "TYPE")]
+        matcher: MatcherKind,
+        /// The match string of the normalizer
+        pattern: String,
+        /// Whether the normalizer ignores case, as in the account config
+        #[clap(long, value_name = "BOOL")]
+        ignore_case: Option<bool>,
+        /// The threshold of a fuzzy normalizer
+        #[clap(long, value_name = "SCORE")]
+        threshold: Option<f64>,
+    },
+}
+
+/// The matcher types of a payee normalizer
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum MatcherKind {
+    Exact,
+    Contains,
+    Regex,
+    Fuzzy,
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config::Arguments;
+    use crate::config::arguments::{Command, PayeesCommand};
+    use clap::error::ErrorKind;
+    use clap::{CommandFactory, Parser};
+
+    #[test]
+    fn test_arguments() {
+        Arguments::command().debug_assert();
+    }
+
+    #[test]
+    fn test_src_file() {
+        // Each --src-file takes one path, so it doesn't take a positional argument with it
+        let args = Arguments::parse_from([
+            "transaction_processor",
+            "payees",
+            "test",
+            "--type",
+            "contains",
+            "-i",
+            "a.csv",
+            "-i",
+            "b.csv",
+            "STARBUCKS",
+        ]);
+        assert_eq!(args.src_file, vec!["a.csv", "b.csv"]);
+        assert!(matches!(
+            args.command,
+            Option::Some(Command::Payees {
+                command: Option::Some(PayeesCommand::Test { ref pattern, .. })
+            }) if pattern == "STARBUCKS"
+        ));
+    }
+
+    #[test]
+    fn test_check_required() {
+        let check = |args: &[&str]| {
+            Arguments::parse_from(["transaction_processor"].iter().chain(args))
+                .check_required()
+                .map_err(|e| e.kind())
+        };
+        let files = [
+            "--account-config-file",
+            "checking.json",
+            "--categories-config-file",
+            "categories.json",
+        ];
+        assert_eq!(check(&files), Err(ErrorKind::MissingRequiredArgument));
+        assert_eq!(
+            check(&[&files[..], &["--dst-format", "ynab"]].concat()),
+            Ok(())
+        );
+        assert_eq!(
+            check(&[&["lint"], &files[..2]].concat()),
+            Err(ErrorKind::MissingRequiredArgument)
+        );
+        // Only convert exports, so only convert needs a destination format
+        assert_eq!(check(&[&["lint"], &files[..]].concat()), Ok(()));
+        assert_eq!(check(&["lint", "--manifest", "manifest.json"]), Ok(()));
+        assert_eq!(check(&["presets"]), Ok(()));
+        assert_eq!(check(&["infer-format", "sample.csv"]), Ok(()));
+        assert_eq!(
+            check(&["convert-config", "checking.json", "checking.yaml"]),
+            Ok(())
+        );
+    }
 }
@@ -0,0 +1,150 @@
+use crate::diagnostics::Diagnostic;
+use crate::util;
+use anyhow::anyhow;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::io::Read;
+use std::path::Path;
+
+/// The syntax of a config file, chosen by its extension. Files without a YAML or TOML extension
+/// are JSON.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FileFormat {
+    Json,
+    Yaml,
+    Toml,
+}
+
+impl FileFormat {
+    pub fn from_path(filename: &Path) -> FileFormat {
+        let extension = filename
+            .extension()
+            .and_then(|x| x.to_str())
+            .map(str::to_lowercase);
+        match extension.as_deref() {
+            Option::Some("yaml") | Option::Some("yml") => FileFormat::Yaml,
+            Option::Some("toml") => FileFormat::Toml,
+            _ => FileFormat::Json,
+        }
+    }
+
+    /// Whether the file has the extension of a config file
+    pub fn is_config_file(filename: &Path) -> bool {
+        filename
+            .extension()
+            .and_then(|x| x.to_str())
+            .is_some_and(|x| ["json", "yaml", "yml", "toml"].contains(&x.to_lowercase().as_str()))
+    }
+
+    pub fn parse<T: DeserializeOwned>(self, s: &str) -> anyhow::Result<T> {
+        Ok(match self {
+            FileFormat::Json => serde_json::from_str(s)?,
+            FileFormat::Yaml => serde_yaml::from_str(s)?,
+            FileFormat::Toml => toml::from_str(s)?,
+        })
+    }
+
+    /// TOML has no null, so null values are left out. Missing and null values mean the same
+    /// thing in every config file.
+    pub fn to_string<T: Serialize>(self, value: &T) -> anyhow::Result<String> {
+        Ok(match self {
+            FileFormat::Json => serde_json::to_string_pretty(value)?,
+            FileFormat::Yaml => serde_yaml::to_string(value)?,
+            FileFormat::Toml => {
+                let mut value = serde_json::to_value(value)?;
+                remove_nulls(&mut value);
+                toml::to_string_pretty(&value)?
+            }
+        })
+    }
+}
+
+fn remove_nulls(value: &mut Value) {
+    match value {
+        Value::Object(m) => {
+            m.retain(|_, v| !v.is_null());
+            m.values_mut().for_each(remove_nulls);
+        }
+        Value::Array(a) => a.iter_mut().for_each(remove_nulls),
+        _ => {}
+    }
+}
+
+/// Read a config file in the format given by its extension
+pub fn read_config_file<T: DeserializeOwned>(filename: &Path) -> anyhow::Result<T> {
+    let mut s = String::new();
+    util::reader_from_file_name(filename)?
+        .read_to_string(&mut s)
+        .map_err(|e| {
+            anyhow!(
+                "Unable to read file [{}]: {}",
+                filename.to_str().unwrap_or("Invalid file name"),
+                e
+            )
+        })?;
+    FileFormat::from_path(filename).parse(&s)
+}
+
+/// Write a config file in the format given by its extension, keeping a backup of the previous
+/// version. Comments in YAML and TOML files are not kept.
+pub fn write_config_file<T: Serialize>(filename: &Path, value: &T) -> anyhow::Result<()> {
+    let s = FileFormat::from_path(filename).to_string(value)?;
+    util::write_file_with_backup(filename, &s)
+}
+
+/// Convert a config file to the format given by the extension of the destination file. Fails
+/// without writing anything if the converted file wouldn't read back the same.
+pub fn convert_config_file(src: &Path, dst: &Path) -> anyhow::Result<()> {
+    let mut value: Value = read_config_file(src).map_err(|e| {
+        anyhow!(
+            "Unable to parse file [{}]: {}",
+            src.to_str().unwrap_or("Invalid file name"),
+            e
+        )
+    })?;
+    let format = FileFormat::from_path(dst);
+    let s = format.to_string(&value)?;
+    if format == FileFormat::Toml {
+        remove_nulls(&mut value);
+    }
+    let converted: Value = format.parse(&s)?;
+    if converted != value {
+        return Err(anyhow!(
+            "Unable to convert [{}] without changing it, e.g. because of numbers too large for the destination format.",
+            src.to_str().unwrap_or("Invalid file name")
+        ));
+    }
+    util::write_file_with_backup(dst, &s)?;
+    Diagnostic::info(format!(
+        "Converted [{}] to [{}]",
+        src.to_str().unwrap_or("Invalid file name"),
+        dst.to_str().unwrap_or("Invalid file name")
+    ))
+    .emit();
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config::FormatConfigFile;
+    use crate::config::file_format::FileFormat;
+    use serde_json::Value;
+
+    #[test]
+    fn test_formats_are_equivalent() {
+        let json = r#"{"id": "bank", "name": "Bank", "dataFormat": "csv",
+            "fieldOrder": ["Date", "Description", "Amount"],
+            "dateTimeConfig": {"dateField": "Date", "dateFormat": "%Y-%m-%d", "timeField": null},
+            "payeeConfig": {"fieldName": "Description"},
+            "amountConfig": {"format": {"type": "SingleAmountField", "fieldName": "Amount", "debitIsNegative": true}}}"#;
+        let value: Value = FileFormat::Json.parse(json).unwrap();
+        for format in [FileFormat::Json, FileFormat::Yaml, FileFormat::Toml] {
+            let s = format.to_string(&value).unwrap();
+            let f: FormatConfigFile = format.parse(&s).unwrap();
+            assert_eq!(f.id, "bank");
+            assert_eq!(f.date_time_config.time_field, Option::None);
+            assert_eq!(f.field_order, vec!["Date", "Description", "Amount"]);
+        }
+    }
+}
@@ -0,0 +1,588 @@
+use crate::config::{FormatConfigFile, validate_format};
+use anyhow::anyhow;
+use chrono::{Datelike, NaiveDate};
+use currency::Currency;
+use num::Signed;
+use serde_json::{Map, Value, json};
+use std::fmt;
+use std::fmt::Display;
+
+// Delimiters to try, in order of preference
+const DELIMITERS: [u8; 4] = [b',', b';', b'\t', b'|'];
+// Date formats to try, in order of preference when several parse every value
+const DATE_FORMATS: [&str; 12] = [
+    "%Y-%m-%d",
+    "%m/%d/%Y",
+    "%d/%m/%Y",
+    "%m/%d/%y",
+    "%d/%m/%y",
+    "%Y/%m/%d",
+    "%d.%m.%Y",
+    "%m-%d-%Y",
+    "%d-%m-%Y",
+    "%Y%m%d",
+    "%b %d, %Y",
+    "%d %b %Y",
+];
+// How many rows are inspected
+const SAMPLE_ROWS: usize = 100;
+
+const PAYEE_NAMES: [&str; 5] = ["payee", "description", "merchant", "name", "narrative"];
+const MEMO_NAMES: [&str; 4] = ["memo", "note", "details", "reference"];
+const DEBIT_NAMES: [&str; 3] = ["debit", "withdrawal", "out"];
+const CREDIT_NAMES: [&str; 3] = ["credit", "deposit", "in"];
+const DEBIT_TYPES: [&str; 6] = ["debit", "dr", "d", "withdrawal", "sale", "purchase"];
+const CREDIT_TYPES: [&str; 7] = [
+    "credit", "cr", "c", "deposit", "payment", "return", "refund",
+];
+const STATUSES: [&str; 7] = [
+    "pending",
+    "posted",
+    "cleared",
+    "complete",
+    "completed",
+    "booked",
+    "settled",
+];
+
+#[derive(Debug, Clone, Copy)]
+enum Confidence {
+    High,
+    Medium,
+    Low,
+}
+
+impl Display for Confidence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Confidence::High => write!(f, "high"),
+            Confidence::Medium => write!(f, "medium"),
+            Confidence::Low => write!(f, "low"),
+        }
+    }
+}
+
+fn note<S: Display>(confidence: Confidence, reason: S) -> Value {
+    json!(format!("{} confidence: {}", confidence, reason))
+}
+
+/// The delimiter of the file, and where its header row is
+struct Dialect {
+    delimiter: u8,
+    /// Lines before the header row
+    preamble_lines: usize,
+    /// Records before the header row
+    header_index: usize,
+    width: usize,
+    /// Rows after the header row with as many columns as the header, and the number of rows
+    consistent_rows: usize,
+    total_rows: usize,
+}
+
+impl Dialect {
+    fn note(&self) -> Value {
+        let confidence = if self.consistent_rows == self.total_rows {
+            Confidence::High
+        } else {
+            Confidence::Medium
+        };
+        let name = match self.delimiter {
+            b',' => "comma",
+            b';' => "semicolon",
+            b'\t' => "tab",
+            _ => "pipe",
+        };
+        note(
+            confidence,
+            format!(
+                "{} delimited, {} of {} rows have {} columns",
+                name, self.consistent_rows, self.total_rows, self.width
+            ),
+        )
+    }
+}
+
+/// A column of the sample, with its trimmed, non-empty values
+struct Column {
+    name: String,
+    values: Vec<String>,
+}
+
+impl Column {
+    fn is_named(&self, words: &[&str]) -> bool {
+        let name = self.name.to_lowercase();
+        name.split(|c: char| !c.is_alphanumeric())
+            .any(|word| words.contains(&word))
+    }
+
+    /// The amounts in the column, if every value is an amount
+    fn amounts(&self) -> Option<Vec<Currency>> {
+        if self.values.is_empty() {
+            return Option::None;
+        }
+        self.values
+            .iter()
+            .map(|v| {
+                // Currency parses any string, ignoring the characters that aren't digits
+                let is_amount = v.chars().any(|c| c.is_ascii_digit())
+                    && v.chars()
+                        .all(|c| c.is_ascii_digit() || "+-.,()$€£¥ ".contains(c));
+                if is_amount {
+                    Currency::from_str(v).ok()
+                } else {
+                    Option::None
+                }
+            })
+            .collect()
+    }
+
+    /// The date formats that parse every value in the column
+    fn date_formats(&self) -> Vec<&'static str> {
+        if self.values.is_empty() {
+            return Vec::new();
+        }
+        DATE_FORMATS
+            .into_iter()
+            .filter(|f| {
+                self.values
+                    .iter()
+                    .all(|v| NaiveDate::parse_from_str(v, f).is_ok_and(|d| d.year() >= 1900))
+            })
+            .collect()
+    }
+
+    fn is_amount(&self) -> bool {
+        self.amounts().is_some() && self.date_formats().is_empty()
+    }
+
+    fn is_text(&self) -> bool {
+        self.amounts().is_none() && self.date_formats().is_empty()
+    }
+
+    fn distinct(&self) -> Vec<&str> {
+        let mut distinct = Vec::new();
+        for v in self.values.iter() {
+            if !distinct.contains(&v.as_str()) {
+                distinct.push(v.as_str());
+            }
+        }
+        distinct
+    }
+}
+
+/// The columns that haven't been assigned to a field yet
+struct Columns {
+    columns: Vec<Column>,
+    used: Vec<usize>,
+}
+
+impl Columns {
+    fn find<P: Fn(&Column) -> bool>(&self, predicate: P) -> Option<usize> {
+        (0..self.columns.len()).find(|i| !self.used.contains(i) && predicate(&self.columns[*i]))
+    }
+
+    fn take(&mut self, i: usize) -> &Column {
+        self.used.push(i);
+        &self.columns[i]
+    }
+}
+
+/// Draft a source format config from a sample file. Each guess is explained by a `note` key
+/// saying how confident it is, and the `notes` key describes the file and whether the draft
+/// passes validation.
+pub fn infer_format(id: &str, contents: &[u8]) -> anyhow::Result<Value> {
+    let dialect = DELIMITERS
+        .into_iter()
+        .filter_map(|d| detect_dialect(contents, d))
+        .reduce(|best, d| {
+            if d.consistent_rows > best.consistent_rows {
+                d
+            } else {
+                best
+            }
+        })
+        .ok_or_else(|| anyhow!("Unable to find a header row with more than one column."))?;
+    let (headers, columns) = read_columns(contents, &dialect)?;
+    let mut columns = Columns {
+        columns,
+        used: Vec::new(),
+    };
+
+    let date_time_config = infer_date(&mut columns);
+    let balance_config = infer_balance(&mut columns);
+    let amount_config = infer_amount(&mut columns);
+    let status_config = infer_status(&mut columns);
+    let payee_config = infer_payee(&mut columns);
+    let memo_config = columns
+        .find(|c| c.is_text() && c.is_named(&MEMO_NAMES))
+        .map(|i| {
+            json!({
+                "fieldName": columns.take(i).name,
+                "note": note(Confidence::High, "the column is named like a memo"),
+            })
+        });
+
+    let mut notes = vec![dialect.note()];
+    let mut config = Map::new();
+    config.insert("id".to_owned(), json!(id));
+    config.insert("name".to_owned(), json!(id));
+    config.insert("dataFormat".to_owned(), json!("csv"));
+    if dialect.delimiter != b',' {
+        config.insert(
+            "delimiter".to_owned(),
+            json!((dialect.delimiter as char).to_string()),
+        );
+    }
+    config.insert("fieldOrder".to_owned(), json!(headers));
+    if dialect.preamble_lines > 0 {
+        config.insert(
+            "preambleConfig".to_owned(),
+            json!({ "lineCount": dialect.preamble_lines }),
+        );
+        notes.push(note(
+            Confidence::Medium,
+            format!(
+                "skipped {} lines before the header row",
+                dialect.preamble_lines
+            ),
+        ));
+    }
+    config.insert("dateTimeConfig".to_owned(), date_time_config);
+    config.insert("payeeConfig".to_owned(), payee_config);
+    config.insert("amountConfig".to_owned(), amount_config);
+    let optional = [
+        ("balanceConfig", balance_config),
+        ("statusConfig", status_config),
+        ("memoConfig", memo_config),
+    ];
+    for (key, value) in optional {
+        if let Option::Some(value) = value {
+            config.insert(key.to_owned(), value);
+        }
+    }
+
+    let validation = serde_json::from_value::<FormatConfigFile>(Value::Object(config.clone()))
+        .map_err(anyhow::Error::from)
+        .and_then(|f| validate_format(&f));
+    notes.push(match validation {
+        Ok(()) => json!("The draft passes validation."),
+        Err(e) => json!(format!("The draft does not pass validation: {}", e)),
+    });
+    config.insert("notes".to_owned(), json!(notes));
+    Ok(Value::Object(config))
+}
+
+/// The header row is the first row with the most common number of columns
+fn detect_dialect(contents: &[u8], delimiter: u8) -> Option<Dialect> {
+    let records: Vec<csv::StringRecord> = csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(contents)
+        .records()
+        .take(SAMPLE_ROWS)
+        .filter_map(Result::ok)
+        .collect();
+    let count = |width: usize| records.iter().filter(|r| r.len() == width).count();
+    let width = records
+        .iter()
+        .map(csv::StringRecord::len)
+        .max_by_key(|w| (count(*w), *w))?;
+    if width < 2 {
+        return Option::None;
+    }
+    let header_index = records.iter().position(|r| r.len() == width)?;
+    let preamble_lines = records[header_index]
+        .position()
+        .map_or(0, |p| p.line() as usize - 1);
+    Option::Some(Dialect {
+        delimiter,
+        preamble_lines,
+        header_index,
+        width,
+        consistent_rows: count(width) - 1,
+        total_rows: records.len() - header_index - 1,
+    })
+}
+
+fn read_columns(contents: &[u8], dialect: &Dialect) -> anyhow::Result<(Vec<String>, Vec<Column>)> {
+    let mut records = csv::ReaderBuilder::new()
+        .delimiter(dialect.delimiter)
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(contents)
+        .into_records()
+        .skip(dialect.header_index);
+    let headers: Vec<String> = match records.next() {
+        Option::Some(r) => r?.iter().map(str::to_owned).collect(),
+        Option::None => return Err(anyhow!("Unable to read the header row.")),
+    };
+    let mut columns: Vec<Column> = headers
+        .iter()
+        .map(|h| Column {
+            name: h.to_owned(),
+            values: Vec::new(),
+        })
+        .collect();
+    for record in records
+        .take(SAMPLE_ROWS)
+        .filter_map(Result::ok)
+        .filter(|r| r.len() == dialect.width)
+    {
+        for (column, value) in columns.iter_mut().zip(record.iter()) {
+            if !value.trim().is_empty() {
+                column.values.push(value.trim().to_owned());
+            }
+        }
+    }
+    Ok((headers, columns))
+}
+
+fn infer_date(columns: &mut Columns) -> Value {
+    let candidates: Vec<usize> = (0..columns.columns.len())
+        .filter(|i| !columns.columns[*i].date_formats().is_empty())
+        .collect();
+    let chosen = candidates
+        .iter()
+        .find(|i| columns.columns[**i].is_named(&["date"]))
+        .or(candidates.first());
+    let i = match chosen {
+        Option::Some(i) => *i,
+        Option::None => {
+            return json!({
+                "dateField": "",
+                "dateFormat": "",
+                "note": note(Confidence::Low, "no column has dates in a known format"),
+            });
+        }
+    };
+    let others: Vec<String> = candidates
+        .iter()
+        .filter(|c| **c != i)
+        .map(|c| columns.columns[*c].name.to_owned())
+        .collect();
+    let mut reason = String::new();
+    let c = columns.take(i);
+    let formats = c.date_formats();
+    let confidence = if formats.len() > 1 {
+        reason.push_str(&format!(
+            "every value parses with [{}], assumed the first",
+            formats.join("], [")
+        ));
+        Confidence::Medium
+    } else {
+        reason.push_str(&format!(
+            "{} of {} values parse",
+            c.values.len(),
+            c.values.len()
+        ));
+        Confidence::High
+    };
+    if !others.is_empty() {
+        reason.push_str(&format!(", also found dates in [{}]", others.join(", ")));
+    }
+    json!({
+        "dateField": c.name,
+        "dateFormat": formats[0],
+        "note": note(confidence, reason),
+    })
+}
+
+fn infer_balance(columns: &mut Columns) -> Option<Value> {
+    let i = columns.find(|c| c.is_amount() && c.is_named(&["balance"]))?;
+    Option::Some(json!({
+        "fieldName": columns.take(i).name,
+        "note": note(Confidence::High, "the column is named like a balance"),
+    }))
+}
+
+fn infer_amount(columns: &mut Columns) -> Value {
+    let debit = columns.find(|c| c.is_amount() && c.is_named(&DEBIT_NAMES));
+    let credit = columns.find(|c| c.is_amount() && c.is_named(&CREDIT_NAMES));
+    if let (Option::Some(debit), Option::Some(credit)) = (debit, credit) {
+        return json!({
+            "format": {
+                "type": "SeparateDebitCreditFields",
+                "debitField": columns.take(debit).name,
+                "creditField": columns.take(credit).name,
+            },
+            "note": note(Confidence::High, "the file has separate debit and credit columns"),
+        });
+    }
+
+    let amount = columns
+        .find(|c| c.is_amount() && c.is_named(&["amount", "value"]))
+        .or_else(|| columns.find(Column::is_amount));
+    let amount = match amount {
+        Option::Some(i) => i,
+        Option::None => {
+            return json!({
+                "format": { "type": "SingleAmountField", "fieldName": "" },
+                "note": note(Confidence::Low, "no column has an amount in every row"),
+            });
+        }
+    };
+    let is_type = |c: &Column| {
+        let distinct = c.distinct();
+        let known = distinct.iter().all(|v| {
+            let v = v.to_lowercase();
+            DEBIT_TYPES.contains(&v.as_str()) || CREDIT_TYPES.contains(&v.as_str())
+        });
+        c.is_text() && distinct.len() == 2 && (known || c.is_named(&["type"]))
+    };
+    // A type column is only needed when the amounts aren't signed
+    let amounts = columns.columns[amount].amounts().unwrap_or_default();
+    let negative = amounts.iter().filter(|a| a.value().is_negative()).count();
+    let positive = amounts.len() - negative;
+    let type_column = if negative == 0 {
+        columns.find(is_type)
+    } else {
+        Option::None
+    };
+    if let Option::Some(i) = type_column {
+        let amount_field = columns.take(amount).name.to_owned();
+        let c = columns.take(i);
+        let distinct = c.distinct();
+        let (debit, credit, confidence) =
+            if CREDIT_TYPES.contains(&distinct[0].to_lowercase().as_str()) {
+                (distinct[1], distinct[0], Confidence::High)
+            } else if DEBIT_TYPES.contains(&distinct[0].to_lowercase().as_str()) {
+                (distinct[0], distinct[1], Confidence::High)
+            } else {
+                (distinct[0], distinct[1], Confidence::Low)
+            };
+        return json!({
+            "format": {
+                "type": "TransactionTypeAndAmountFields",
+                "amountField": amount_field,
+                "transactionTypeField": c.name,
+                "creditString": credit,
+                "debitString": debit,
+            },
+            "note": note(
+                confidence,
+                format!("the type column has the values [{}] and [{}]", debit, credit)
+            ),
+        });
+    }
+
+    let c = columns.take(amount);
+    let (debit_is_negative, confidence, reason) = if negative > 0 && positive > 0 {
+        (
+            negative >= positive,
+            Confidence::Medium,
+            format!(
+                "{} negative and {} positive amounts, assumed the more common sign is debits",
+                negative, positive
+            ),
+        )
+    } else if negative > 0 {
+        (
+            true,
+            Confidence::Medium,
+            String::from("every amount is negative, assumed they are debits"),
+        )
+    } else {
+        (
+            false,
+            Confidence::Low,
+            String::from("every amount is positive, assumed they are debits"),
+        )
+    };
+    json!({
+        "format": {
+            "type": "SingleAmountField",
+            "fieldName": c.name,
+            "debitIsNegative": debit_is_negative,
+        },
+        "note": note(confidence, reason),
+    })
+}
+
+fn infer_status(columns: &mut Columns) -> Option<Value> {
+    let i = columns.find(|c| {
+        let distinct = c.distinct();
+        let known = distinct
+            .iter()
+            .all(|v| STATUSES.contains(&v.to_lowercase().as_str()));
+        c.is_text() && distinct.len() <= 2 && (known || c.is_named(&["status"]))
+    })?;
+    let c = columns.take(i);
+    let distinct = c.distinct();
+    let pending = distinct
+        .iter()
+        .find(|v| v.to_lowercase().starts_with("pend"));
+    let cleared = distinct.iter().find(|v| Option::Some(*v) != pending);
+    let (pending, cleared, confidence) = match (pending, cleared) {
+        (Option::Some(p), Option::Some(c)) => (*p, *c, Confidence::High),
+        (Option::Some(p), Option::None) => (*p, "Posted", Confidence::Low),
+        (Option::None, Option::Some(c)) => ("Pending", *c, Confidence::Low),
+        (Option::None, Option::None) => ("Pending", "Posted", Confidence::Low),
+    };
+    Option::Some(json!({
+        "fieldName": c.name,
+        "pendingString": pending,
+        "clearedString": cleared,
+        "note": note(
+            confidence,
+            format!("the column has the values [{}]", distinct.join("], ["))
+        ),
+    }))
+}
+
+fn infer_payee(columns: &mut Columns) -> Value {
+    if let Option::Some(i) = columns.find(|c| c.is_text() && c.is_named(&PAYEE_NAMES)) {
+        return json!({
+            "fieldName": columns.take(i).name,
+            "note": note(Confidence::High, "the column is named like a payee"),
+        });
+    }
+    let most_distinct = (0..columns.columns.len())
+        .filter(|i| !columns.used.contains(i) && columns.columns[*i].is_text())
+        .max_by_key(|i| columns.columns[*i].distinct().len());
+    match most_distinct {
+        Option::Some(i) => json!({
+            "fieldName": columns.take(i).name,
+            "note": note(Confidence::Low, "the text column with the most distinct values"),
+        }),
+        Option::None => json!({
+            "fieldName": "",
+            "note": note(Confidence::Low, "no column looks like a payee"),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config::infer::infer_format;
+
+    #[test]
+    fn test_infer_format() {
+        let sample = b"Account;Checking\n\
+            Date;Description;Withdrawal;Deposit;Balance\n\
+            24/01/2024;STARBUCKS;4.10;;95.90\n\
+            25/01/2024;PAYROLL;;1000.00;1095.90\n";
+        let f = infer_format("bank", sample).unwrap();
+        assert_eq!(f["delimiter"], ";");
+        assert_eq!(f["preambleConfig"]["lineCount"], 1);
+        assert_eq!(f["dateTimeConfig"]["dateFormat"], "%d/%m/%Y");
+        assert_eq!(f["payeeConfig"]["fieldName"], "Description");
+        assert_eq!(
+            f["amountConfig"]["format"]["type"],
+            "SeparateDebitCreditFields"
+        );
+        assert_eq!(f["balanceConfig"]["fieldName"], "Balance");
+        assert_eq!(f["notes"][2], "The draft passes validation.");
+
+        let sample = b"Posted,Merchant,Amount,Status\n\
+            2024-01-09,STARBUCKS,-4.10,Pending\n\
+            2024-01-10,COSTCO,-30.00,Posted\n\
+            2024-01-11,REFUND,2.00,Posted\n";
+        let f = infer_format("card", sample).unwrap();
+        assert_eq!(f["dateTimeConfig"]["dateField"], "Posted");
+        assert_eq!(f["amountConfig"]["format"]["debitIsNegative"], true);
+        assert_eq!(f["statusConfig"]["pendingString"], "Pending");
+        assert_eq!(f["statusConfig"]["clearedString"], "Posted");
+        assert_eq!(f["notes"][1], "The draft passes validation.");
+    }
+}
@@ -0,0 +1,411 @@
+use crate::config::manifest::Manifest;
+use crate::config::{
+    AccountConfigFile, AccountFiles, Arguments, CategoriesConfigFile, Config, DecisionsFile,
+    FormatConfigFile, MatcherType, OverridesFile, PayeeNormalizerConfig, config_problems,
+    overrides_file_name,
+};
+use crate::transaction::payee::Matcher;
+use crate::transaction::transaction_io::TransactionIO;
+use anyhow::anyhow;
+use regex::Regex;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A problem with a config file. The path is where in the file the problem is, e.g.
+/// `payees[amazon].normalizers[0]`. The line is only known for files that can't be parsed.
+#[derive(Debug, Clone)]
+pub struct Problem {
+    pub severity: Severity,
+    pub file: PathBuf,
+    pub path: String,
+    pub message: String,
+    pub line: Option<usize>,
+}
+
+impl Problem {
+    pub fn error(file: &Path, path: impl Into<String>, message: String) -> Problem {
+        Problem {
+            severity: Severity::Error,
+            file: file.to_owned(),
+            path: path.into(),
+            message,
+            line: Option::None,
+        }
+    }
+
+    pub fn warning(file: &Path, path: impl Into<String>, message: String) -> Problem {
+        Problem {
+            severity: Severity::Warning,
+            ..Problem::error(file, path, message)
+        }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(f, "{}", severity)?;
+        // Problems with the command line arguments aren't in a file
+        if !self.file.as_os_str().is_empty() {
+            write!(f, ": {}", self.file.display())?;
+        }
+        if let Option::Some(line) = self.line {
+            write!(f, ":{}", line)?;
+        }
+        if !self.path.is_empty() {
+            write!(f, ": {}", self.path)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+/// Check the config files of a single account, or of every account in a manifest, and print
+/// every problem found. Source files are used as samples to find normalizers that never match.
+pub fn lint(args: Arguments) -> anyhow::Result<()> {
+    check(args, true)
+}
+
+/// Like `lint`, without the warnings
+pub fn validate(args: Arguments) -> anyhow::Result<()> {
+    check(args, false)
+}
+
+fn check(args: Arguments, warnings: bool) -> anyhow::Result<()> {
+    let accounts = if args.manifest.is_some() {
+        Manifest::from_args(args.to_owned())?.account_files()
+    } else {
+        let label = args
+            .account_config_file
+            .as_ref()
+            .and_then(|f| f.to_str())
+            .unwrap_or("Invalid file name")
+            .to_owned();
+        vec![(label, Config::account_files(&args))]
+    };
+    let mut problems = Vec::new();
+    // Categories files can be shared, so a category is only unused if no account uses it
+    let mut categories: HashMap<PathBuf, (Vec<String>, HashSet<String>)> = HashMap::new();
+    for (label, files) in accounts {
+        let files = match files {
+            Ok(f) => f,
+            Err(e) => {
+                problems.push(Problem::error(
+                    Path::new(&label),
+                    "",
+                    format!("Unable to find the account's files: {}", e),
+                ));
+                continue;
+            }
+        };
+        let parse_problems = parse_problems(&args, &files);
+        if !parse_problems.is_empty() {
+            problems.extend(parse_problems);
+            continue;
+        }
+        let config = match Config::load(args.to_owned(), files) {
+            Ok(c) => c,
+            Err(e) => {
+                problems.push(Problem::error(Path::new(&label), "", e.to_string()));
+                continue;
+            }
+        };
+        problems.extend(config_problems(&config));
+        if !warnings {
+            continue;
+        }
+        let normalizers = normalizers(&config);
+        problems.extend(conflicting_normalizers(&config, &normalizers));
+        problems.extend(overlapping_contains_strings(&config, &normalizers));
+        problems.extend(unmatched_normalizers(&config, &normalizers));
+        for (file, format) in config
+            .files
+            .src_format_config_files
+            .iter()
+            .chain(&config.files.dst_format_config_file)
+            .zip(config.src_formats().iter().chain(config.dst_format()))
+        {
+            problems.extend(duplicate_fields(file, format));
+        }
+        let (ids, used) = categories
+            .entry(config.files.categories_config_file.to_owned())
+            .or_default();
+        *ids = config.categories().map(|c| c.id.to_owned()).collect();
+        used.extend(used_categories(&config));
+    }
+    for (file, (ids, used)) in categories {
+        for id in ids.iter().filter(|id| !used.contains(*id)) {
+            problems.push(Problem::warning(
+                &file,
+                format!("categories[{}]", id),
+                format!(
+                    "Category [{}] is not used by any payee, category rule or decision.",
+                    id
+                ),
+            ));
+        }
+    }
+
+    problems.sort_by(|a, b| (&a.file, a.line, &a.path).cmp(&(&b.file, b.line, &b.path)));
+    problems.dedup_by(|a, b| a.file == b.file && a.path == b.path && a.message == b.message);
+    for p in &problems {
+        println!("{}", p);
+    }
+    let errors = problems
+        .iter()
+        .filter(|p| p.severity == Severity::Error)
+        .count();
+    let warnings = problems.len() - errors;
+    println!("{} error(s), {} warning(s)", errors, warnings);
+    if errors > 0 {
+        return Err(anyhow!("The config files have {} error(s).", errors));
+    }
+    Ok(())
+}
+
+/// Read each config file on its own, so that every file that can't be read is reported
+fn parse_problems(args: &Arguments, files: &AccountFiles) -> Vec<Problem> {
+    let mut results = vec![
+        (
+            files.account_config_file.to_owned(),
+            AccountConfigFile::from_file(&files.account_config_file, &files.payee_libraries)
+                .map(|_| ()),
+        ),
+        (
+            files.categories_config_file.to_owned(),
+            CategoriesConfigFile::from_file(&files.categories_config_file).map(|_| ()),
+        ),
+    ];
+    for f in files
+        .src_format_config_files
+        .iter()
+        .chain(&files.dst_format_config_file)
+    {
+        results.push((f.to_owned(), FormatConfigFile::from_file(f).map(|_| ())));
+    }
+    let overrides = overrides_file_name(args, files);
+    results.push((
+        overrides.to_owned(),
+        OverridesFile::from_file(&overrides).map(|_| ()),
+    ));
+    if let Option::Some(ref f) = args.decisions_file {
+        results.push((f.to_owned(), DecisionsFile::from_file(f).map(|_| ())));
+    }
+    results
+        .into_iter()
+        .filter_map(|(file, r)| r.err().map(|e| (file, e)))
+        .map(|(file, e)| {
+            let message = e.to_string();
+            let line = Regex::new(r"line (\d+)")
+                .unwrap()
+                .captures(&message)
+                .and_then(|c| c[1].parse().ok());
+            Problem {
+                line,
+                ..Problem::error(&file, "", message)
+            }
+        })
+        .collect()
+}
+
+/// A normalizer of the account, compiled once to compare it with the other normalizers
+struct Normalizer<'a> {
+    payee_id: &'a String,
+    i: usize,
+    config: &'a PayeeNormalizerConfig,
+    matcher: Matcher<'a>,
+}
+
+fn normalizers(config: &Config) -> Vec<Normalizer<'_>> {
+    config
+        .account()
+        .payees
+        .iter()
+        .flat_map(|(id, p)| {
+            p.normalizers
+                .iter()
+                .enumerate()
+                .map(move |(i, n)| (id, i, n))
+        })
+        // Invalid regexes are already reported as errors
+        .filter_map(|(payee_id, i, n)| {
+            Matcher::new(n).ok().map(|matcher| Normalizer {
+                payee_id,
+                i,
+                config: n,
+                matcher,
+            })
+        })
+        .collect()
+}
+
+/// The string a normalizer matches, for normalizers that match a single string
+fn literal(normalizer: &PayeeNormalizerConfig) -> Option<&String> {
+    match normalizer.normalizer_type {
+        MatcherType::Exact {
+            ref exact_match_string,
+        } => Option::Some(exact_match_string),
+        MatcherType::Contains {
+            ref contains_string,
+        } => Option::Some(contains_string),
+        MatcherType::Fuzzy {
+            ref fuzzy_match_string,
+            ..
+        } => Option::Some(fuzzy_match_string),
+        MatcherType::Regex { .. } => Option::None,
+    }
+}
+
+/// A warning about normalizer `i` of the payee, in the file that defines the normalizer
+fn normalizer_warning(config: &Config, payee_id: &str, i: usize, message: String) -> Problem {
+    let (file, i) = config.normalizer_source(payee_id, i);
+    Problem::warning(
+        file,
+        format!("payees[{}].normalizers[{}].matchString", payee_id, i),
+        message,
+    )
+}
+
+/// Exact and fuzzy normalizers whose string is also matched by an exact or regex normalizer of
+/// another payee. Payees are checked in no particular order, and fuzzy normalizers only after
+/// every other normalizer, so either payee may be used.
+fn conflicting_normalizers(config: &Config, normalizers: &[Normalizer]) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    for n in normalizers {
+        let s = match n.config.normalizer_type {
+            MatcherType::Exact { .. } | MatcherType::Fuzzy { .. } => literal(n.config),
+            _ => Option::None,
+        };
+        let Option::Some(s) = s else { continue };
+        let conflict = normalizers.iter().find(|other| {
+            other.payee_id != n.payee_id
+                && matches!(
+                    other.config.normalizer_type,
+                    MatcherType::Exact { .. } | MatcherType::Regex { .. }
+                )
+                && other.matcher.score(s).is_some()
+        });
+        if let Option::Some(other) = conflict {
+            problems.push(normalizer_warning(
+                config,
+                n.payee_id,
+                n.i,
+                format!(
+                    "Normalizer [{}] of payee [{}] is also matched by a normalizer of payee [{}], so either payee may be used.",
+                    s, n.payee_id, other.payee_id
+                ),
+            ));
+        }
+    }
+    problems
+}
+
+/// Contains normalizers whose string is part of a match string of another payee, so that they
+/// also match the other payee's transactions
+fn overlapping_contains_strings(config: &Config, normalizers: &[Normalizer]) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    for n in normalizers {
+        let MatcherType::Contains {
+            ref contains_string,
+        } = n.config.normalizer_type
+        else {
+            continue;
+        };
+        let overlap = normalizers.iter().find_map(|other| {
+            literal(other.config)
+                .filter(|s| other.payee_id != n.payee_id && n.matcher.score(s).is_some())
+                .map(|s| (other.payee_id, s))
+        });
+        if let Option::Some((other_id, s)) = overlap {
+            problems.push(normalizer_warning(
+                config,
+                n.payee_id,
+                n.i,
+                format!(
+                    "Contains string [{}] of payee [{}] is part of [{}], a match string of payee [{}].",
+                    contains_string, n.payee_id, s, other_id
+                ),
+            ));
+        }
+    }
+    problems
+}
+
+/// Normalizers that don't match any payee in the source files. Nothing is reported without
+/// source files.
+fn unmatched_normalizers(config: &Config, normalizers: &[Normalizer]) -> Vec<Problem> {
+    if config.src_files().is_empty() {
+        return Vec::new();
+    }
+    let raw_payees = match TransactionIO::raw_payees(config) {
+        Ok(p) => p,
+        Err(e) => {
+            return vec![Problem::error(Path::new(""), "", e.to_string())];
+        }
+    };
+    normalizers
+        .iter()
+        .filter(|n| !raw_payees.iter().any(|s| n.matcher.score(s).is_some()))
+        .map(|n| {
+            normalizer_warning(
+                config,
+                n.payee_id,
+                n.i,
+                format!(
+                    "Normalizer [{}] of payee [{}] did not match any of the {} payees in the source files.",
+                    literal(n.config).map_or("regex", String::as_str),
+                    n.payee_id,
+                    raw_payees.len()
+                ),
+            )
+        })
+        .collect()
+}
+
+fn duplicate_fields(file: &Path, format: &FormatConfigFile) -> Vec<Problem> {
+    let mut seen = HashSet::new();
+    format
+        .field_order
+        .iter()
+        .enumerate()
+        .filter(|(_, f)| !seen.insert(*f))
+        .map(|(i, f)| {
+            Problem::warning(
+                file,
+                format!("fieldOrder[{}]", i),
+                format!(
+                    "Field [{}] appears more than once in the field order of format [{}].",
+                    f, format.id
+                ),
+            )
+        })
+        .collect()
+}
+
+fn used_categories(config: &Config) -> HashSet<String> {
+    let from_payees = config
+        .account()
+        .payees
+        .values()
+        .flat_map(|p| p.category_ids.iter().flatten());
+    let from_rules = config.category_rules().iter().map(|r| &r.category_id);
+    let from_decisions = config
+        .decisions_file
+        .decisions
+        .values()
+        .filter_map(|d| d.category_id.as_ref());
+    from_payees
+        .chain(from_rules)
+        .chain(from_decisions)
+        .cloned()
+        .collect()
+}
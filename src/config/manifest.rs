@@ -0,0 +1,359 @@
+use crate::config::file_format::read_config_file;
+use crate::config::presets;
+use crate::config::{AccountFiles, Arguments, Config, expand_src_paths, src_format_config_files};
+use crate::runner::Failure;
+use anyhow::anyhow;
+use std::path::{Path, PathBuf};
+
+/// Lists the accounts to process in a single run, and the files they share. Relative paths are
+/// relative to the directory of the manifest.
+#[derive(Debug, Deserialize)]
+pub struct ManifestFile {
+    #[serde(rename = "categoriesConfigFile")]
+    categories_config_file: PathBuf,
+    /// Only needed to export
+    #[serde(rename = "dstFormatConfigFile")]
+    dst_format_config_file: Option<PathBuf>,
+    /// Payee libraries shared by every account
+    #[serde(rename = "include", default)]
+    include: Vec<PathBuf>,
+    /// Merge all accounts into this file. Otherwise, each account is written to its own file.
+    #[serde(rename = "dstFile")]
+    dst_file: Option<PathBuf>,
+    #[serde(rename = "accounts")]
+    accounts: Vec<ManifestAccount>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ManifestAccount {
+    /// Selects the account with `--account`. Defaults to the name of the account config file
+    /// without its extension.
+    #[serde(rename = "id")]
+    id: Option<String>,
+    #[serde(rename = "accountConfigFile")]
+    account_config_file: PathBuf,
+    #[serde(rename = "srcFormatConfigFile")]
+    src_format_config_file: Option<PathBuf>,
+    /// A directory of formats to detect the format of each file from
+    #[serde(rename = "srcFormatDir")]
+    src_format_dir: Option<PathBuf>,
+    /// Paths, glob patterns or directories of the files to import. Required when the manifest has
+    /// several accounts, unless the account is chosen with `--account` and read from stdin.
+    #[serde(rename = "srcFiles", default)]
+    src_files: Vec<String>,
+    #[serde(rename = "dstFile")]
+    dst_file: Option<PathBuf>,
+}
+
+impl ManifestAccount {
+    fn id(&self) -> String {
+        self.id.to_owned().unwrap_or_else(|| {
+            self.account_config_file
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .unwrap_or_default()
+                .to_owned()
+        })
+    }
+}
+
+/// Where the accounts in a manifest are exported
+#[derive(Debug, PartialEq)]
+pub enum ManifestOutput {
+    /// A single file for all accounts, or stdout
+    Merged(Option<PathBuf>),
+    /// A file for each account
+    PerAccount,
+}
+
+#[derive(Debug)]
+pub struct Manifest {
+    args: Arguments,
+    file: ManifestFile,
+    dir: PathBuf,
+}
+
+impl Manifest {
+    pub fn from_args(args: Arguments) -> anyhow::Result<Manifest> {
+        let filename = args
+            .manifest
+            .to_owned()
+            .ok_or_else(|| anyhow!("The manifest file is required."))?;
+        let file: ManifestFile = read_config_file(&filename).map_err(|e| {
+            anyhow!(
+                "Unable to parse manifest file [{}]: {}",
+                filename.to_str().unwrap_or("Invalid file name"),
+                e
+            )
+        })?;
+        let dir = filename.parent().map(Path::to_path_buf).unwrap_or_default();
+        Manifest::new(args, file, dir)
+    }
+
+    fn new(args: Arguments, file: ManifestFile, dir: PathBuf) -> anyhow::Result<Manifest> {
+        let manifest = Manifest { args, file, dir };
+        manifest.output()?;
+        if let Option::Some(ref id) = manifest.args.account {
+            if !manifest.file.accounts.iter().any(|a| a.id() == *id) {
+                return Err(anyhow!(
+                    "Account [{}] is not in the manifest. The accounts are [{}].",
+                    id,
+                    manifest
+                        .file
+                        .accounts
+                        .iter()
+                        .map(ManifestAccount::id)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ));
+            }
+        } else if !manifest.args.src_file.is_empty() {
+            return Err(anyhow!(
+                "Choose the account to import the source files into with --account."
+            ));
+        }
+        Ok(manifest)
+    }
+
+    pub fn output(&self) -> anyhow::Result<ManifestOutput> {
+        if let Option::Some(ref f) = self.args.dst_file {
+            return Ok(ManifestOutput::Merged(Option::Some(f.to_owned())));
+        }
+        if let Option::Some(ref f) = self.file.dst_file {
+            return Ok(ManifestOutput::Merged(Option::Some(self.path(f))));
+        }
+        let per_account = self
+            .file
+            .accounts
+            .iter()
+            .filter(|a| a.dst_file.is_some())
+            .count();
+        if per_account == 0 {
+            Ok(ManifestOutput::Merged(Option::None))
+        } else if per_account == self.file.accounts.len() {
+            Ok(ManifestOutput::PerAccount)
+        } else {
+            Err(anyhow!(
+                "Either every account in the manifest needs a destination file, or the manifest needs a single destination file."
+            ))
+        }
+    }
+
+    /// The config of each account in the manifest, or of the account chosen with `--account`,
+    /// labeled with the account config file. An account with invalid config doesn't prevent the
+    /// others from being processed.
+    pub fn account_configs(&self) -> Vec<(String, anyhow::Result<Config>)> {
+        self.account_files()
+            .into_iter()
+            .map(|(label, files)| {
+                let config = files.and_then(|f| Config::new_for_files(self.args.to_owned(), f));
+                (label, config)
+            })
+            .collect()
+    }
+
+    /// The files of each account in the manifest, or of the account chosen with `--account`,
+    /// labeled with the account config file
+    pub fn account_files(&self) -> Vec<(String, anyhow::Result<AccountFiles>)> {
+        self.file
+            .accounts
+            .iter()
+            .filter(|a| self.args.account.as_ref().is_none_or(|id| *id == a.id()))
+            .map(|a| {
+                let label = a
+                    .account_config_file
+                    .to_str()
+                    .unwrap_or("Invalid file name")
+                    .to_owned();
+                (label, self.files(a))
+            })
+            .collect()
+    }
+
+    fn files(&self, a: &ManifestAccount) -> anyhow::Result<AccountFiles> {
+        // Source files on the command line replace the ones in the manifest
+        let src_paths: Vec<String> = if self.args.src_file.is_empty() {
+            a.src_files
+                .iter()
+                .map(|f| self.path(Path::new(f)).to_string_lossy().into_owned())
+                .collect()
+        } else {
+            self.args.src_file.to_owned()
+        };
+        // Without source files the account is read from stdin, which only a single account can be
+        if src_paths.is_empty() && self.args.account.is_none() && self.file.accounts.len() > 1 {
+            return Err(Failure::Config.wrap(anyhow!(
+                "Account [{}] has no source files. Add srcFiles to the manifest, or choose the account with --account to read it from stdin.",
+                a.id()
+            )));
+        }
+        let files = AccountFiles {
+            account_config_file: self.path(&a.account_config_file),
+            categories_config_file: self.path(&self.file.categories_config_file),
+            src_format_config_files: src_format_config_files(
+                &a.src_format_config_file
+                    .iter()
+                    .map(|f| self.path(f))
+                    .collect::<Vec<_>>(),
+                a.src_format_dir.as_ref().map(|d| self.path(d)).as_deref(),
+            )?,
+            dst_format_config_file: self
+                .file
+                .dst_format_config_file
+                .as_ref()
+                .map(|f| self.path(f)),
+            payee_libraries: self.file.include.iter().map(|f| self.path(f)).collect(),
+            src_files: expand_src_paths(&src_paths).map_err(|e| Failure::Input.wrap(e))?,
+            // The destination of the merged export, if there's one
+            dst_file: a
+                .dst_file
+                .as_ref()
+                .map(|f| self.path(f))
+                .or_else(|| self.args.dst_file.to_owned())
+                .or_else(|| self.file.dst_file.as_ref().map(|f| self.path(f))),
+        };
+        Ok(files)
+    }
+
+    fn path(&self, p: &Path) -> PathBuf {
+        if presets::preset_id(p).is_some() {
+            return p.to_owned();
+        }
+        self.dir.join(p)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config::Arguments;
+    use crate::config::manifest::{Manifest, ManifestOutput};
+    use clap::Parser;
+    use serde_json::json;
+    use std::path::{Path, PathBuf};
+
+    fn manifest(args: &[&str], file: serde_json::Value) -> anyhow::Result<Manifest> {
+        let args = Arguments::parse_from(
+            [
+                "transaction_processor",
+                "--manifest",
+                "budget/manifest.json",
+            ]
+            .iter()
+            .chain(args),
+        );
+        let file = serde_json::from_value(file).unwrap();
+        Manifest::new(args, file, PathBuf::from("budget"))
+    }
+
+    fn accounts(dst_files: [Option<&str>; 2]) -> serde_json::Value {
+        json!({
+            "categoriesConfigFile": "categories.json",
+            "dstFormatConfigFile": "preset:ynab",
+            "accounts": [
+                {"accountConfigFile": "accounts/checking.json", "dstFile": dst_files[0]},
+                {"id": "card", "accountConfigFile": "accounts/sapphire.yaml", "dstFile": dst_files[1]},
+            ]
+        })
+    }
+
+    #[test]
+    fn test_path() {
+        let m = manifest(&[], accounts([Option::None, Option::None])).unwrap();
+        assert_eq!(
+            m.path(Path::new("accounts/checking.json")),
+            PathBuf::from("budget/accounts/checking.json")
+        );
+        assert_eq!(
+            m.path(Path::new("/tmp/out.csv")),
+            PathBuf::from("/tmp/out.csv")
+        );
+        assert_eq!(
+            m.path(Path::new("preset:ynab")),
+            PathBuf::from("preset:ynab")
+        );
+    }
+
+    #[test]
+    fn test_output() {
+        let output = |args: &[&str], file| manifest(args, file).and_then(|m| m.output());
+        assert_eq!(
+            output(&[], accounts([Option::None, Option::None])).unwrap(),
+            ManifestOutput::Merged(Option::None)
+        );
+        assert_eq!(
+            output(
+                &[],
+                accounts([Option::Some("a.csv"), Option::Some("b.csv")])
+            )
+            .unwrap(),
+            ManifestOutput::PerAccount
+        );
+        let mut file = accounts([Option::None, Option::None]);
+        file["dstFile"] = json!("all.csv");
+        assert_eq!(
+            output(&[], file).unwrap(),
+            ManifestOutput::Merged(Option::Some(PathBuf::from("budget/all.csv")))
+        );
+        // The output file on the command line wins, relative to the working directory
+        assert_eq!(
+            output(
+                &["-o", "out.csv"],
+                accounts([Option::Some("a.csv"), Option::Some("b.csv")])
+            )
+            .unwrap(),
+            ManifestOutput::Merged(Option::Some(PathBuf::from("out.csv")))
+        );
+        assert!(output(&[], accounts([Option::Some("a.csv"), Option::None])).is_err());
+    }
+
+    #[test]
+    fn test_account_selection() {
+        let labels = |args: &[&str]| -> anyhow::Result<Vec<String>> {
+            let m = manifest(args, accounts([Option::None, Option::None]))?;
+            Ok(m.account_files()
+                .into_iter()
+                .map(|(label, _)| label)
+                .collect())
+        };
+        assert_eq!(
+            labels(&[]).unwrap(),
+            vec!["accounts/checking.json", "accounts/sapphire.yaml"]
+        );
+        // The ID defaults to the account config file name without its extension
+        assert_eq!(
+            labels(&["--account", "checking"]).unwrap(),
+            vec!["accounts/checking.json"]
+        );
+        assert_eq!(
+            labels(&["--account", "card", "--src-file", "statement.csv"]).unwrap(),
+            vec!["accounts/sapphire.yaml"]
+        );
+        assert_eq!(
+            labels(&["--account", "sapphire"]).unwrap_err().to_string(),
+            "Account [sapphire] is not in the manifest. The accounts are [checking, card]."
+        );
+        // Source files on the command line need an account to be imported into
+        assert!(labels(&["--src-file", "statement.csv"]).is_err());
+    }
+
+    #[test]
+    fn test_stdin_account() {
+        let errors = |args: &[&str]| -> Vec<String> {
+            manifest(args, accounts([Option::None, Option::None]))
+                .unwrap()
+                .account_files()
+                .into_iter()
+                .filter_map(|(_, files)| files.err().map(|e| e.to_string()))
+                .collect()
+        };
+        // Only one account can be read from stdin
+        assert_eq!(
+            errors(&[]),
+            vec![
+                "Account [checking] has no source files. Add srcFiles to the manifest, or choose the account with --account to read it from stdin.",
+                "Account [card] has no source files. Add srcFiles to the manifest, or choose the account with --account to read it from stdin.",
+            ]
+        );
+        assert!(errors(&["--account", "card"]).is_empty());
+    }
+}
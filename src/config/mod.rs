@@ -1,81 +1,205 @@
-use crate::config::arguments::Arguments;
-use crate::parser::{Keyed, default_false, default_true, deserialize_keyed_items};
+use crate::diagnostics::Diagnostic;
+use crate::parser::{
+    Keyed, default_false, default_fuzzy_threshold, default_true, deserialize_keyed_items,
+};
+use crate::runner::Failure;
+use crate::transaction::fuzzy::normalize_tokens;
+use crate::transaction::transaction_io::StateFile;
 use crate::util;
 use anyhow::anyhow;
 use clap::Parser;
+use currency::Currency;
+use file_format::{FileFormat, read_config_file, write_config_file};
+use lint::{Problem, Severity};
 use regex::RegexBuilder;
+use schemars::JsonSchema;
 use serde_json;
 use std::collections::HashMap;
 use std::fmt;
 use std::fmt::Display;
+use std::fs;
 use std::path::{Path, PathBuf};
 
 mod arguments;
+pub mod file_format;
+pub mod infer;
+pub mod lint;
+pub mod manifest;
+pub mod payee_library;
+pub mod presets;
+pub mod schema;
+
+pub use arguments::{Arguments, Command, MatcherKind, PayeesCommand};
+
+/// The files used to process a single account
+#[derive(Debug, Clone)]
+pub struct AccountFiles {
+    pub account_config_file: PathBuf,
+    pub categories_config_file: PathBuf,
+    pub src_format_config_files: Vec<PathBuf>,
+    /// Only needed to export
+    pub dst_format_config_file: Option<PathBuf>,
+    /// Payee libraries included in the account config, in addition to the ones it includes
+    pub payee_libraries: Vec<PathBuf>,
+    /// Read from stdin if empty
+    pub src_files: Vec<PathBuf>,
+    /// Written to stdout if `None`
+    pub dst_file: Option<PathBuf>,
+}
 
 pub struct Config {
     args: Arguments,
+    files: AccountFiles,
     account_config_file: AccountConfigFile,
     categories_config_file: CategoriesConfigFile,
-    src_format_config_file: FormatConfigFile,
-    dst_format_config_file: FormatConfigFile,
+    src_format_config_files: Vec<FormatConfigFile>,
+    dst_format_config_file: Option<FormatConfigFile>,
+    overrides_file: OverridesFile,
+    decisions_file: DecisionsFile,
+    pending_decisions: Vec<Decision>,
+    statement_balances: StatementBalances,
+    state: Option<StateFile>,
 }
 
 impl Config {
-    pub fn new_and_parse_args() -> anyhow::Result<Config> {
-        let args: Arguments = Arguments::parse();
-        let account_config_file = AccountConfigFile::from_file(&args.account_config_file)?;
-        let categories_config_file = CategoriesConfigFile::from_file(&args.categories_config_file)?;
-        let src_format_config_file = FormatConfigFile::from_file(&args.src_format_config_file)?;
-        let dst_format_config_file = FormatConfigFile::from_file(&args.dst_format_config_file)?;
-
-        let config = Config {
-            args,
-            account_config_file,
-            categories_config_file,
-            src_format_config_file,
-            dst_format_config_file,
+    pub fn parse_args() -> Arguments {
+        let args = Arguments::parse();
+        if let Err(e) = args.check_required() {
+            e.exit();
+        }
+        args
+    }
+
+    /// The config for a single account, using the files given on the command line
+    pub fn new(args: Arguments) -> anyhow::Result<Config> {
+        let files = Config::account_files(&args)?;
+        Config::new_for_files(args, files)
+    }
+
+    /// The files given on the command line
+    fn account_files(args: &Arguments) -> anyhow::Result<AccountFiles> {
+        let required = |f: &Option<PathBuf>, name: &str| {
+            f.to_owned()
+                .ok_or_else(|| anyhow!("The {} is required.", name))
+        };
+        let files = AccountFiles {
+            account_config_file: required(&args.account_config_file, "account config file")?,
+            categories_config_file: required(
+                &args.categories_config_file,
+                "categories config file",
+            )?,
+            src_format_config_files: src_format_config_files(
+                &args
+                    .src_format_config_file
+                    .iter()
+                    .cloned()
+                    .chain(args.src_format.iter().map(|id| presets::preset_path(id)))
+                    .collect::<Vec<_>>(),
+                args.src_format_dir.as_deref(),
+            )?,
+            dst_format_config_file: args
+                .dst_format_config_file
+                .to_owned()
+                .or_else(|| args.dst_format.as_deref().map(presets::preset_path)),
+            payee_libraries: Vec::new(),
+            src_files: expand_src_paths(&args.src_file).map_err(|e| Failure::Input.wrap(e))?,
+            dst_file: args.dst_file.to_owned(),
         };
+        Ok(files)
+    }
 
+    pub fn new_for_files(args: Arguments, files: AccountFiles) -> anyhow::Result<Config> {
+        let config = Config::load(args, files)?;
         validate_configs(&config)?;
-
         Ok(config)
     }
 
+    /// Read the config files without validating them
+    fn load(args: Arguments, files: AccountFiles) -> anyhow::Result<Config> {
+        let account_config_file =
+            AccountConfigFile::from_file(&files.account_config_file, &files.payee_libraries)?;
+        let categories_config_file =
+            CategoriesConfigFile::from_file(&files.categories_config_file)?;
+        let src_format_config_files = files
+            .src_format_config_files
+            .iter()
+            .map(|f| FormatConfigFile::from_file(f))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let dst_format_config_file = files
+            .dst_format_config_file
+            .as_ref()
+            .map(|f| FormatConfigFile::from_file(f))
+            .transpose()?;
+        let overrides_file = OverridesFile::from_file(&overrides_file_name(&args, &files))?;
+        let decisions_file = match args.decisions_file {
+            Option::Some(ref f) => DecisionsFile::from_file(f)?,
+            Option::None => DecisionsFile::default(),
+        };
+
+        Ok(Config {
+            args,
+            files,
+            account_config_file,
+            categories_config_file,
+            src_format_config_files,
+            dst_format_config_file,
+            overrides_file,
+            decisions_file,
+            pending_decisions: Vec::new(),
+            statement_balances: StatementBalances::default(),
+            state: Option::None,
+        })
+    }
+
     pub fn account(&self) -> &AccountConfigFile {
         // We validated the input, so this should never return Option::None
         &self.account_config_file
     }
 
-    pub fn src_format(&self) -> &FormatConfigFile {
-        // We validated the input, so this should never return Option::None
-        &self.src_format_config_file
+    /// The formats the source files may be in. There's always at least one.
+    pub fn src_formats(&self) -> &[FormatConfigFile] {
+        &self.src_format_config_files
     }
 
-    pub fn dst_format(&self) -> &FormatConfigFile {
-        // We validated the input, so this should never return Option::None
-        &self.dst_format_config_file
+    /// The format to export with. Only subcommands that export need one.
+    pub fn dst_format(&self) -> Option<&FormatConfigFile> {
+        self.dst_format_config_file.as_ref()
     }
 
-    pub fn src_file(&self) -> Option<&PathBuf> {
-        self.args.src_file.as_ref()
+    /// The format to export with, or an error if none was given
+    pub fn require_dst_format(&self) -> anyhow::Result<&FormatConfigFile> {
+        self.dst_format()
+            .ok_or_else(|| anyhow!("A destination format is required to export."))
+    }
+
+    pub fn src_files(&self) -> &[PathBuf] {
+        &self.files.src_files
     }
 
     pub fn dst_file(&self) -> Option<&PathBuf> {
-        self.args.dst_file.as_ref()
+        self.files.dst_file.as_ref()
+    }
+
+    /// Prompts would be mixed with the exported transactions, so nothing is prompted for
+    fn exports_to_stdout(&self) -> bool {
+        self.files.dst_file.is_none()
     }
 
     pub fn category(&self, category_id: &str) -> Option<&Category> {
         self.categories_config_file.categories.get(category_id)
     }
 
+    pub fn categories(&self) -> impl Iterator<Item = &Category> {
+        self.categories_config_file.categories.values()
+    }
+
     pub fn sort_order(&self) -> Option<SortOrder> {
         if self.args.sort_order.is_some() {
             return self.args.sort_order.clone();
         }
 
         self.dst_format()
-            .sort
-            .as_ref()
+            .and_then(|f| f.sort.as_ref())
             .map(|sort| sort.order.clone())
             .or(Some(SortOrder::Ascending))
     }
@@ -86,8 +210,7 @@ impl Config {
         }
 
         self.dst_format()
-            .sort
-            .as_ref()
+            .and_then(|f| f.sort.as_ref())
             .map(|sort| sort.sort_by.clone())
             .or(Some(SortBy::Date))
     }
@@ -96,7 +219,7 @@ impl Config {
     pub fn include_header(&self) -> bool {
         self.args
             .include_header
-            .or(self.dst_format().include_header)
+            .or(self.dst_format().and_then(|f| f.include_header))
             .unwrap_or(false)
     }
 
@@ -108,37 +231,399 @@ impl Config {
     }
 
     pub fn skip_prompts(&self) -> bool {
+        self.prompts_disabled() || self.exports_to_stdout()
+    }
+
+    /// Whether prompts are turned off, wherever the transactions are exported to
+    pub fn prompts_disabled(&self) -> bool {
+        // Anything that would have been prompted for can be handled in the review screen
+        self.review()
+            || self
+                .args
+                .skip_prompts
+                .or(self.account().skip_prompts)
+                .unwrap_or(false)
+    }
+
+    /// Whether to skip transactions exported by previous runs
+    pub fn since_last_run(&self) -> bool {
+        self.args.since_last_run || self.account().since_last_run.unwrap_or(false)
+    }
+
+    pub fn state_file(&self) -> PathBuf {
         self.args
-            .skip_prompts
-            .or(self.account().skip_prompts)
-            .unwrap_or(false)
+            .state_file
+            .to_owned()
+            .unwrap_or_else(|| self.files.account_config_file.with_extension("state.json"))
+    }
+
+    /// How to detect duplicate transactions within an import, if at all
+    pub fn dedupe(&self) -> Option<DedupeConfig> {
+        let config = self.account().dedupe.to_owned();
+        match self.args.dedupe_policy {
+            Option::Some(ref policy) => Option::Some(DedupeConfig {
+                policy: policy.to_owned(),
+                ..config.unwrap_or_default()
+            }),
+            Option::None => config,
+        }
+    }
+
+    /// How to find transfers between this account and the other accounts in the same run, if
+    /// transfer matching is turned on for the account
+    pub fn transfer_match(&self) -> Option<&TransferMatchConfig> {
+        self.account().transfer_match.as_ref()
+    }
+
+    /// The balance of the account before the first imported transaction, if known
+    pub fn opening_balance(&self) -> Option<Currency> {
+        self.args
+            .opening_balance
+            .as_ref()
+            .and_then(|b| Currency::from_str(b).ok())
+            .or_else(|| self.statement_balances.opening.to_owned())
+    }
+
+    /// The balance of the account after the last imported transaction, if known
+    pub fn closing_balance(&self) -> Option<Currency> {
+        self.args
+            .closing_balance
+            .as_ref()
+            .and_then(|b| Currency::from_str(b).ok())
+            .or_else(|| self.statement_balances.closing.to_owned())
+    }
+
+    /// Remember the balances read from the imported statement
+    pub fn set_statement_balances(&mut self, balances: StatementBalances) {
+        self.statement_balances = balances;
+    }
+
+    /// The state of previous runs as of this import, to be saved once it's exported
+    pub fn state(&self) -> Option<&StateFile> {
+        self.state.as_ref()
+    }
+
+    pub fn set_state(&mut self, state: StateFile) {
+        self.state = Option::Some(state);
+    }
+
+    pub fn reconcile(&self) -> bool {
+        self.args.reconcile
+    }
+
+    /// How to match cleared transactions to the pending transactions they replace. Matching
+    /// within an import only happens if the account configures it.
+    pub fn pending_match(&self) -> Option<&PendingMatchConfig> {
+        self.account().pending_match.as_ref()
+    }
+
+    pub fn review(&self) -> bool {
+        self.args.review
+    }
+
+    /// Whether to interactively choose or create payees for transactions that aren't normalized
+    pub fn prompt_for_payees(&self) -> bool {
+        let prompt = !self.prompts_disabled()
+            && self
+                .args
+                .prompt_for_payees
+                .or(self.account().prompt_for_payees)
+                .unwrap_or(false);
+        if prompt && self.exports_to_stdout() {
+            Diagnostic::warning(
+                "Payees are not prompted for when transactions are exported to stdout. Choose a destination file with --dst-file to be prompted.",
+            )
+            .account(&self.account().name)
+            .emit();
+            return false;
+        }
+        prompt
+    }
+
+    /// Add a new payee and save it to the account config file.
+    pub fn add_payee(&mut self, payee: Payee) -> anyhow::Result<()> {
+        if self.account_config_file.payees.contains_key(&payee.id) {
+            return Err(anyhow!("Payee [{}] already exists.", payee.id));
+        }
+        let value = serde_json::to_value(&payee)?;
+        let change = format!("payee {}", value);
+        update_account_config_file(&self.files.account_config_file, &change, |account| {
+            payees_value(account)?.push(value);
+            Ok(())
+        })?;
+        self.account_config_file
+            .payees
+            .insert(payee.id.to_owned(), payee);
+        Ok(())
+    }
+
+    /// Add a normalizer to an existing payee and save it to the account config file.
+    pub fn add_payee_normalizer(
+        &mut self,
+        payee_id: &str,
+        normalizer: PayeeNormalizerConfig,
+    ) -> anyhow::Result<()> {
+        if !self.account_config_file.payees.contains_key(payee_id) {
+            return Err(anyhow!("Payee [{}] does not exist.", payee_id));
+        }
+        let value = serde_json::to_value(&normalizer)?;
+        let change = format!("normalizer {} to payee [{}]", value, payee_id);
+        update_account_config_file(&self.files.account_config_file, &change, |account| {
+            let payees = payees_value(account)?;
+            let payee = match payees
+                .iter_mut()
+                .find(|p| p.get("id").and_then(|id| id.as_str()) == Option::Some(payee_id))
+            {
+                Option::Some(payee) => payee,
+                // The payee is from a payee library, so extend it in the account config
+                Option::None => {
+                    payees.push(serde_json::json!({
+                        "id": payee_id,
+                        "extend": true,
+                        "normalizers": [value],
+                    }));
+                    return Ok(());
+                }
+            };
+            // A payee that extends a library payee doesn't need its own normalizers
+            if payee.get("extend").is_some() && payee.get("normalizers").is_none() {
+                payee["normalizers"] = serde_json::json!([]);
+            }
+            match payee.get_mut("normalizers").and_then(|n| n.as_array_mut()) {
+                Option::Some(normalizers) => normalizers.push(value),
+                Option::None => {
+                    return Err(anyhow!(
+                        "Payee [{}] does not have a list of normalizers.",
+                        payee_id
+                    ));
+                }
+            }
+            Ok(())
+        })?;
+        if let Option::Some(payee) = self.account_config_file.payees.get_mut(payee_id) {
+            payee.normalizers.push(normalizer);
+        }
+        Ok(())
+    }
+
+    /// The file that defines normalizer `i` of the payee, and the normalizer's index in that
+    /// file. A library payee that the account extends is spread over two files.
+    pub fn normalizer_source(&self, payee_id: &str, i: usize) -> (&Path, usize) {
+        let files = self
+            .account_config_file
+            .normalizer_files
+            .get(payee_id)
+            .map_or(&[][..], Vec::as_slice);
+        match files.get(i) {
+            Option::Some(file) => (file, files[..i].iter().filter(|f| *f == file).count()),
+            Option::None => (&self.files.account_config_file, i),
+        }
+    }
+
+    /// The decision for the transaction with the given fingerprint, if one was provided
+    pub fn decision(&self, fingerprint: &str) -> Option<&Decision> {
+        self.decisions_file.decisions.get(fingerprint)
+    }
+
+    /// Whether unanswered category prompts should be written to a template instead of asked
+    pub fn write_decisions_template(&self) -> bool {
+        self.args.decisions_template.is_some()
+    }
+
+    /// Save a category prompt that wasn't answered, to be written to the decisions template
+    pub fn add_pending_decision(&mut self, decision: Decision) {
+        if !self
+            .pending_decisions
+            .iter()
+            .any(|d| d.fingerprint == decision.fingerprint)
+        {
+            self.pending_decisions.push(decision);
+        }
+    }
+
+    /// Write the category prompts that weren't answered to the decisions template file, if one
+    /// was requested.
+    pub fn save_decisions_template(&self) -> anyhow::Result<()> {
+        if let Option::Some(ref filename) = self.args.decisions_template {
+            let template = DecisionsTemplate {
+                decisions: &self.pending_decisions,
+            };
+            write_config_file(filename, &template)?;
+            Diagnostic::info(format!(
+                "Wrote {} decision(s) to [{}]",
+                self.pending_decisions.len(),
+                filename.to_str().unwrap_or("Invalid file name")
+            ))
+            .emit();
+        }
+        Ok(())
+    }
+
+    pub fn category_rules(&self) -> &[CategoryRule] {
+        &self.overrides_file.category_rules
+    }
+
+    /// Remember a category choice for future runs by saving it to the overrides file.
+    pub fn add_category_rule(&mut self, rule: CategoryRule) -> anyhow::Result<()> {
+        self.overrides_file.category_rules.push(rule);
+        self.overrides_file
+            .save(&overrides_file_name(&self.args, &self.files))
     }
 }
 
+/// Rewrite the account config file, keeping the order of the existing fields and a backup of the
+/// previous version. YAML and TOML files are left alone, since rewriting them would drop their
+/// comments; the change is only used for this run and printed so it can be added by hand.
+fn update_account_config_file<F>(filename: &Path, change: &str, f: F) -> anyhow::Result<()>
+where
+    F: FnOnce(&mut serde_json::Value) -> anyhow::Result<()>,
+{
+    if FileFormat::from_path(filename) != FileFormat::Json {
+        Diagnostic::warning(format!(
+            "[{}] is not a JSON file, so it was not changed. Add {} to it by hand, or convert it to JSON with the convert-config command to have changes saved.",
+            filename.to_str().unwrap_or("Invalid file name"),
+            change
+        ))
+        .emit();
+        return Ok(());
+    }
+    let mut account: serde_json::Value = read_config_file(filename)?;
+    f(&mut account)?;
+    write_config_file(filename, &account)
+}
+
+fn payees_value(account: &mut serde_json::Value) -> anyhow::Result<&mut Vec<serde_json::Value>> {
+    account
+        .get_mut("payees")
+        .and_then(|p| p.as_array_mut())
+        .ok_or_else(|| anyhow!("The account config file does not have a list of payees."))
+}
+
+/// The source format files given on the command line, followed by the format files in the
+/// format directory
+pub fn src_format_config_files(
+    files: &[PathBuf],
+    dir: Option<&Path>,
+) -> anyhow::Result<Vec<PathBuf>> {
+    let mut files = files.to_vec();
+    if let Option::Some(dir) = dir {
+        let mut entries = fs::read_dir(dir)
+            .and_then(|d| {
+                d.map(|e| e.map(|e| e.path()))
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .map_err(|e| {
+                anyhow!(
+                    "Unable to read format directory [{}]: {}",
+                    dir.to_str().unwrap_or("Invalid file name"),
+                    e
+                )
+            })?;
+        entries.retain(|e| e.is_file() && FileFormat::is_config_file(e));
+        entries.sort();
+        if entries.is_empty() {
+            return Err(anyhow!(
+                "Format directory [{}] does not contain any format files.",
+                dir.to_str().unwrap_or("Invalid file name")
+            ));
+        }
+        files.extend(entries);
+    }
+    Ok(files)
+}
+
+/// Expand the paths given for the source files. Directories are replaced by the files in them,
+/// and glob patterns by the files that match.
+pub fn expand_src_paths<S: AsRef<str>>(paths: &[S]) -> anyhow::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for path in paths {
+        let path = path.as_ref();
+        let p = Path::new(path);
+        if p.is_dir() {
+            let mut entries = fs::read_dir(p)
+                .and_then(|d| {
+                    d.map(|e| e.map(|e| e.path()))
+                        .collect::<Result<Vec<_>, _>>()
+                })
+                .map_err(|e| anyhow!("Unable to read directory [{}]: {}", path, e))?;
+            entries.retain(|e| e.is_file());
+            entries.sort();
+            files.extend(entries);
+        } else if p.exists() {
+            files.push(p.to_path_buf());
+        } else {
+            let mut matches = glob::glob(path)
+                .map_err(|e| anyhow!("Invalid file pattern [{}]: {}", path, e))?
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| anyhow!("Unable to read [{}]: {}", path, e))?;
+            if matches.is_empty() {
+                return Err(anyhow!("No files match [{}]", path));
+            }
+            matches.sort();
+            files.extend(matches);
+        }
+    }
+    Ok(files)
+}
+
+fn overrides_file_name(args: &Arguments, files: &AccountFiles) -> PathBuf {
+    args.overrides_file
+        .to_owned()
+        .unwrap_or_else(|| files.account_config_file.with_extension("overrides.json"))
+}
+
 fn validate_configs(config: &Config) -> anyhow::Result<()> {
-    validate_account_config(config)?;
-    validate_payee_normalizer_configs(config)?;
-    validate_payees(config)?;
-    validate_format(&config.src_format_config_file)?;
-    validate_format(&config.dst_format_config_file)?;
-    Ok(())
+    match config_problems(config)
+        .into_iter()
+        .find(|p| p.severity == Severity::Error)
+    {
+        Option::Some(p) => Err(anyhow!(p.message)),
+        Option::None => Ok(()),
+    }
+}
+
+/// Every problem with the config files, rather than only the first
+fn config_problems(config: &Config) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    validate_account_config(config, &mut problems);
+    validate_payee_normalizer_configs(config, &mut problems);
+    validate_payees(config, &mut problems);
+    validate_category_rules(config, &mut problems);
+    validate_decisions(config, &mut problems);
+    validate_pending_match(config, &mut problems);
+    validate_transfer_rules(config, &mut problems);
+    validate_balances(config, &mut problems);
+    for (file, format) in config
+        .files
+        .src_format_config_files
+        .iter()
+        .zip(&config.src_format_config_files)
+    {
+        validate_format_file(file, format, &mut problems);
+    }
+    if let (Option::Some(file), Option::Some(format)) = (
+        &config.files.dst_format_config_file,
+        &config.dst_format_config_file,
+    ) {
+        validate_format_file(file, format, &mut problems);
+    }
+    problems
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 struct CategoriesConfigFile {
     #[serde(rename = "categories", deserialize_with = "deserialize_keyed_items")]
+    #[schemars(with = "Vec<Category>")]
     pub categories: HashMap<String, Category>,
 }
 
 impl CategoriesConfigFile {
     fn from_file(filename: &Path) -> anyhow::Result<CategoriesConfigFile> {
-        let r = util::reader_from_file_name(filename)?;
-        let config_file: CategoriesConfigFile = serde_json::from_reader(r)?;
-        Ok(config_file)
+        read_config_file(filename)
     }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 pub struct AccountConfigFile {
     #[serde(rename = "id")]
     pub id: String,
@@ -150,15 +635,51 @@ pub struct AccountConfigFile {
     ignore_pending: Option<bool>,
     #[serde(rename = "skipPrompts")]
     skip_prompts: Option<bool>,
+    #[serde(rename = "promptForPayees")]
+    prompt_for_payees: Option<bool>,
+    #[serde(rename = "sinceLastRun")]
+    since_last_run: Option<bool>,
+    #[serde(rename = "dedupe")]
+    dedupe: Option<DedupeConfig>,
+    #[serde(rename = "pendingMatch")]
+    pending_match: Option<PendingMatchConfig>,
+    #[serde(rename = "transferMatch")]
+    transfer_match: Option<TransferMatchConfig>,
     #[serde(rename = "payees", deserialize_with = "deserialize_keyed_items")]
+    #[schemars(with = "Vec<Payee>")]
     pub payees: HashMap<String, Payee>,
+    // The file each normalizer of a payee comes from, if it isn't the account config file
+    #[serde(skip)]
+    normalizer_files: HashMap<String, Vec<PathBuf>>,
 }
 
 impl AccountConfigFile {
-    fn from_file(filename: &Path) -> anyhow::Result<AccountConfigFile> {
-        let r = util::reader_from_file_name(filename)?;
-        let config_file: AccountConfigFile = serde_json::from_reader(r)?;
-        Ok(config_file)
+    /// Read the account config, along with the payees of the given payee libraries and the ones
+    /// listed in its `include` field. Included paths are relative to the account config file.
+    fn from_file(filename: &Path, libraries: &[PathBuf]) -> anyhow::Result<AccountConfigFile> {
+        let mut account: serde_json::Value = read_config_file(filename)?;
+        let dir = filename.parent().unwrap_or(Path::new(""));
+        let included: Vec<PathBuf> = account
+            .get("include")
+            .and_then(|i| i.as_array())
+            .into_iter()
+            .flatten()
+            .filter_map(|p| p.as_str())
+            .map(|p| dir.join(p))
+            .collect();
+        let mut payees = Vec::new();
+        for library in libraries.iter().chain(included.iter()) {
+            payees.extend(
+                payee_library::read_payee_library(library)?
+                    .into_iter()
+                    .map(|p| (library.to_owned(), p)),
+            );
+        }
+        let normalizer_files = payee_library::merge_payees(&mut account, filename, payees)?;
+        Ok(AccountConfigFile {
+            normalizer_files,
+            ..serde_json::from_value(account)?
+        })
     }
 }
 
@@ -174,24 +695,290 @@ impl Keyed<String> for AccountConfigFile {
     }
 }
 
-fn validate_account_config(config: &Config) -> anyhow::Result<()> {
-    if config.account_config_file.format_id != config.src_format_config_file.id {
-        Err(anyhow!(
-            "Format ID [{}] for account [{}] is different from the ID of the provided source format file [{}].",
-            config.account_config_file.format_id,
-            config.account_config_file.name,
-            config
-                .args
-                .src_format_config_file
-                .to_str()
-                .unwrap_or("Invalid file name")
-        ))
-    } else {
-        Ok(())
+fn validate_account_config(config: &Config, problems: &mut Vec<Problem>) {
+    let file = &config.files.account_config_file;
+    if config.src_format_config_files.is_empty() {
+        problems.push(Problem::error(
+            Path::new(""),
+            "--src-format-config-file",
+            String::from("At least one source format file is required."),
+        ));
+    }
+    // With several formats, each file is imported with the format that matches it, so the
+    // account's format only has to be one of them
+    if let [format] = &config.src_format_config_files[..] {
+        if format.id != config.account_config_file.format_id {
+            problems.push(Problem::error(
+                file,
+                "formatId",
+                format!(
+                    "Format ID [{}] for account [{}] is different from the ID of the provided source format file [{}].",
+                    config.account_config_file.format_id,
+                    config.account_config_file.name,
+                    config.files.src_format_config_files[0]
+                        .to_str()
+                        .unwrap_or("Invalid file name")
+                ),
+            ));
+        }
+    } else if config.src_format_config_files.len() > 1
+        && !config
+            .src_format_config_files
+            .iter()
+            .any(|f| f.id == config.account_config_file.format_id)
+    {
+        problems.push(Problem::error(
+            file,
+            "formatId",
+            format!(
+                "Format ID [{}] for account [{}] is not the ID of any of the source format files [{}].",
+                config.account_config_file.format_id,
+                config.account_config_file.name,
+                config
+                    .src_format_config_files
+                    .iter()
+                    .map(|f| f.id.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        ));
+    }
+}
+
+/// Detects transactions that appear more than once in the same import, e.g. because a bank
+/// statement overlaps itself.
+#[derive(Debug, Deserialize, Clone, JsonSchema)]
+pub struct DedupeConfig {
+    /// Transactions are duplicates if all of these fields are the same
+    #[serde(rename = "keyFields", default = "default_dedupe_key_fields")]
+    pub key_fields: Vec<DedupeField>,
+    /// Dates within this many days of each other are considered the same
+    #[serde(rename = "dateToleranceDays", default)]
+    pub date_tolerance_days: u32,
+    #[serde(rename = "policy", default)]
+    pub policy: DedupePolicy,
+}
+
+impl Default for DedupeConfig {
+    fn default() -> Self {
+        DedupeConfig {
+            key_fields: default_dedupe_key_fields(),
+            date_tolerance_days: 0,
+            policy: DedupePolicy::default(),
+        }
+    }
+}
+
+fn default_dedupe_key_fields() -> Vec<DedupeField> {
+    vec![
+        DedupeField::Date,
+        DedupeField::Amount,
+        DedupeField::Payee,
+        DedupeField::Memo,
+    ]
+}
+
+#[derive(Debug, Deserialize, PartialEq, Clone, JsonSchema)]
+pub enum DedupeField {
+    #[serde(rename = "date")]
+    Date,
+    /// The amount and transaction type
+    #[serde(rename = "amount")]
+    Amount,
+    /// The raw payee
+    #[serde(rename = "payee")]
+    Payee,
+    #[serde(rename = "memo")]
+    Memo,
+    #[serde(rename = "status")]
+    Status,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Clone, Default, clap::ValueEnum, JsonSchema)]
+pub enum DedupePolicy {
+    /// Keep the first of each set of duplicates
+    #[default]
+    #[serde(rename = "keepFirst")]
+    KeepFirst,
+    /// Keep every duplicate, but print a warning
+    #[serde(rename = "warn")]
+    Warn,
+    /// Stop processing if there are any duplicates
+    #[serde(rename = "fail")]
+    Fail,
+}
+
+/// Pairs transactions in this account with opposite transactions in the other accounts of the
+/// same run, e.g. a credit card payment from checking. Transfers are only matched for accounts
+/// that set this.
+#[derive(Debug, Deserialize, Clone, JsonSchema)]
+pub struct TransferMatchConfig {
+    /// The most days between the two sides of a transfer
+    #[serde(
+        rename = "dateWindowDays",
+        default = "default_transfer_date_window_days"
+    )]
+    pub date_window_days: u32,
+    #[serde(rename = "rules", default)]
+    pub rules: Vec<TransferRule>,
+    /// Also pair transactions that no rule points at, with accounts that set this too. Any two
+    /// opposite transactions with equal amounts within the date window are paired.
+    #[serde(rename = "matchUnguided", default)]
+    pub match_unguided: bool,
+}
+
+fn default_transfer_date_window_days() -> u32 {
+    3
+}
+
+/// Transactions with the payee are transfers to or from the account, and are only paired with
+/// transactions in that account.
+#[derive(Debug, Deserialize, Clone, JsonSchema)]
+pub struct TransferRule {
+    #[serde(rename = "payeeId")]
+    pub payee_id: String,
+    #[serde(rename = "accountId")]
+    pub account_id: String,
+}
+
+fn validate_transfer_rules(config: &Config, problems: &mut Vec<Problem>) {
+    let file = &config.files.account_config_file;
+    let rules = config.transfer_match().map_or(&[][..], |m| &m.rules);
+    for (i, rule) in rules.iter().enumerate() {
+        if !config.account().payees.contains_key(&rule.payee_id) {
+            problems.push(Problem::error(
+                file,
+                format!("transferMatch.rules[{}].payeeId", i),
+                format!(
+                    "Transfer rule for account [{}] refers to payee [{}], which does not exist.",
+                    rule.account_id, rule.payee_id
+                ),
+            ));
+        }
+    }
+}
+
+/// Links cleared transactions to the pending transactions they replace. The amount and
+/// description of a transaction often change once it posts, e.g. when a tip is added.
+#[derive(Debug, Deserialize, Clone, JsonSchema)]
+pub struct PendingMatchConfig {
+    /// The largest difference between the pending and cleared amounts
+    #[serde(rename = "amountTolerance", default)]
+    pub amount_tolerance: Option<String>,
+    /// The largest difference between the pending and cleared amounts, as a percentage of the
+    /// pending amount. The larger of the two tolerances is used.
+    #[serde(rename = "amountTolerancePercent", default)]
+    pub amount_tolerance_percent: f64,
+    /// The most days after the pending transaction that it may clear
+    #[serde(
+        rename = "dateWindowDays",
+        default = "default_pending_date_window_days"
+    )]
+    pub date_window_days: u32,
+    /// The lowest fuzzy similarity between the pending and cleared payees, between 0 and 1. 0
+    /// matches any payee.
+    #[serde(
+        rename = "payeeSimilarity",
+        default = "default_pending_payee_similarity"
+    )]
+    pub payee_similarity: f64,
+    /// What to export when a pending transaction exported by a previous run clears
+    #[serde(rename = "supersededPolicy", default)]
+    pub superseded_policy: SupersededPolicy,
+}
+
+impl Default for PendingMatchConfig {
+    fn default() -> Self {
+        PendingMatchConfig {
+            amount_tolerance: Option::None,
+            amount_tolerance_percent: 0.0,
+            date_window_days: default_pending_date_window_days(),
+            payee_similarity: default_pending_payee_similarity(),
+            superseded_policy: SupersededPolicy::default(),
+        }
     }
 }
 
-#[derive(Debug, Deserialize)]
+fn default_pending_date_window_days() -> u32 {
+    7
+}
+
+/// Low enough for reworded payees, e.g. `SQ *BLUE BOTTLE` posting as `BLUE BOTTLE COFFEE`
+fn default_pending_payee_similarity() -> f64 {
+    0.5
+}
+
+#[derive(Debug, Deserialize, PartialEq, Clone, Default, JsonSchema)]
+pub enum SupersededPolicy {
+    /// Don't export the cleared transaction
+    #[default]
+    #[serde(rename = "skip")]
+    Skip,
+    /// Export the cleared transaction as an update of the pending transaction
+    #[serde(rename = "update")]
+    Update,
+    /// Export a removal of the pending transaction, and the cleared transaction as a new one
+    #[serde(rename = "remove")]
+    Remove,
+}
+
+fn validate_balances(config: &Config, problems: &mut Vec<Problem>) {
+    if let Option::Some(ref balance) = config.args.opening_balance {
+        if let Err(e) = Currency::from_str(balance) {
+            problems.push(Problem::error(
+                Path::new(""),
+                "--opening-balance",
+                format!("Invalid opening balance [{}]: {}", balance, e),
+            ));
+        }
+    }
+    if let Option::Some(ref balance) = config.args.closing_balance {
+        if let Err(e) = Currency::from_str(balance) {
+            problems.push(Problem::error(
+                Path::new(""),
+                "--closing-balance",
+                format!("Invalid closing balance [{}]: {}", balance, e),
+            ));
+        }
+    }
+}
+
+fn validate_pending_match(config: &Config, problems: &mut Vec<Problem>) {
+    let file = &config.files.account_config_file;
+    if let Option::Some(c) = config.pending_match() {
+        if let Option::Some(ref amount) = c.amount_tolerance {
+            if let Err(e) = Currency::from_str(amount) {
+                problems.push(Problem::error(
+                    file,
+                    "pendingMatch.amountTolerance",
+                    format!("Invalid pending match amount tolerance [{}]: {}", amount, e),
+                ));
+            }
+        }
+        if c.amount_tolerance_percent < 0.0 {
+            problems.push(Problem::error(
+                file,
+                "pendingMatch.amountTolerancePercent",
+                format!(
+                    "Pending match amount tolerance percent [{}] must not be negative.",
+                    c.amount_tolerance_percent
+                ),
+            ));
+        }
+        if !(0.0..=1.0).contains(&c.payee_similarity) {
+            problems.push(Problem::error(
+                file,
+                "pendingMatch.payeeSimilarity",
+                format!(
+                    "Pending match payee similarity [{}] must be between 0 and 1.",
+                    c.payee_similarity
+                ),
+            ));
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, JsonSchema)]
 pub struct PayeeNormalizerConfig {
     #[serde(rename = "matcher")]
     #[serde(flatten)]
@@ -200,24 +987,84 @@ pub struct PayeeNormalizerConfig {
     pub ignore_case: bool,
 }
 
-fn validate_payee_normalizer_configs(config: &Config) -> anyhow::Result<()> {
+fn validate_payee_normalizer_configs(config: &Config, problems: &mut Vec<Problem>) {
     for (p_id, payee) in &config.account_config_file.payees {
-        for normalizer in &payee.normalizers {
-            if let MatcherType::Regex { ref regex_string } = normalizer.normalizer_type {
-                if let Err(e) = RegexBuilder::new(regex_string).build() {
-                    return Err(anyhow!(
-                        "Invalid regex string provided to normalizer for payee [{}]: {}",
-                        p_id,
-                        e
-                    ));
-                }
+        for (i, normalizer) in payee.normalizers.iter().enumerate() {
+            let (file, i) = config.normalizer_source(p_id, i);
+            for (field, message) in validate_normalizer(normalizer) {
+                problems.push(Problem::error(
+                    file,
+                    format!("payees[{}].normalizers[{}].{}", p_id, i, field),
+                    format!("Normalizer for payee [{}]: {}", p_id, message),
+                ));
+            }
+        }
+    }
+}
+
+/// Check a single payee normalizer. Returns the field and message of each problem.
+pub fn validate_normalizer(normalizer: &PayeeNormalizerConfig) -> Vec<(&'static str, String)> {
+    let mut problems = Vec::new();
+    match normalizer.normalizer_type {
+        MatcherType::Exact {
+            exact_match_string: ref s,
+        }
+        | MatcherType::Contains {
+            contains_string: ref s,
+        } => {
+            if s.trim().is_empty() {
+                problems.push((
+                    "matchString",
+                    String::from("Match string must not be empty."),
+                ));
+            }
+        }
+        MatcherType::Regex { ref regex_string } => {
+            if let Err(e) = RegexBuilder::new(regex_string).build() {
+                problems.push((
+                    "matchString",
+                    format!("Invalid regex string [{}]: {}", regex_string, e),
+                ));
+            }
+        }
+        MatcherType::Fuzzy {
+            ref fuzzy_match_string,
+            threshold,
+            ref token_weights,
+        } => {
+            if normalize_tokens(fuzzy_match_string).is_empty() {
+                problems.push((
+                    "matchString",
+                    format!(
+                        "Fuzzy match string [{}] does not contain any words to match.",
+                        fuzzy_match_string
+                    ),
+                ));
+            }
+            if threshold <= 0.0 || threshold > 1.0 {
+                problems.push((
+                    "threshold",
+                    format!(
+                        "Fuzzy match threshold [{}] must be greater than 0 and at most 1.",
+                        threshold
+                    ),
+                ));
+            }
+            if let Option::Some((token, weight)) = token_weights.iter().find(|(_, w)| **w < 0.0) {
+                problems.push((
+                    "tokenWeights",
+                    format!(
+                        "Fuzzy token weight [{}] for token [{}] must not be negative.",
+                        weight, token
+                    ),
+                ));
             }
         }
     }
-    Ok(())
+    problems
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize, JsonSchema)]
 #[serde(tag = "type")]
 pub enum MatcherType {
     #[serde(rename = "Exact")]
@@ -236,15 +1083,30 @@ pub enum MatcherType {
         #[serde(rename = "matchString")]
         regex_string: String,
     },
+    /// Matches payees whose words are similar to the match string, ignoring store numbers, digits
+    /// and state codes. Always case insensitive.
+    #[serde(rename = "Fuzzy")]
+    Fuzzy {
+        #[serde(rename = "matchString")]
+        fuzzy_match_string: String,
+        #[serde(rename = "threshold", default = "default_fuzzy_threshold")]
+        threshold: f64,
+        #[serde(
+            rename = "tokenWeights",
+            default,
+            skip_serializing_if = "HashMap::is_empty"
+        )]
+        token_weights: HashMap<String, f64>,
+    },
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize, JsonSchema)]
 pub struct Payee {
     #[serde(rename = "id")]
     pub id: String,
     #[serde(rename = "name")]
     pub name: String,
-    #[serde(rename = "categoryIds")]
+    #[serde(rename = "categoryIds", skip_serializing_if = "Option::is_none")]
     pub category_ids: Option<Vec<String>>,
     #[serde(rename = "normalizers")]
     pub normalizers: Vec<PayeeNormalizerConfig>,
@@ -262,24 +1124,216 @@ impl Keyed<String> for Payee {
     }
 }
 
-fn validate_payees(config: &Config) -> anyhow::Result<()> {
+fn validate_payees(config: &Config, problems: &mut Vec<Problem>) {
+    let file = &config.files.account_config_file;
     for (p_id, p) in &config.account_config_file.payees {
         if let Option::Some(ref category_ids) = p.category_ids {
             for c_id in category_ids {
                 if !config.categories_config_file.categories.contains_key(c_id) {
-                    return Err(anyhow!(
-                        "Category [{}] does not exist. Referenced from payee [{}].",
-                        c_id,
-                        p_id
+                    problems.push(Problem::error(
+                        file,
+                        format!("payees[{}].categoryIds", p_id),
+                        format!(
+                            "Category [{}] does not exist. Referenced from payee [{}].",
+                            c_id, p_id
+                        ),
                     ));
                 }
             }
         }
     }
-    Ok(())
 }
 
-#[derive(Debug, Deserialize)]
+/// Choices made in interactive prompts that should be applied automatically in future runs.
+#[derive(Debug, Default, Deserialize, Serialize, JsonSchema)]
+struct OverridesFile {
+    #[serde(rename = "categoryRules", default)]
+    category_rules: Vec<CategoryRule>,
+}
+
+impl OverridesFile {
+    fn from_file(filename: &Path) -> anyhow::Result<OverridesFile> {
+        // The overrides file is only created once a choice is remembered
+        if !filename.exists() {
+            return Ok(OverridesFile::default());
+        }
+        read_config_file(filename)
+    }
+
+    fn save(&self, filename: &Path) -> anyhow::Result<()> {
+        write_config_file(filename, self)
+    }
+}
+
+/// Assigns a category to transactions matching all of the provided conditions. Amounts are
+/// compared using the absolute value of the transaction amount.
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct CategoryRule {
+    #[serde(rename = "categoryId")]
+    pub category_id: String,
+    #[serde(rename = "payeeId", default, skip_serializing_if = "Option::is_none")]
+    pub payee_id: Option<String>,
+    #[serde(rename = "rawPayee", default, skip_serializing_if = "Option::is_none")]
+    pub raw_payee: Option<String>,
+    #[serde(rename = "minAmount", default, skip_serializing_if = "Option::is_none")]
+    pub min_amount: Option<String>,
+    #[serde(rename = "maxAmount", default, skip_serializing_if = "Option::is_none")]
+    pub max_amount: Option<String>,
+}
+
+fn validate_category_rules(config: &Config, problems: &mut Vec<Problem>) {
+    let file = overrides_file_name(&config.args, &config.files);
+    for (i, rule) in config.category_rules().iter().enumerate() {
+        if rule.payee_id.is_none() && rule.raw_payee.is_none() {
+            problems.push(Problem::error(
+                &file,
+                format!("categoryRules[{}]", i),
+                format!(
+                    "Category rule for category [{}] must specify a payee ID or a raw payee.",
+                    rule.category_id
+                ),
+            ));
+        }
+        if !config
+            .categories_config_file
+            .categories
+            .contains_key(&rule.category_id)
+        {
+            problems.push(Problem::error(
+                &file,
+                format!("categoryRules[{}].categoryId", i),
+                format!(
+                    "Category [{}] does not exist. Referenced from a category rule.",
+                    rule.category_id
+                ),
+            ));
+        }
+        if let Option::Some(ref p_id) = rule.payee_id {
+            if !config.account_config_file.payees.contains_key(p_id) {
+                problems.push(Problem::error(
+                    &file,
+                    format!("categoryRules[{}].payeeId", i),
+                    format!(
+                        "Payee [{}] does not exist. Referenced from a category rule.",
+                        p_id
+                    ),
+                ));
+            }
+        }
+        for amount in [&rule.min_amount, &rule.max_amount].into_iter().flatten() {
+            if let Err(e) = Currency::from_str(amount) {
+                problems.push(Problem::error(
+                    &file,
+                    format!("categoryRules[{}]", i),
+                    format!(
+                        "Invalid amount [{}] in category rule for category [{}]: {}",
+                        amount, rule.category_id, e
+                    ),
+                ));
+            }
+        }
+        let cents = |a: &Option<String>| {
+            a.as_ref()
+                .and_then(|a| Currency::from_str(a).ok())
+                .map(|a| util::currency_to_cents(&a).abs())
+        };
+        if let (Option::Some(min), Option::Some(max)) =
+            (cents(&rule.min_amount), cents(&rule.max_amount))
+        {
+            if min > max {
+                problems.push(Problem::error(
+                    &file,
+                    format!("categoryRules[{}]", i),
+                    format!(
+                        "Category rule for category [{}] has a minAmount greater than its maxAmount, so it never matches.",
+                        rule.category_id
+                    ),
+                ));
+            }
+        }
+    }
+}
+
+/// Answers to category prompts, keyed by the fingerprint of the transaction they apply to.
+#[derive(Debug, Default, Deserialize, JsonSchema)]
+struct DecisionsFile {
+    #[serde(rename = "decisions", deserialize_with = "deserialize_keyed_items")]
+    #[schemars(with = "Vec<Decision>")]
+    decisions: HashMap<String, Decision>,
+}
+
+impl DecisionsFile {
+    fn from_file(filename: &Path) -> anyhow::Result<DecisionsFile> {
+        read_config_file(filename)
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct DecisionsTemplate<'a> {
+    #[serde(rename = "decisions")]
+    decisions: &'a [Decision],
+}
+
+/// The category to use for a single transaction. Only the fingerprint and category ID are used
+/// when reading decisions; the other fields describe the transaction for whoever fills in the
+/// template.
+#[derive(Debug, Deserialize, Serialize, JsonSchema)]
+pub struct Decision {
+    #[serde(rename = "fingerprint")]
+    pub fingerprint: String,
+    /// Leave empty to skip categorizing the transaction
+    #[serde(rename = "categoryId")]
+    pub category_id: Option<String>,
+    #[serde(rename = "date", default)]
+    pub date: Option<String>,
+    #[serde(rename = "payee", default)]
+    pub payee: Option<String>,
+    #[serde(rename = "rawPayee", default)]
+    pub raw_payee: Option<String>,
+    #[serde(rename = "amount", default)]
+    pub amount: Option<String>,
+    #[serde(rename = "memo", default)]
+    pub memo: Option<String>,
+    #[serde(rename = "options", default)]
+    pub options: Vec<String>,
+}
+
+impl Display for Decision {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Decision: (fingerprint: {})", self.fingerprint)
+    }
+}
+
+impl Keyed<String> for Decision {
+    fn key(&self) -> String {
+        self.fingerprint.to_owned()
+    }
+}
+
+fn validate_decisions(config: &Config, problems: &mut Vec<Problem>) {
+    let file = config
+        .args
+        .decisions_file
+        .as_deref()
+        .unwrap_or(Path::new(""));
+    for decision in config.decisions_file.decisions.values() {
+        if let Option::Some(ref c_id) = decision.category_id {
+            if !config.categories_config_file.categories.contains_key(c_id) {
+                problems.push(Problem::error(
+                    file,
+                    format!("decisions[{}].categoryId", decision.fingerprint),
+                    format!(
+                        "Category [{}] does not exist. Referenced from decision for transaction [{}].",
+                        c_id,
+                        decision.fingerprint
+                    ),
+                ));
+            }
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
 pub struct Category {
     #[serde(rename = "id")]
     pub id: String,
@@ -299,7 +1353,7 @@ impl Keyed<String> for Category {
     }
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Clone, JsonSchema)]
 pub struct Sort {
     #[serde(rename = "sortBy")]
     pub sort_by: SortBy,
@@ -307,13 +1361,13 @@ pub struct Sort {
     pub order: SortOrder,
 }
 
-#[derive(Debug, Deserialize, PartialEq, Clone, clap::ValueEnum)]
+#[derive(Debug, Deserialize, PartialEq, Clone, clap::ValueEnum, JsonSchema)]
 pub enum SortBy {
     #[serde(rename = "date")]
     Date,
 }
 
-#[derive(Debug, Deserialize, PartialEq, Clone, clap::ValueEnum)]
+#[derive(Debug, Deserialize, PartialEq, Clone, clap::ValueEnum, JsonSchema)]
 pub enum SortOrder {
     #[serde(rename = "ascending")]
     Ascending,
@@ -322,7 +1376,7 @@ pub enum SortOrder {
 }
 
 // todo: should FormatConfig go in the 'formats' module?
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 pub struct FormatConfigFile {
     #[serde(rename = "id")]
     pub id: String,
@@ -334,6 +1388,9 @@ pub struct FormatConfigFile {
     sort: Option<Sort>,
     #[serde(rename = "dataFormat")]
     pub data_format: DataFormat,
+    /// The character between fields. Defaults to a comma.
+    #[serde(rename = "delimiter")]
+    delimiter: Option<char>,
     #[serde(rename = "fieldOrder")]
     pub field_order: Vec<String>,
     #[serde(rename = "dateTimeConfig")]
@@ -350,13 +1407,32 @@ pub struct FormatConfigFile {
     pub category_config: Option<CategoryConfig>,
     #[serde(rename = "accountConfig")]
     pub account_config: Option<AccountConfig>,
+    #[serde(rename = "idConfig")]
+    pub id_config: Option<IdConfig>,
+    #[serde(rename = "changeConfig")]
+    pub change_config: Option<ChangeConfig>,
+    #[serde(rename = "balanceConfig")]
+    pub balance_config: Option<BalanceConfig>,
+    #[serde(rename = "preambleConfig")]
+    pub preamble_config: Option<PreambleConfig>,
+    #[serde(rename = "transferConfig")]
+    pub transfer_config: Option<TransferConfig>,
+    #[serde(rename = "fileMatch")]
+    pub file_match: Option<FileMatchConfig>,
 }
 
 impl FormatConfigFile {
+    /// Read a format file, which may be based on a preset, or a preset given as `preset:<id>`
     fn from_file(filename: &Path) -> anyhow::Result<FormatConfigFile> {
-        let r = util::reader_from_file_name(filename)?;
-        let config_file: FormatConfigFile = serde_json::from_reader(r)?;
-        Ok(config_file)
+        let value = match presets::preset_id(filename) {
+            Option::Some(id) => presets::preset(id)?.value()?,
+            Option::None => presets::apply_preset(read_config_file(filename)?)?,
+        };
+        Ok(serde_json::from_value(value)?)
+    }
+
+    pub fn delimiter(&self) -> u8 {
+        self.delimiter.map_or(b',', |d| d as u8)
     }
 }
 
@@ -373,17 +1449,45 @@ impl Keyed<String> for FormatConfigFile {
 }
 
 fn validate_format(format_config: &FormatConfigFile) -> anyhow::Result<()> {
-    validate_date_time_config(format_config, &format_config.date_time_config)?;
-    validate_amount_config(format_config, &format_config.amount_config)?;
+    let mut problems = Vec::new();
+    validate_format_file(Path::new(""), format_config, &mut problems);
+    match problems.into_iter().next() {
+        Option::Some(p) => Err(anyhow!(p.message)),
+        Option::None => Ok(()),
+    }
+}
+
+fn validate_format_file(
+    file: &Path,
+    format_config: &FormatConfigFile,
+    problems: &mut Vec<Problem>,
+) {
+    if let Option::Some(d) = format_config.delimiter {
+        if !d.is_ascii() {
+            problems.push(Problem::error(
+                file,
+                "delimiter",
+                format!(
+                    "Delimiter [{}] for format [{}] is not an ASCII character.",
+                    d, format_config.id
+                ),
+            ));
+        }
+    }
+    validate_date_time_config(file, format_config, problems);
+    validate_amount_config(file, format_config, problems);
 
     if !format_config
         .field_order
         .contains(&format_config.payee_config.field_name)
     {
-        return Err(anyhow!(
-            "Payee field name [{}] for format [{}] not included in field order.",
-            format_config.payee_config.field_name,
-            format_config.id
+        problems.push(Problem::error(
+            file,
+            "payeeConfig.fieldName",
+            format!(
+                "Payee field name [{}] for format [{}] not included in field order.",
+                format_config.payee_config.field_name, format_config.id
+            ),
         ));
     }
     if let Option::Some(ref status_config) = format_config.status_config {
@@ -391,19 +1495,25 @@ fn validate_format(format_config: &FormatConfigFile) -> anyhow::Result<()> {
             .field_order
             .contains(&status_config.field_name)
         {
-            return Err(anyhow!(
-                "Status field name [{}] for format [{}] not included in field order.",
-                status_config.field_name,
-                format_config.id
+            problems.push(Problem::error(
+                file,
+                "statusConfig.fieldName",
+                format!(
+                    "Status field name [{}] for format [{}] not included in field order.",
+                    status_config.field_name, format_config.id
+                ),
             ));
         }
     }
     if let Option::Some(ref memo_config) = format_config.memo_config {
         if !format_config.field_order.contains(&memo_config.field_name) {
-            return Err(anyhow!(
-                "Status field name [{}] for format [{}] not included in field order.",
-                memo_config.field_name,
-                format_config.id
+            problems.push(Problem::error(
+                file,
+                "memoConfig.fieldName",
+                format!(
+                    "Status field name [{}] for format [{}] not included in field order.",
+                    memo_config.field_name, format_config.id
+                ),
             ));
         }
     }
@@ -412,23 +1522,131 @@ fn validate_format(format_config: &FormatConfigFile) -> anyhow::Result<()> {
             .field_order
             .contains(&category_config.field_name)
         {
-            return Err(anyhow!(
-                "Status field name [{}] for format [{}] not included in field order.",
-                category_config.field_name,
-                format_config.id
+            problems.push(Problem::error(
+                file,
+                "categoryConfig.fieldName",
+                format!(
+                    "Status field name [{}] for format [{}] not included in field order.",
+                    category_config.field_name, format_config.id
+                ),
+            ));
+        }
+    }
+    if let Option::Some(ref id_config) = format_config.id_config {
+        if !format_config.field_order.contains(&id_config.field_name) {
+            problems.push(Problem::error(
+                file,
+                "idConfig.fieldName",
+                format!(
+                    "ID field name [{}] for format [{}] not included in field order.",
+                    id_config.field_name, format_config.id
+                ),
+            ));
+        }
+    }
+    if let Option::Some(ref balance_config) = format_config.balance_config {
+        if !format_config
+            .field_order
+            .contains(&balance_config.field_name)
+        {
+            problems.push(Problem::error(
+                file,
+                "balanceConfig.fieldName",
+                format!(
+                    "Balance field name [{}] for format [{}] not included in field order.",
+                    balance_config.field_name, format_config.id
+                ),
+            ));
+        }
+    }
+    if let Option::Some(ref pattern) = format_config
+        .file_match
+        .as_ref()
+        .and_then(|c| c.file_name_pattern.as_ref())
+    {
+        if let Err(e) = glob::Pattern::new(pattern) {
+            problems.push(Problem::error(
+                file,
+                "fileMatch.fileNamePattern",
+                format!(
+                    "Invalid file name pattern [{}] for format [{}]: {}",
+                    pattern, format_config.id, e
+                ),
             ));
         }
     }
-    Ok(())
+    if let Option::Some(ref preamble_config) = format_config.preamble_config {
+        for regex_string in [
+            &preamble_config.opening_balance_regex,
+            &preamble_config.closing_balance_regex,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            validate_preamble_regex(file, format_config, regex_string, problems);
+        }
+    }
+    for field_name in format_config
+        .transfer_config
+        .iter()
+        .flat_map(|c| [&c.field_name, &c.id_field_name])
+        .flatten()
+    {
+        if !format_config.field_order.contains(field_name) {
+            problems.push(Problem::error(
+                file,
+                "transferConfig",
+                format!(
+                    "Transfer field name [{}] for format [{}] not included in field order.",
+                    field_name, format_config.id
+                ),
+            ));
+        }
+    }
+    if let Option::Some(ref change_config) = format_config.change_config {
+        if !format_config
+            .field_order
+            .contains(&change_config.field_name)
+        {
+            problems.push(Problem::error(
+                file,
+                "changeConfig.fieldName",
+                format!(
+                    "Change field name [{}] for format [{}] not included in field order.",
+                    change_config.field_name, format_config.id
+                ),
+            ));
+        }
+    }
+}
+
+fn validate_preamble_regex(
+    file: &Path,
+    format_config: &FormatConfigFile,
+    regex_string: &str,
+    problems: &mut Vec<Problem>,
+) {
+    let message = match RegexBuilder::new(regex_string).build() {
+        Ok(r) if r.captures_len() > 1 => return,
+        Ok(_) => format!(
+            "Preamble regex [{}] for format [{}] has no capture group for the amount.",
+            regex_string, format_config.id
+        ),
+        Err(e) => format!(
+            "Invalid preamble regex [{}] for format [{}]: {}",
+            regex_string, format_config.id, e
+        ),
+    };
+    problems.push(Problem::error(file, "preambleConfig", message));
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 pub enum DataFormat {
     #[serde(rename = "csv")]
     Csv,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 pub struct DateTimeConfig {
     #[serde(rename = "dateField")]
     pub date_field: String,
@@ -442,86 +1660,107 @@ pub struct DateTimeConfig {
     pub deliminator: Option<String>,
 }
 
-fn validate_date_time_config(f: &FormatConfigFile, d: &DateTimeConfig) -> anyhow::Result<()> {
+fn validate_date_time_config(file: &Path, f: &FormatConfigFile, problems: &mut Vec<Problem>) {
+    let d = &f.date_time_config;
     if !f.field_order.contains(&d.date_field) {
-        return Err(anyhow!(
-            "Date field name [{}] for format [{}] not included in field order.",
-            d.date_field,
-            f.id
+        problems.push(Problem::error(
+            file,
+            "dateTimeConfig.dateField",
+            format!(
+                "Date field name [{}] for format [{}] not included in field order.",
+                d.date_field, f.id
+            ),
         ));
     }
     if let Option::Some(ref time_field) = d.time_field {
         if !f.field_order.contains(time_field) {
-            return Err(anyhow!(
-                "Time field name [{}] for format [{}] not included in field order.",
-                time_field,
-                f.id
+            problems.push(Problem::error(
+                file,
+                "dateTimeConfig.timeField",
+                format!(
+                    "Time field name [{}] for format [{}] not included in field order.",
+                    time_field, f.id
+                ),
             ));
         }
     }
-    Ok(())
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 pub struct PayeeConfig {
     #[serde(rename = "fieldName")]
     pub field_name: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 pub struct AmountConfig {
     #[serde(rename = "format")]
     pub format: AmountFormat,
 }
 
-fn validate_amount_config(f: &FormatConfigFile, a: &AmountConfig) -> anyhow::Result<()> {
-    match a.format {
+fn validate_amount_config(file: &Path, f: &FormatConfigFile, problems: &mut Vec<Problem>) {
+    match f.amount_config.format {
         AmountFormat::SingleAmountField(ref c) => {
             if !f.field_order.contains(&c.field_name) {
-                return Err(anyhow!(
-                    "Amount field name [{}] for format [{}] not included in field order.",
-                    c.field_name,
-                    f.id
+                problems.push(Problem::error(
+                    file,
+                    "amountConfig.format.fieldName",
+                    format!(
+                        "Amount field name [{}] for format [{}] not included in field order.",
+                        c.field_name, f.id
+                    ),
                 ));
             }
         }
         AmountFormat::SeparateDebitCreditFields(ref c) => {
             if !f.field_order.contains(&c.debit_field) {
-                return Err(anyhow!(
-                    "Debit field name [{}] for format [{}] not included in field order.",
-                    c.debit_field,
-                    f.id
+                problems.push(Problem::error(
+                    file,
+                    "amountConfig.format.debitField",
+                    format!(
+                        "Debit field name [{}] for format [{}] not included in field order.",
+                        c.debit_field, f.id
+                    ),
                 ));
             }
             if !f.field_order.contains(&c.credit_field) {
-                return Err(anyhow!(
-                    "Credit field name [{}] for format [{}] not included in field order.",
-                    c.credit_field,
-                    f.id
+                problems.push(Problem::error(
+                    file,
+                    "amountConfig.format.creditField",
+                    format!(
+                        "Credit field name [{}] for format [{}] not included in field order.",
+                        c.credit_field, f.id
+                    ),
                 ));
             }
         }
         AmountFormat::TransactionTypeAndAmountFields(ref c) => {
             if !f.field_order.contains(&c.amount_field) {
-                return Err(anyhow!(
-                    "Amount field name [{}] for format [{}] not included in field order.",
-                    c.amount_field,
-                    f.id
+                problems.push(Problem::error(
+                    file,
+                    "amountConfig.format.amountField",
+                    format!(
+                        "Amount field name [{}] for format [{}] not included in field order.",
+                        c.amount_field, f.id
+                    ),
                 ));
             }
             if !f.field_order.contains(&c.transaction_type_field) {
-                return Err(anyhow!(
-                    "Transaction type field name [{}] for format [{}] not included in field order.",
-                    c.transaction_type_field,
-                    f.id
+                problems.push(Problem::error(
+                    file,
+                    "amountConfig.format.transactionTypeField",
+                    format!(
+                        "Transaction type field name [{}] for format [{}] not included in field order.",
+                        c.transaction_type_field,
+                        f.id
+                    ),
                 ));
             }
         }
     }
-    Ok(())
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 #[serde(tag = "type")]
 pub enum AmountFormat {
     #[serde(rename = "SingleAmountField")]
@@ -532,7 +1771,7 @@ pub enum AmountFormat {
     TransactionTypeAndAmountFields(TransactionTypeAndAmountFieldsConfig),
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 pub struct SingleAmountFieldConfig {
     #[serde(rename = "fieldName")]
     pub field_name: String,
@@ -540,7 +1779,7 @@ pub struct SingleAmountFieldConfig {
     pub debit_is_negative: bool,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 pub struct SeparateDebitCreditFieldsConfig {
     #[serde(rename = "debitField")]
     pub debit_field: String,
@@ -548,7 +1787,7 @@ pub struct SeparateDebitCreditFieldsConfig {
     pub credit_field: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 pub struct TransactionTypeAndAmountFieldsConfig {
     #[serde(rename = "amountField")]
     pub amount_field: String,
@@ -562,7 +1801,7 @@ pub struct TransactionTypeAndAmountFieldsConfig {
     pub include_debit_sign: bool,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 pub struct StatusConfig {
     #[serde(rename = "fieldName")]
     pub field_name: String,
@@ -572,20 +1811,109 @@ pub struct StatusConfig {
     pub cleared_string: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 pub struct MemoConfig {
     #[serde(rename = "fieldName")]
     pub field_name: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 pub struct CategoryConfig {
     #[serde(rename = "fieldName")]
     pub field_name: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 pub struct AccountConfig {
     #[serde(rename = "fieldName")]
     pub field_name: String,
 }
+
+/// A stable ID for each exported transaction. Updates and removals use the ID of the pending
+/// transaction they replace.
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct IdConfig {
+    #[serde(rename = "fieldName")]
+    pub field_name: String,
+}
+
+/// The balances of the account before and after the transactions in a statement
+#[derive(Debug, Default)]
+pub struct StatementBalances {
+    pub opening: Option<Currency>,
+    pub closing: Option<Currency>,
+}
+
+/// The balance of the account after each transaction. When importing, the balances are used to
+/// find the opening and closing balances of the statement.
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct BalanceConfig {
+    #[serde(rename = "fieldName")]
+    pub field_name: String,
+}
+
+/// Lines before the header row, e.g. an account summary at the top of a statement
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct PreambleConfig {
+    #[serde(rename = "lineCount")]
+    pub line_count: usize,
+    /// Finds the opening balance in the preamble. The first capture group is the amount.
+    #[serde(rename = "openingBalanceRegex")]
+    pub opening_balance_regex: Option<String>,
+    /// Finds the closing balance in the preamble. The first capture group is the amount.
+    #[serde(rename = "closingBalanceRegex")]
+    pub closing_balance_regex: Option<String>,
+}
+
+/// Identifies the source files in the format, when importing files in several formats
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct FileMatchConfig {
+    /// A glob pattern for the file name, e.g. `Chase*_Activity_*.CSV`
+    #[serde(rename = "fileNamePattern")]
+    pub file_name_pattern: Option<String>,
+    /// Columns that must all be in the header row. Defaults to the columns the format imports.
+    #[serde(rename = "headers", default)]
+    pub headers: Vec<String>,
+}
+
+/// How transfers between accounts are exported. Transfers are never categorized.
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct TransferConfig {
+    /// The payee of a transfer, where `{account}` is the name of the other account
+    #[serde(rename = "payeeFormat", default = "default_transfer_payee_format")]
+    pub payee_format: String,
+    /// A field for the name of the other account
+    #[serde(rename = "fieldName")]
+    pub field_name: Option<String>,
+    /// A field for an ID shared by both sides of the transfer
+    #[serde(rename = "idFieldName")]
+    pub id_field_name: Option<String>,
+}
+
+impl Default for TransferConfig {
+    fn default() -> Self {
+        TransferConfig {
+            payee_format: default_transfer_payee_format(),
+            field_name: Option::None,
+            id_field_name: Option::None,
+        }
+    }
+}
+
+fn default_transfer_payee_format() -> String {
+    String::from("Transfer : {account}")
+}
+
+/// Whether each exported transaction is new, or updates or removes a pending transaction that
+/// was exported by a previous run.
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct ChangeConfig {
+    #[serde(rename = "fieldName")]
+    pub field_name: String,
+    #[serde(rename = "addString")]
+    pub add_string: String,
+    #[serde(rename = "updateString")]
+    pub update_string: String,
+    #[serde(rename = "removeString")]
+    pub remove_string: String,
+}
@@ -0,0 +1,164 @@
+use crate::config::file_format::read_config_file;
+use anyhow::anyhow;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// The payees of a payee library file
+pub fn read_payee_library(filename: &Path) -> anyhow::Result<Vec<Value>> {
+    let mut library: Value = read_config_file(filename).map_err(|e| {
+        anyhow!(
+            "Unable to parse payee library [{}]: {}",
+            filename.to_str().unwrap_or("Invalid file name"),
+            e
+        )
+    })?;
+    match library.get_mut("payees").map(Value::take) {
+        Option::Some(Value::Array(payees)) => Ok(payees),
+        _ => Err(anyhow!(
+            "Payee library [{}] does not have a list of payees.",
+            filename.to_str().unwrap_or("Invalid file name")
+        )),
+    }
+}
+
+/// Add the payees of the included libraries, each listed with its library file, to the payees of
+/// the account config. A library payee is used as is, unless the account has a payee with the
+/// same ID that either sets `override` to replace it, or sets `extend` to add its own name,
+/// category IDs and normalizers to it. Any other payee ID that's in both the account and a
+/// library is a duplicate.
+///
+/// Returns the file each normalizer of each payee comes from, by payee ID.
+pub fn merge_payees(
+    account: &mut Value,
+    account_file: &Path,
+    library_payees: Vec<(PathBuf, Value)>,
+) -> anyhow::Result<HashMap<String, Vec<PathBuf>>> {
+    let account_payees = match account.get_mut("payees").map(Value::take) {
+        Option::Some(Value::Array(p)) => p,
+        _ => Vec::new(),
+    };
+    let normalizer_count = |payee: &Value| {
+        payee
+            .get("normalizers")
+            .and_then(Value::as_array)
+            .map_or(0, Vec::len)
+    };
+    let mut sources: HashMap<String, Vec<PathBuf>> = HashMap::new();
+    let mut payees = Vec::new();
+    for (file, payee) in library_payees {
+        if let Option::Some(id) = payee.get("id").and_then(Value::as_str) {
+            sources.insert(id.to_owned(), vec![file; normalizer_count(&payee)]);
+        }
+        payees.push(payee);
+    }
+    for mut payee in account_payees {
+        let id = payee.get("id").and_then(Value::as_str).map(str::to_owned);
+        let added = vec![account_file.to_owned(); normalizer_count(&payee)];
+        let flag = |payee: &mut Value, name: &str| {
+            payee
+                .as_object_mut()
+                .and_then(|p| p.remove(name))
+                .and_then(|v| v.as_bool())
+                .unwrap_or(false)
+        };
+        let replaces = flag(&mut payee, "override");
+        let extends = flag(&mut payee, "extend");
+        if !replaces && !extends {
+            if let Option::Some(id) = id {
+                sources.insert(id, added);
+            }
+            payees.push(payee);
+            continue;
+        }
+        let id = id.unwrap_or_default();
+        let library_payee = payees
+            .iter_mut()
+            .find(|p| p.get("id").and_then(Value::as_str) == Option::Some(id.as_str()))
+            .ok_or_else(|| anyhow!("Payee [{}] is not in any included payee library.", id))?;
+        if replaces {
+            *library_payee = payee;
+            sources.insert(id, added);
+        } else {
+            extend_payee(library_payee, payee);
+            sources.entry(id).or_default().extend(added);
+        }
+    }
+    if let Option::Some(a) = account.as_object_mut() {
+        a.insert("payees".to_owned(), Value::Array(payees));
+    }
+    Ok(sources)
+}
+
+/// Lists are appended to, and other fields are replaced
+fn extend_payee(payee: &mut Value, extension: Value) {
+    let (Option::Some(payee), Value::Object(extension)) = (payee.as_object_mut(), extension) else {
+        return;
+    };
+    for (key, value) in extension {
+        match (payee.get_mut(&key), value) {
+            (Option::Some(Value::Array(existing)), Value::Array(added)) => existing.extend(added),
+            (_, value) => {
+                payee.insert(key, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config::AccountConfigFile;
+    use crate::config::payee_library::merge_payees;
+    use serde_json::json;
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn test_merge_payees() {
+        let library = || {
+            [
+                json!({"id": "amazon", "name": "Amazon", "categoryIds": ["shopping"],
+                    "normalizers": [{"type": "Contains", "matchString": "AMZN"}]}),
+                json!({"id": "netflix", "name": "Netflix", "normalizers": []}),
+                json!({"id": "power", "name": "Power", "normalizers": []}),
+            ]
+            .into_iter()
+            .map(|p| (PathBuf::from("library.json"), p))
+            .collect::<Vec<_>>()
+        };
+        let account_file = Path::new("checking.json");
+        let mut account = json!({
+            "id": "checking", "name": "Checking", "formatId": "bank",
+            "payees": [
+                {"id": "amazon", "extend": true,
+                    "normalizers": [{"type": "Contains", "matchString": "AMAZON"}]},
+                {"id": "netflix", "override": true, "name": "Streaming", "normalizers": []},
+                {"id": "cafe", "name": "Cafe", "normalizers": []}
+            ]
+        });
+        let sources = merge_payees(&mut account, account_file, library()).unwrap();
+        let a: AccountConfigFile = serde_json::from_value(account).unwrap();
+        assert_eq!(a.payees.len(), 4);
+        assert_eq!(a.payees["amazon"].name, "Amazon");
+        assert_eq!(a.payees["amazon"].normalizers.len(), 2);
+        assert_eq!(a.payees["netflix"].name, "Streaming");
+        // The library's normalizer comes first in an extended payee
+        assert_eq!(
+            sources["amazon"],
+            vec![
+                PathBuf::from("library.json"),
+                PathBuf::from("checking.json")
+            ]
+        );
+
+        // A payee with the same ID as a library payee has to override or extend it
+        let mut account = json!({
+            "id": "checking", "name": "Checking", "formatId": "bank",
+            "payees": [{"id": "power", "name": "Power", "normalizers": []}]
+        });
+        merge_payees(&mut account, account_file, library()).unwrap();
+        assert!(serde_json::from_value::<AccountConfigFile>(account).is_err());
+
+        let mut account = json!({"payees": [{"id": "gym", "extend": true}]});
+        assert!(merge_payees(&mut account, account_file, library()).is_err());
+    }
+}
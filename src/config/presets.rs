@@ -0,0 +1,140 @@
+use anyhow::anyhow;
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+
+/// A format config embedded in the binary, for the export files of a bank or the import files
+/// of a budgeting tool
+pub struct Preset {
+    pub id: &'static str,
+    pub config: &'static str,
+    /// An example of a file in the format
+    pub sample: &'static str,
+}
+
+macro_rules! preset {
+    ($id:literal) => {
+        Preset {
+            id: $id,
+            config: include_str!(concat!("../../presets/", $id, ".json")),
+            sample: include_str!(concat!("../../presets/", $id, ".sample.csv")),
+        }
+    };
+}
+
+pub const PRESETS: &[Preset] = &[
+    preset!("amex"),
+    preset!("capital-one"),
+    preset!("chase-credit"),
+    preset!("discover"),
+    preset!("mint"),
+    preset!("monarch"),
+    preset!("ynab"),
+];
+
+/// Presets are given in place of a format file as `preset:<id>`
+const PRESET_PREFIX: &str = "preset:";
+
+pub fn preset(id: &str) -> anyhow::Result<&'static Preset> {
+    PRESETS.iter().find(|p| p.id == id).ok_or_else(|| {
+        anyhow!(
+            "There is no format preset [{}]. The presets are [{}].",
+            id,
+            PRESETS.iter().map(|p| p.id).collect::<Vec<_>>().join(", ")
+        )
+    })
+}
+
+/// The path that stands for a preset in place of a format file
+pub fn preset_path(id: &str) -> PathBuf {
+    PathBuf::from(format!("{}{}", PRESET_PREFIX, id))
+}
+
+pub fn preset_id(filename: &Path) -> Option<&str> {
+    filename.to_str()?.strip_prefix(PRESET_PREFIX)
+}
+
+impl Preset {
+    pub fn value(&self) -> anyhow::Result<Value> {
+        serde_json::from_str(self.config)
+            .map_err(|e| anyhow!("Unable to parse format preset [{}]: {}", self.id, e))
+    }
+}
+
+/// A format file that sets `preset` is applied on top of the preset. Objects are merged field by
+/// field, and any other value replaces the value of the preset.
+pub fn apply_preset(mut format: Value) -> anyhow::Result<Value> {
+    let id = match format.as_object_mut().and_then(|f| f.remove("preset")) {
+        Option::Some(Value::String(id)) => id,
+        Option::Some(v) => return Err(anyhow!("The preset [{}] is not a preset ID.", v)),
+        Option::None => return Ok(format),
+    };
+    let mut value = preset(&id)?.value()?;
+    merge(&mut value, format);
+    Ok(value)
+}
+
+fn merge(value: &mut Value, overrides: Value) {
+    match (value, overrides) {
+        (Value::Object(value), Value::Object(overrides)) => {
+            for (key, v) in overrides {
+                match value.get_mut(&key) {
+                    Option::Some(existing) => merge(existing, v),
+                    Option::None => {
+                        value.insert(key, v);
+                    }
+                }
+            }
+        }
+        (value, overrides) => *value = overrides,
+    }
+}
+
+/// List the presets, or print the format config or sample file of a preset
+pub fn print_presets(id: Option<&str>, sample: bool) -> anyhow::Result<()> {
+    match id {
+        Option::Some(id) => {
+            let p = preset(id)?;
+            print!("{}", if sample { p.sample } else { p.config });
+        }
+        Option::None => {
+            for p in PRESETS {
+                let name = p.value()?["name"].as_str().unwrap_or_default().to_owned();
+                println!("{:<16}{}", p.id, name);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config::presets::{PRESETS, apply_preset};
+    use crate::config::{FormatConfigFile, validate_format};
+    use serde_json::json;
+
+    #[test]
+    fn test_presets_are_valid() {
+        for p in PRESETS {
+            let f: FormatConfigFile = serde_json::from_value(p.value().unwrap()).unwrap();
+            assert_eq!(f.id, p.id);
+            validate_format(&f).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_apply_preset() {
+        let format = json!({
+            "preset": "chase-credit",
+            "id": "chase-uk",
+            "dateTimeConfig": {"dateFormat": "%d/%m/%Y"},
+            "fieldOrder": ["Transaction Date", "Description", "Amount"]
+        });
+        let f: FormatConfigFile = serde_json::from_value(apply_preset(format).unwrap()).unwrap();
+        assert_eq!(f.id, "chase-uk");
+        assert_eq!(f.date_time_config.date_field, "Transaction Date");
+        assert_eq!(f.date_time_config.date_format, "%d/%m/%Y");
+        assert_eq!(f.field_order.len(), 3);
+
+        assert!(apply_preset(json!({"preset": "no-such-bank"})).is_err());
+    }
+}
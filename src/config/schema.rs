@@ -0,0 +1,152 @@
+use crate::config::presets::PRESETS;
+use crate::config::{AccountConfigFile, CategoriesConfigFile, FormatConfigFile};
+use crate::diagnostics::Diagnostic;
+use anyhow::anyhow;
+use schemars::schema_for;
+use serde_json::{Value, json};
+use std::fs;
+use std::path::Path;
+
+/// The config file types that have a JSON Schema
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum SchemaKind {
+    Account,
+    Categories,
+    Format,
+}
+
+impl SchemaKind {
+    pub const ALL: [SchemaKind; 3] = [
+        SchemaKind::Account,
+        SchemaKind::Categories,
+        SchemaKind::Format,
+    ];
+
+    pub fn schema(self) -> anyhow::Result<Value> {
+        let mut schema = match self {
+            SchemaKind::Account => serde_json::to_value(schema_for!(AccountConfigFile))?,
+            SchemaKind::Categories => serde_json::to_value(schema_for!(CategoriesConfigFile))?,
+            SchemaKind::Format => serde_json::to_value(schema_for!(FormatConfigFile))?,
+        };
+        match self {
+            SchemaKind::Account => add_payee_library_keys(&mut schema),
+            SchemaKind::Categories => {}
+            SchemaKind::Format => add_preset_key(&mut schema),
+        }
+        Ok(schema)
+    }
+
+    /// The name of the schema file, e.g. `account.schema.json`
+    pub fn file_name(self) -> String {
+        let name = match self {
+            SchemaKind::Account => "account",
+            SchemaKind::Categories => "categories",
+            SchemaKind::Format => "format",
+        };
+        format!("{}.schema.json", name)
+    }
+}
+
+/// The keys that are handled before the account config is read into its type: `include` lists
+/// payee libraries, and a payee that sets `extend` only needs its ID. `override` replaces the
+/// library payee with a complete payee.
+fn add_payee_library_keys(schema: &mut Value) {
+    schema["properties"]["include"] = json!({
+        "description": "Payee libraries to add to the payees of the account, relative to this file",
+        "type": "array",
+        "items": {"type": "string"}
+    });
+    let payee = &mut schema["definitions"]["Payee"];
+    payee["properties"]["extend"] = json!({
+        "description": "Add the name, category IDs and normalizers to the library payee with the same ID",
+        "type": "boolean"
+    });
+    payee["properties"]["override"] = json!({
+        "description": "Replace the library payee with the same ID",
+        "type": "boolean"
+    });
+    let required = payee["required"].take();
+    payee["required"] = json!(["id"]);
+    payee["if"] = json!({"properties": {"extend": {"const": true}}, "required": ["extend"]});
+    payee["else"] = json!({"required": required});
+}
+
+/// A format file that sets `preset` is merged into the preset, so it needs none of the other
+/// fields.
+fn add_preset_key(schema: &mut Value) {
+    schema["properties"]["preset"] = json!({
+        "description": "Use this preset, with the other fields of this file replacing the preset's",
+        "type": "string",
+        "enum": PRESETS.iter().map(|p| p.id).collect::<Vec<_>>()
+    });
+    let required = schema["required"].take();
+    if let Option::Some(s) = schema.as_object_mut() {
+        s.shift_remove("required");
+    }
+    schema["if"] = json!({"required": ["preset"]});
+    schema["else"] = json!({"required": required});
+}
+
+/// Print the schema of a single config file type, or write the schema of every type, or of a
+/// single type, to a directory
+pub fn write_schemas(kind: Option<SchemaKind>, out_dir: Option<&Path>) -> anyhow::Result<()> {
+    let Option::Some(dir) = out_dir else {
+        let kind = kind.ok_or_else(|| {
+            anyhow!(
+                "Choose a config file type, or a directory to write every schema to with --out-dir."
+            )
+        })?;
+        println!("{}", serde_json::to_string_pretty(&kind.schema()?)?);
+        return Ok(());
+    };
+    // The schemas are generated, so they're replaced without a backup
+    fs::create_dir_all(dir).map_err(|e| {
+        anyhow!(
+            "Unable to create directory [{}]: {}",
+            dir.to_str().unwrap_or("Invalid file name"),
+            e
+        )
+    })?;
+    for kind in kind.map_or(SchemaKind::ALL.to_vec(), |k| vec![k]) {
+        let f = dir.join(kind.file_name());
+        fs::write(&f, serde_json::to_string_pretty(&kind.schema()?)? + "\n").map_err(|e| {
+            anyhow!(
+                "Unable to write file [{}]: {}",
+                f.to_str().unwrap_or("Invalid file name"),
+                e
+            )
+        })?;
+        Diagnostic::info(format!(
+            "Wrote schema [{}]",
+            f.to_str().unwrap_or("Invalid file name")
+        ))
+        .emit();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config::schema::SchemaKind;
+    use std::fs;
+    use std::path::Path;
+
+    /// The published schemas are generated from the config types, so they have to be
+    /// regenerated with `schema --out-dir schemas` whenever the types change.
+    #[test]
+    fn test_published_schemas_are_up_to_date() {
+        for kind in SchemaKind::ALL {
+            let f = Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("schemas")
+                .join(kind.file_name());
+            let published: serde_json::Value =
+                serde_json::from_str(&fs::read_to_string(&f).unwrap()).unwrap();
+            assert_eq!(
+                published,
+                kind.schema().unwrap(),
+                "{} is out of date",
+                f.display()
+            );
+        }
+    }
+}
@@ -0,0 +1,173 @@
+use crate::transaction::Transaction;
+use serde::Serialize;
+use std::fmt;
+use std::sync::{LazyLock, RwLock};
+
+/// How important a diagnostic is. Levels are ordered from the most to the least important.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    Error,
+    Warning,
+    Info,
+    Debug,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum LogFormat {
+    /// One line of text per diagnostic
+    #[default]
+    Text,
+    /// One JSON object per line
+    Json,
+}
+
+/// A message about the run, along with the account and source row it's about, if any. Prompts
+/// aren't diagnostics: they're written straight to stderr, so that `--quiet` can't hide them.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Diagnostic {
+    pub level: Level,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub account: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line: Option<u64>,
+}
+
+impl Diagnostic {
+    pub fn new(level: Level, message: impl Into<String>) -> Diagnostic {
+        Diagnostic {
+            level,
+            message: message.into(),
+            account: Option::None,
+            file: Option::None,
+            line: Option::None,
+        }
+    }
+
+    pub fn error(message: impl Into<String>) -> Diagnostic {
+        Diagnostic::new(Level::Error, message)
+    }
+
+    pub fn warning(message: impl Into<String>) -> Diagnostic {
+        Diagnostic::new(Level::Warning, message)
+    }
+
+    pub fn info(message: impl Into<String>) -> Diagnostic {
+        Diagnostic::new(Level::Info, message)
+    }
+
+    pub fn debug(message: impl Into<String>) -> Diagnostic {
+        Diagnostic::new(Level::Debug, message)
+    }
+
+    pub fn account(mut self, name: &str) -> Diagnostic {
+        self.account = Option::Some(name.to_owned());
+        self
+    }
+
+    /// The source file and row the transaction was imported from
+    pub fn transaction(mut self, t: &Transaction) -> Diagnostic {
+        self.file = t.source_file().map(str::to_owned);
+        self.line = t.line();
+        self
+    }
+
+    /// Send the diagnostic to the installed sink
+    pub fn emit(self) {
+        SINK.read().unwrap_or_else(|e| e.into_inner()).emit(&self);
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let level = match self.level {
+            Level::Error => "error",
+            Level::Warning => "warning",
+            Level::Info => "info",
+            Level::Debug => "debug",
+        };
+        write!(f, "{}: ", level)?;
+        if let Option::Some(ref account) = self.account {
+            write!(f, "[{}] ", account)?;
+        }
+        match (&self.file, self.line) {
+            (Option::Some(file), Option::Some(line)) => write!(f, "{}:{}: ", file, line)?,
+            (Option::Some(file), Option::None) => write!(f, "{}: ", file)?,
+            _ => {}
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+/// Receives the diagnostics of a run. Library users can install their own with `set_sink`, to
+/// capture or forward them.
+pub trait Sink: Send + Sync {
+    fn emit(&self, diagnostic: &Diagnostic);
+}
+
+/// Writes diagnostics up to a level to stderr, so they never mix with transactions exported to
+/// stdout. This is the default sink.
+#[derive(Debug)]
+pub struct StderrSink {
+    pub max_level: Level,
+    pub format: LogFormat,
+}
+
+impl Default for StderrSink {
+    fn default() -> StderrSink {
+        StderrSink {
+            max_level: Level::Info,
+            format: LogFormat::Text,
+        }
+    }
+}
+
+impl Sink for StderrSink {
+    fn emit(&self, diagnostic: &Diagnostic) {
+        if diagnostic.level > self.max_level {
+            return;
+        }
+        match self.format {
+            LogFormat::Text => eprintln!("{}", diagnostic),
+            LogFormat::Json => match serde_json::to_string(diagnostic) {
+                Ok(json) => eprintln!("{}", json),
+                Err(_) => eprintln!("{}", diagnostic),
+            },
+        }
+    }
+}
+
+static SINK: LazyLock<RwLock<Box<dyn Sink>>> =
+    LazyLock::new(|| RwLock::new(Box::new(StderrSink::default())));
+
+/// Replace the sink that receives every diagnostic
+pub fn set_sink(sink: Box<dyn Sink>) {
+    *SINK.write().unwrap_or_else(|e| e.into_inner()) = sink;
+}
+
+#[cfg(test)]
+mod test {
+    use crate::diagnostics::Diagnostic;
+
+    #[test]
+    fn test_diagnostic_formats() {
+        let mut d = Diagnostic::warning("Dropped duplicate transaction").account("Checking");
+        d.file = Option::Some(String::from("statements/checking.csv"));
+        d.line = Option::Some(4);
+        assert_eq!(
+            d.to_string(),
+            "warning: [Checking] statements/checking.csv:4: Dropped duplicate transaction"
+        );
+        assert_eq!(
+            serde_json::to_string(&d).unwrap(),
+            r#"{"level":"warning","message":"Dropped duplicate transaction","account":"Checking","file":"statements/checking.csv","line":4}"#
+        );
+        assert_eq!(
+            Diagnostic::info("The statement reconciles.").to_string(),
+            "info: The statement reconciles."
+        );
+    }
+}
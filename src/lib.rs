@@ -2,18 +2,18 @@ extern crate chrono;
 extern crate csv;
 #[macro_use]
 extern crate serde_derive;
-extern crate regex;
-extern crate serde;
-extern crate serde_json;
-#[macro_use]
-extern crate text_io;
 extern crate anyhow;
 extern crate clap;
 extern crate currency;
 extern crate num;
+extern crate regex;
+extern crate serde;
+extern crate serde_json;
 extern crate typed_builder;
 
 pub mod config;
+pub mod diagnostics;
 pub mod parser;
+pub mod runner;
 pub mod transaction;
 pub mod util;
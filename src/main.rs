@@ -1,16 +1,26 @@
 extern crate transaction_processor;
 
+use std::process;
+
 use transaction_processor::config::Config;
-use transaction_processor::transaction::transaction_io::TransactionIO;
+use transaction_processor::diagnostics::{self, Diagnostic, Level, StderrSink};
+use transaction_processor::runner::{Failure, Runner};
 
 fn main() {
-    let r = Config::new_and_parse_args().and_then(|c| {
-        let transactions = TransactionIO::import(&c)?;
-        TransactionIO::export(&c, transactions)
-    });
-    if let Err(e) = r {
-        println!();
-        println!("An error occurred, please try again.");
-        println!("Error: {}", e);
+    let args = Config::parse_args();
+    let max_level = if args.quiet {
+        Level::Error
+    } else if args.verbose {
+        Level::Debug
+    } else {
+        Level::Info
+    };
+    diagnostics::set_sink(Box::new(StderrSink {
+        max_level,
+        format: args.log_format,
+    }));
+    if let Err(e) = Runner::run(args) {
+        Diagnostic::error(e.to_string()).emit();
+        process::exit(Failure::of(&e).map_or(1, Failure::exit_code));
     }
 }
@@ -38,6 +38,35 @@ where
     Ok(values)
 }
 
+/// Parse CSV records, along with the line number each record starts on.
+pub fn parse_csv_with_lines_from_reader<T>(
+    r: Box<dyn io::Read>,
+    delimiter: u8,
+) -> anyhow::Result<Vec<(u64, T)>>
+where
+    for<'de> T: serde::Deserialize<'de>,
+{
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .from_reader(r);
+    let headers = reader
+        .headers()
+        .map_err(|e| anyhow!("An error occurred while parsing input: {}", e))?
+        .to_owned();
+    let mut values = Vec::new();
+    for result in reader.records() {
+        let value = result.and_then(|record| {
+            let line = record.position().map(|p| p.line()).unwrap_or_default();
+            record.deserialize(Some(&headers)).map(|v| (line, v))
+        });
+        match value {
+            Ok(value) => values.push(value),
+            Err(e) => return Err(anyhow!("An error occurred while parsing input: {}", e)),
+        }
+    }
+    Ok(values)
+}
+
 pub fn write_csv<T>(values: Vec<T>, has_headers: bool) -> anyhow::Result<()>
 where
     T: serde::Serialize,
@@ -53,7 +82,7 @@ pub fn write_csv_to_writer<T>(
 where
     T: serde::Serialize,
 {
-    let mut writer = create_csv_writer(has_headers, writer);
+    let mut writer = create_csv_writer(has_headers, b',', writer);
     for value in values {
         let r = writer.serialize(value);
         if let Err(e) = r {
@@ -65,10 +94,12 @@ where
 
 pub fn create_csv_writer(
     has_headers: bool,
+    delimiter: u8,
     writer: Box<dyn io::Write>,
 ) -> Writer<Box<dyn io::Write>> {
     csv::WriterBuilder::new()
         .has_headers(has_headers)
+        .delimiter(delimiter)
         .from_writer(writer)
 }
 
@@ -121,3 +152,7 @@ pub fn default_true() -> bool {
 pub fn default_false() -> bool {
     false
 }
+
+pub fn default_fuzzy_threshold() -> f64 {
+    0.8
+}
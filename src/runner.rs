@@ -0,0 +1,422 @@
+use crate::config::manifest::{Manifest, ManifestOutput};
+use crate::config::{
+    Arguments, Command, Config, MatcherKind, MatcherType, PayeeNormalizerConfig, PayeesCommand,
+    file_format, infer, lint, presets, schema,
+};
+use crate::diagnostics::Diagnostic;
+use crate::parser::default_fuzzy_threshold;
+use crate::transaction::Transaction;
+use crate::transaction::explain::Explain;
+use crate::transaction::normalizer_test::NormalizerTest;
+use crate::transaction::reconcile::Reconciliation;
+use crate::transaction::report::Report;
+use crate::transaction::review::Review;
+use crate::transaction::transaction_io::TransactionIO;
+use crate::util;
+use anyhow::anyhow;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// Why a run failed, which decides the exit code. Other errors exit with 1, and invalid
+/// arguments with 2.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Failure {
+    /// A config file can't be read or isn't valid
+    Config,
+    /// A source file can't be read or imported, or doesn't reconcile
+    Input,
+    /// The user cancelled the run
+    Aborted,
+}
+
+impl Failure {
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Failure::Config => 3,
+            Failure::Input => 4,
+            Failure::Aborted => 5,
+        }
+    }
+
+    /// The kind of failure of an error returned by `Runner::run`, if it's known
+    pub fn of(e: &anyhow::Error) -> Option<Failure> {
+        e.downcast_ref::<RunError>().map(|e| e.failure)
+    }
+
+    /// Mark the error as this kind of failure, unless it already has a kind
+    pub(crate) fn wrap(self, error: anyhow::Error) -> anyhow::Error {
+        if Failure::of(&error).is_some() {
+            return error;
+        }
+        anyhow::Error::new(RunError {
+            failure: self,
+            error,
+        })
+    }
+}
+
+#[derive(Debug)]
+struct RunError {
+    failure: Failure,
+    error: anyhow::Error,
+}
+
+impl fmt::Display for RunError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.error)
+    }
+}
+
+impl std::error::Error for RunError {}
+
+/// Imports, checks and exports the transactions of one account, or of every account in a
+/// manifest.
+#[derive(Debug)]
+pub struct Runner {}
+
+impl Runner {
+    /// Run the subcommand, or convert the source files if there isn't one
+    pub fn run(args: Arguments) -> anyhow::Result<()> {
+        match args.command.to_owned().unwrap_or(Command::Convert) {
+            Command::Convert => Runner::convert(args),
+            Command::Validate => lint::validate(args).map_err(|e| Failure::Config.wrap(e)),
+            Command::Lint => lint::lint(args).map_err(|e| Failure::Config.wrap(e)),
+            Command::Payees { command } => match command.unwrap_or(PayeesCommand::List) {
+                PayeesCommand::List => Runner::list_payees(args),
+                PayeesCommand::Test {
+                    matcher,
+                    ref pattern,
+                    ignore_case,
+                    threshold,
+                } => {
+                    let normalizer = candidate_normalizer(matcher, pattern, ignore_case, threshold);
+                    NormalizerTest::check(&normalizer)?;
+                    Runner::test_normalizer(&args, &normalizer)
+                }
+            },
+            Command::Categories => Runner::list_categories(args),
+            Command::Explain { ref filter } => Runner::explain(&args, filter.as_deref()),
+            Command::Report { json_patch } => Runner::report(args, json_patch),
+            Command::Schema { kind, out_dir } => schema::write_schemas(kind, out_dir.as_deref()),
+            Command::Presets { id, sample } => presets::print_presets(id.as_deref(), sample),
+            Command::InferFormat { ref sample } => {
+                Runner::infer_format(sample, args.dst_file.as_ref())
+            }
+            Command::ConvertConfig { ref src, ref dst } => {
+                file_format::convert_config_file(src, dst)
+            }
+        }
+    }
+
+    fn convert(args: Arguments) -> anyhow::Result<()> {
+        if args.manifest.is_some() {
+            let manifest = Manifest::from_args(args).map_err(|e| Failure::Config.wrap(e))?;
+            return Runner::run_manifest(manifest);
+        }
+        let mut c = Config::new(args)
+            .and_then(|c| {
+                c.require_dst_format()?;
+                Ok(c)
+            })
+            .map_err(|e| Failure::Config.wrap(e))?;
+        let transactions = TransactionIO::import(&mut c).map_err(|e| Failure::Input.wrap(e))?;
+        match Runner::review_and_reconcile(&c, transactions)? {
+            Option::Some(transactions) => {
+                print_report(&c, &transactions);
+                TransactionIO::export(&c, transactions)
+            }
+            Option::None => {
+                Err(Failure::Aborted.wrap(anyhow!("Review cancelled, nothing was exported.")))
+            }
+        }
+    }
+
+    /// The config of the account on the command line, or of each account in the manifest
+    fn configs(args: Arguments) -> anyhow::Result<Vec<Config>> {
+        let configs = if args.manifest.is_some() {
+            Manifest::from_args(args)?
+                .account_configs()
+                .into_iter()
+                .map(|(label, c)| {
+                    c.map_err(|e| {
+                        let failure = Failure::of(&e);
+                        let e = anyhow!("Account [{}]: {}", label, e);
+                        match failure {
+                            Option::Some(f) => f.wrap(e),
+                            Option::None => e,
+                        }
+                    })
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?
+        } else {
+            vec![Config::new(args)?]
+        };
+        Ok(configs)
+    }
+
+    fn list_payees(args: Arguments) -> anyhow::Result<()> {
+        for c in Runner::configs(args).map_err(|e| Failure::Config.wrap(e))? {
+            println!("Account [{}]", c.account().name);
+            let mut payees: Vec<_> = c.account().payees.values().collect();
+            payees.sort_by(|a, b| a.id.cmp(&b.id));
+            for p in payees {
+                println!(
+                    "  {:<24}{:<24}[{}]  {} normalizer(s)",
+                    p.id,
+                    p.name,
+                    p.category_ids.as_deref().unwrap_or_default().join(", "),
+                    p.normalizers.len()
+                );
+            }
+        }
+        Ok(())
+    }
+
+    fn test_normalizer(args: &Arguments, normalizer: &PayeeNormalizerConfig) -> anyhow::Result<()> {
+        for c in Runner::configs(args.to_owned()).map_err(|e| Failure::Config.wrap(e))? {
+            println!("Account [{}]", c.account().name);
+            NormalizerTest::run(&c, normalizer).map_err(|e| Failure::Input.wrap(e))?;
+        }
+        Ok(())
+    }
+
+    fn list_categories(args: Arguments) -> anyhow::Result<()> {
+        let configs = Runner::configs(args).map_err(|e| Failure::Config.wrap(e))?;
+        // The accounts share the categories file
+        let Option::Some(first) = configs.first() else {
+            return Ok(());
+        };
+        let mut categories: Vec<_> = first.categories().collect();
+        categories.sort_by(|a, b| a.id.cmp(&b.id));
+        for category in categories {
+            let payees = configs
+                .iter()
+                .flat_map(|c| c.account().payees.values())
+                .filter(|p| p.category_ids.iter().flatten().any(|id| *id == category.id))
+                .count();
+            println!(
+                "{:<24}{:<24}{} payee(s)",
+                category.id, category.name, payees
+            );
+        }
+        Ok(())
+    }
+
+    fn explain(args: &Arguments, filter: Option<&str>) -> anyhow::Result<()> {
+        let configs = Runner::configs(args.to_owned()).map_err(|e| Failure::Config.wrap(e))?;
+        Explain::run(&configs, filter).map_err(|e| Failure::Input.wrap(e))
+    }
+
+    fn report(mut args: Arguments, json_patch: bool) -> anyhow::Result<()> {
+        args.skip_prompts = Option::Some(true);
+        args.prompt_for_payees = Option::Some(false);
+        args.review = false;
+        args.decisions_template = Option::None;
+        let mut configs = Runner::configs(args).map_err(|e| Failure::Config.wrap(e))?;
+        if json_patch && configs.len() > 1 {
+            return Err(Failure::Config.wrap(anyhow!(
+                "A JSON patch applies to a single account config. Choose the account with --account."
+            )));
+        }
+        let mut batches = configs
+            .iter_mut()
+            .map(TransactionIO::import_uncategorized)
+            .collect::<anyhow::Result<Vec<_>>>()
+            .map_err(|e| Failure::Input.wrap(e))?;
+        TransactionIO::detect_transfers(&configs.iter().collect::<Vec<_>>(), &mut batches);
+        for (mut c, transactions) in configs.into_iter().zip(batches) {
+            let transactions = TransactionIO::categorize(&mut c, transactions)
+                .map_err(|e| Failure::Input.wrap(e))?;
+            let report = Report::new(&c, &transactions);
+            if json_patch {
+                println!("{}", serde_json::to_string_pretty(&report.json_patch())?);
+            } else {
+                print!("{}", report);
+            }
+        }
+        Ok(())
+    }
+
+    fn infer_format(sample: &Path, dst_file: Option<&PathBuf>) -> anyhow::Result<()> {
+        let mut contents = Vec::new();
+        util::reader_from_file_name(sample)?
+            .read_to_end(&mut contents)
+            .map_err(|e| anyhow!("An error occurred while reading the sample file: {}", e))?;
+        let id = sample
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or("format")
+            .to_lowercase();
+        let draft = serde_json::to_string_pretty(&infer::infer_format(&id, &contents)?)?;
+        match dst_file {
+            Option::Some(f) => {
+                util::write_file_with_backup(f, &draft)?;
+                Diagnostic::info(format!(
+                    "Wrote draft format [{}] to [{}]. Check the notes before using it.",
+                    id,
+                    f.to_str().unwrap_or("Invalid file name")
+                ))
+                .emit();
+            }
+            Option::None => println!("{}", draft),
+        }
+        Ok(())
+    }
+
+    /// Returns `None` if the review was cancelled.
+    fn review_and_reconcile(
+        c: &Config,
+        transactions: Vec<Transaction>,
+    ) -> anyhow::Result<Option<Vec<Transaction>>> {
+        let transactions = if c.review() {
+            match Review::run(c, transactions)? {
+                Option::Some(t) => t,
+                Option::None => return Ok(Option::None),
+            }
+        } else {
+            transactions
+        };
+        if c.reconcile() {
+            Reconciliation::run(c, &transactions).map_err(|e| Failure::Input.wrap(e))?;
+        }
+        Ok(Option::Some(transactions))
+    }
+
+    /// Process every account in the manifest. An account that fails is reported and left out of
+    /// the export, and the other accounts are still processed.
+    fn run_manifest(manifest: Manifest) -> anyhow::Result<()> {
+        let output = manifest.output()?;
+        let mut failed: Vec<(String, Option<Failure>)> = Vec::new();
+        let mut report = |label: &str, e: anyhow::Error| {
+            Diagnostic::error(format!("Account [{}] failed: {}", label, e)).emit();
+            failed.push((label.to_owned(), Failure::of(&e)));
+        };
+
+        let mut labels = Vec::new();
+        let mut configs = Vec::new();
+        let mut batches = Vec::new();
+        for (label, config) in manifest.account_configs() {
+            let imported = config
+                .and_then(|c| {
+                    c.require_dst_format()?;
+                    Ok(c)
+                })
+                .map_err(|e| Failure::Config.wrap(e))
+                .and_then(|mut c| {
+                    let transactions = TransactionIO::import_uncategorized(&mut c)
+                        .map_err(|e| Failure::Input.wrap(e))?;
+                    Ok((c, transactions))
+                });
+            match imported {
+                Ok((c, transactions)) => {
+                    labels.push(label);
+                    configs.push(c);
+                    batches.push(transactions);
+                }
+                Err(e) => report(&label, e),
+            }
+        }
+
+        TransactionIO::detect_transfers(&configs.iter().collect::<Vec<_>>(), &mut batches);
+
+        let mut exports = Vec::new();
+        for ((label, mut c), transactions) in labels.into_iter().zip(configs).zip(batches) {
+            let r = TransactionIO::categorize(&mut c, transactions)
+                .and_then(|t| Runner::review_and_reconcile(&c, t));
+            match r {
+                Ok(Option::Some(transactions)) => {
+                    print_report(&c, &transactions);
+                    exports.push((label, c, transactions))
+                }
+                Ok(Option::None) => Diagnostic::warning(format!(
+                    "Review of account [{}] cancelled, it was not exported.",
+                    label
+                ))
+                .emit(),
+                Err(e) => report(&label, e),
+            }
+        }
+
+        let exported = match output {
+            ManifestOutput::Merged(dst_file) => {
+                let batches = exports.iter().map(|(_, c, t)| (c, t.to_owned())).collect();
+                TransactionIO::export_merged(batches, dst_file.as_ref())
+            }
+            ManifestOutput::PerAccount => {
+                for (label, c, transactions) in exports {
+                    if let Err(e) = TransactionIO::export(&c, transactions) {
+                        report(&label, e);
+                    }
+                }
+                Ok(())
+            }
+        };
+
+        let summary = (!failed.is_empty()).then(|| {
+            anyhow!(
+                "{} account(s) failed: {}",
+                failed.len(),
+                failed
+                    .iter()
+                    .map(|(label, _)| label.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+        });
+        if let Err(e) = exported {
+            // The accounts that failed before the merged export are still summarized
+            if let Option::Some(summary) = summary {
+                Diagnostic::error(summary.to_string()).emit();
+            }
+            return Err(e);
+        }
+        let (Option::Some(e), Option::Some((_, failure))) = (summary, failed.first()) else {
+            return Ok(());
+        };
+        // The exit code tells what went wrong only if every account failed the same way
+        match failure {
+            Option::Some(f) if failed.iter().all(|(_, other)| other == failure) => Err(f.wrap(e)),
+            _ => Err(e),
+        }
+    }
+}
+
+/// Report what wasn't normalized or categorized as diagnostics, so it doesn't mix with
+/// transactions exported to stdout
+fn print_report(c: &Config, transactions: &[Transaction]) {
+    for d in Report::new(c, transactions).diagnostics() {
+        d.emit();
+    }
+}
+
+/// The normalizer given on the command line, with the defaults of the account config
+fn candidate_normalizer(
+    matcher: MatcherKind,
+    pattern: &str,
+    ignore_case: Option<bool>,
+    threshold: Option<f64>,
+) -> PayeeNormalizerConfig {
+    let pattern = pattern.to_owned();
+    let normalizer_type = match matcher {
+        MatcherKind::Exact => MatcherType::Exact {
+            exact_match_string: pattern,
+        },
+        MatcherKind::Contains => MatcherType::Contains {
+            contains_string: pattern,
+        },
+        MatcherKind::Regex => MatcherType::Regex {
+            regex_string: pattern,
+        },
+        MatcherKind::Fuzzy => MatcherType::Fuzzy {
+            fuzzy_match_string: pattern,
+            threshold: threshold.unwrap_or_else(default_fuzzy_threshold),
+            token_weights: HashMap::new(),
+        },
+    };
+    PayeeNormalizerConfig {
+        normalizer_type,
+        ignore_case: ignore_case.unwrap_or(true),
+    }
+}
@@ -0,0 +1,366 @@
+use crate::config::{AmountFormat, CategoryRule, Config, FormatConfigFile, MatcherType};
+use crate::transaction::payee::{CategorySource, PayeeNormalizer};
+use crate::transaction::transaction_io::{SourceRow, TransactionIO};
+use crate::transaction::{ChangeType, Transaction, TransactionStatus, TransactionType};
+use crate::util::currency_to_string_without_delim;
+use std::collections::HashMap;
+
+/// Shows how each imported transaction was read, which payee it was normalized to and how its
+/// category was chosen. Nothing is prompted for or saved.
+#[derive(Debug)]
+pub struct Explain {}
+
+impl Explain {
+    /// Print the explanation of each transaction of every account, or of the transactions with a
+    /// raw value that contains the filter, ignoring case.
+    pub fn run(configs: &[Config], filter: Option<&str>) -> anyhow::Result<()> {
+        let filter = filter.map(str::to_lowercase);
+        let rows = configs
+            .iter()
+            .map(TransactionIO::import_rows)
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let mut batches = transfer_batches(configs, &rows);
+        TransactionIO::detect_transfers(&configs.iter().collect::<Vec<_>>(), &mut batches);
+
+        for ((config, rows), batch) in configs.iter().zip(rows).zip(batches) {
+            println!("Account [{}]", config.account().name);
+            let mut explained = 0;
+            for (mut row, t) in rows.into_iter().zip(batch) {
+                if let Option::Some(ref filter) = filter {
+                    if !row
+                        .values
+                        .values()
+                        .any(|v| v.to_lowercase().contains(filter))
+                    {
+                        continue;
+                    }
+                }
+                if explained > 0 {
+                    println!();
+                }
+                row.transaction.transfer = t.transfer;
+                for line in explain(config, row) {
+                    println!("{}", line);
+                }
+                explained += 1;
+            }
+            if explained == 0 {
+                println!("No transactions to explain.");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The normalized transactions of each account, in row order, to match transfers with. Ignored
+/// pending transactions are marked as removed so that they aren't paired.
+fn transfer_batches(configs: &[Config], rows: &[Vec<SourceRow>]) -> Vec<Vec<Transaction>> {
+    configs
+        .iter()
+        .zip(rows)
+        .map(|(config, rows)| {
+            rows.iter()
+                .map(|row| {
+                    let mut t = row.transaction.clone();
+                    t.normalize_payee(config);
+                    if config.ignore_pending() && t.status == TransactionStatus::Pending {
+                        t.change = ChangeType::Remove;
+                    }
+                    t
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn explain(config: &Config, row: SourceRow) -> Vec<String> {
+    let SourceRow {
+        mut transaction,
+        format,
+        values,
+    } = row;
+    let mut lines = vec![format!(
+        "{}, format [{}]",
+        transaction.source_line(),
+        format.id
+    )];
+
+    lines.push(String::from("  Raw values:"));
+    let mut fields: Vec<&String> = format
+        .field_order
+        .iter()
+        .filter(|f| values.contains_key(*f))
+        .collect();
+    let mut others: Vec<&String> = values.keys().filter(|f| !fields.contains(f)).collect();
+    others.sort();
+    fields.extend(others);
+    for field in fields {
+        lines.push(format!("    {}: [{}]", field, values[field]));
+    }
+    lines.push(format!(
+        "  Parsed: [date: {}], [raw payee: {}], [status: {}], [memo: {}]",
+        transaction.date,
+        transaction.raw_payee_name,
+        match transaction.status {
+            TransactionStatus::Pending => "pending",
+            TransactionStatus::Cleared => "cleared",
+        },
+        transaction.memo.as_deref().unwrap_or_default()
+    ));
+    if config.ignore_pending() && transaction.status == TransactionStatus::Pending {
+        lines.push(String::from(
+            "  Ignored: the transaction is pending and pending transactions are ignored.",
+        ));
+    }
+    lines.push(format!(
+        "  Amount: {}",
+        explain_amount(format, &values, &transaction)
+    ));
+
+    let (payee_lines, payee_id) = explain_payee(config, &transaction.raw_payee_name);
+    lines.extend(payee_lines);
+    transaction.normalized_payee_name = payee_id
+        .as_ref()
+        .and_then(|p| config.account().payees.get(p))
+        .map(|p| p.name.to_owned());
+    transaction.normalized_payee_id = payee_id;
+    lines.push(format!(
+        "  Category: {}",
+        explain_category(config, &transaction)
+    ));
+    lines
+}
+
+fn explain_amount(
+    f: &FormatConfigFile,
+    values: &HashMap<String, String>,
+    t: &Transaction,
+) -> String {
+    let value = |field: &String| values.get(field).map_or("missing", String::as_str);
+    let kind = match t.transaction_type {
+        TransactionType::Debit => "debit",
+        TransactionType::Credit => "credit",
+    };
+    let amount = currency_to_string_without_delim(&t.amount);
+    match f.amount_config.format {
+        AmountFormat::SingleAmountField(ref c) => format!(
+            "field [{}] is [{}]. With debitIsNegative {}, {} amounts are debits, so this is a {} of {}.",
+            c.field_name,
+            value(&c.field_name),
+            c.debit_is_negative,
+            if c.debit_is_negative {
+                "negative"
+            } else {
+                "positive"
+            },
+            kind,
+            amount
+        ),
+        AmountFormat::SeparateDebitCreditFields(ref c) => match t.transaction_type {
+            TransactionType::Debit => format!(
+                "debit field [{}] is [{}], so this is a debit of {}.",
+                c.debit_field,
+                value(&c.debit_field),
+                amount
+            ),
+            TransactionType::Credit => format!(
+                "debit field [{}] is empty and credit field [{}] is [{}], so this is a credit of {}.",
+                c.debit_field,
+                c.credit_field,
+                value(&c.credit_field),
+                amount
+            ),
+        },
+        AmountFormat::TransactionTypeAndAmountFields(ref c) => format!(
+            "amount field [{}] is [{}] and type field [{}] is [{}], the {} string, so this is a {} of {}.",
+            c.amount_field,
+            value(&c.amount_field),
+            c.transaction_type_field,
+            value(&c.transaction_type_field),
+            kind,
+            kind,
+            amount
+        ),
+    }
+}
+
+/// Lists the normalizers that match the raw payee, starting with the one that was used. Returns
+/// the normalized payee ID.
+fn explain_payee(config: &Config, raw_payee: &str) -> (Vec<String>, Option<String>) {
+    let matches = PayeeNormalizer::matching_normalizers(config, raw_payee);
+    let Option::Some((payee_id, score)) = PayeeNormalizer::match_payee(config, raw_payee) else {
+        return (
+            vec![String::from(
+                "  Payee: not normalized, no normalizer matches the raw payee.",
+            )],
+            Option::None,
+        );
+    };
+    let is_fuzzy = |id: &String, i: usize| {
+        matches!(
+            config.account().payees[id].normalizers[i].normalizer_type,
+            MatcherType::Fuzzy { .. }
+        )
+    };
+    // The first normalizer of the payee that could have decided the match
+    let winner = matches
+        .iter()
+        .position(|(id, i, s)| {
+            *id == payee_id
+                && match score {
+                    Option::Some(score) => is_fuzzy(id, *i) && *s == score,
+                    Option::None => !is_fuzzy(id, *i),
+                }
+        })
+        .unwrap_or_default();
+
+    let describe = |(id, i, s): &(&String, usize, f64)| {
+        let normalizer = &config.account().payees[*id].normalizers[*i];
+        let score = if is_fuzzy(id, *i) {
+            format!(", score {:.2}", s)
+        } else {
+            String::new()
+        };
+        format!(
+            "[{}] by normalizer {}: {}{}",
+            id,
+            i,
+            PayeeNormalizer::describe(normalizer),
+            score
+        )
+    };
+
+    let mut lines = vec![format!("  Payee: {}", describe(&matches[winner]))];
+    for (_, m) in matches.iter().enumerate().filter(|(j, _)| *j != winner) {
+        lines.push(format!("    Also matched: {}", describe(m)));
+    }
+    let is_ambiguous = matches
+        .iter()
+        .any(|(id, i, _)| *id != payee_id && !is_fuzzy(id, *i));
+    if score.is_none() && is_ambiguous {
+        lines.push(String::from(
+            "    Payees are checked in no particular order, so another run may pick another payee that matched.",
+        ));
+    } else if score.is_some() {
+        lines.push(String::from(
+            "    Fuzzy matches are only used when no other normalizer matches; the highest score wins.",
+        ));
+    }
+    (lines, Option::Some(payee_id.to_owned()))
+}
+
+/// How `PayeeNormalizer::category_for_transaction` would choose the category, without prompting
+fn explain_category(config: &Config, t: &Transaction) -> String {
+    let name = |id: &str| match config.category(id) {
+        Option::Some(c) => format!("[{}] ({})", c.name, id),
+        Option::None => format!("[{}] (unknown category)", id),
+    };
+    match PayeeNormalizer::category_source(config, t) {
+        CategorySource::Transfer(account) => format!(
+            "none, the transaction is a transfer with account [{}], so it isn't categorized.",
+            account
+        ),
+        CategorySource::Rule(i, rule) => format!(
+            "{} from category rule {} of the overrides file, which matches {}.",
+            name(&rule.category_id),
+            i,
+            rule_conditions(rule)
+        ),
+        CategorySource::NoPayee => {
+            String::from("none, the payee isn't normalized and no category rule matches.")
+        }
+        CategorySource::NoCategories(payee) => format!(
+            "none, payee [{}] has no categories and no category rule matches.",
+            payee.id
+        ),
+        CategorySource::OnlyCategory(payee, id) => {
+            format!("{}, the only category of payee [{}].", name(id), payee.id)
+        }
+        CategorySource::Decision(fingerprint, Option::Some(id)) => format!(
+            "{} from the decision for fingerprint [{}].",
+            name(id),
+            fingerprint
+        ),
+        CategorySource::Decision(fingerprint, Option::None) => format!(
+            "none, the decision for fingerprint [{}] has no category.",
+            fingerprint
+        ),
+        CategorySource::Undecided(payee, fingerprint) => {
+            let outcome = if config.write_decisions_template() {
+                "it is written to the decisions template"
+            } else if config.prompts_disabled() {
+                "it is left uncategorized because prompts are skipped"
+            } else {
+                "the category is prompted for, or left uncategorized when exporting to stdout"
+            };
+            format!(
+                "payee [{}] has several categories [{}] and no decision matches fingerprint [{}], so {}.",
+                payee.id,
+                payee.category_ids.as_deref().unwrap_or_default().join(", "),
+                fingerprint,
+                outcome
+            )
+        }
+    }
+}
+
+fn rule_conditions(rule: &CategoryRule) -> String {
+    let mut conditions = Vec::new();
+    if let Option::Some(ref payee_id) = rule.payee_id {
+        conditions.push(format!("payee [{}]", payee_id));
+    }
+    if let Option::Some(ref raw_payee) = rule.raw_payee {
+        conditions.push(format!("raw payee [{}]", raw_payee));
+    }
+    if let Option::Some(ref min) = rule.min_amount {
+        conditions.push(format!("amounts of at least {}", min));
+    }
+    if let Option::Some(ref max) = rule.max_amount {
+        conditions.push(format!("amounts of at most {}", max));
+    }
+    conditions.join(", ")
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config::FormatConfigFile;
+    use crate::transaction::explain::explain_amount;
+    use crate::transaction::test::transaction;
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    fn format(amount_format: serde_json::Value) -> FormatConfigFile {
+        serde_json::from_value(json!({
+            "id": "bank", "name": "Bank", "dataFormat": "csv",
+            "fieldOrder": ["Date", "Payee", "Amount"],
+            "dateTimeConfig": {"dateField": "Date", "dateFormat": "%Y-%m-%d"},
+            "payeeConfig": {"fieldName": "Payee"},
+            "amountConfig": {"format": amount_format}
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_explain_amount() {
+        let f = format(
+            json!({"type": "SingleAmountField", "fieldName": "Amount", "debitIsNegative": true}),
+        );
+        let values = HashMap::from([(String::from("Amount"), String::from("-5.25"))]);
+        assert_eq!(
+            explain_amount(&f, &values, &transaction(2, "STARBUCKS", "-5.25")),
+            "field [Amount] is [-5.25]. With debitIsNegative true, negative amounts are debits, so this is a debit of 5.25."
+        );
+
+        let f = format(
+            json!({"type": "SeparateDebitCreditFields", "debitField": "Out", "creditField": "In"}),
+        );
+        let values = HashMap::from([
+            (String::from("Out"), String::new()),
+            (String::from("In"), String::from("5.25")),
+        ]);
+        assert_eq!(
+            explain_amount(&f, &values, &transaction(2, "STARBUCKS", "5.25")),
+            "debit field [Out] is empty and credit field [In] is [5.25], so this is a credit of 5.25."
+        );
+    }
+}
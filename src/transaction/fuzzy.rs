@@ -0,0 +1,188 @@
+use std::collections::HashMap;
+
+// Two letter US state and territory codes. Raw payee strings frequently end with the state the
+// transaction happened in, which is noise when comparing payees.
+const STATE_CODES: &[&str] = &[
+    "al", "ak", "az", "ar", "ca", "co", "ct", "de", "dc", "fl", "ga", "hi", "id", "il", "in", "ia",
+    "ks", "ky", "la", "me", "md", "ma", "mi", "mn", "ms", "mo", "mt", "ne", "nv", "nh", "nj", "nm",
+    "ny", "nc", "nd", "oh", "ok", "or", "pa", "ri", "sc", "sd", "tn", "tx", "ut", "vt", "va", "wa",
+    "wv", "wi", "wy", "pr", "vi", "gu",
+];
+
+// Words that only introduce a store number, e.g. "STORE 998" or "NO. 12"
+const STORE_NUMBER_MARKERS: &[&str] = &["store", "str", "no", "num", "nbr", "loc"];
+
+// The shortest trailing token that will be treated as a possibly truncated word.
+const MIN_TRUNCATED_TOKEN_LEN: usize = 4;
+
+/// Split a payee string into lowercase tokens, removing the parts that commonly vary between
+/// transactions from the same payee: digits, store numbers and trailing state codes.
+pub fn normalize_tokens(s: &str) -> Vec<String> {
+    let raw_tokens: Vec<String> = s
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|t| !t.is_empty())
+        .map(|t| t.to_lowercase())
+        .collect();
+
+    let mut tokens = Vec::new();
+    for (i, token) in raw_tokens.iter().enumerate() {
+        if token.chars().any(|c| c.is_ascii_digit()) {
+            continue;
+        }
+        let next_is_number = raw_tokens
+            .get(i + 1)
+            .map(|t| t.chars().all(|c| c.is_ascii_digit()))
+            .unwrap_or(false);
+        if next_is_number && STORE_NUMBER_MARKERS.contains(&token.as_str()) {
+            continue;
+        }
+        tokens.push(token.to_owned());
+    }
+
+    // Only strip a state code from the end, and never strip the only remaining token
+    if tokens.len() > 1 {
+        if let Option::Some(last) = tokens.last() {
+            if STATE_CODES.contains(&last.as_str()) {
+                tokens.pop();
+            }
+        }
+    }
+    tokens
+}
+
+/// Compute how well the raw payee string matches the pattern, between 0 and 1.
+///
+/// The score is the weighted fraction of the pattern's tokens that are found in the raw string.
+/// Extra tokens in the raw string (e.g. a city name) don't reduce the score. Tokens are compared
+/// using their edit distance, and the last token of the raw string may be a truncated version of
+/// a pattern token. Pattern tokens without an entry in `token_weights` have a weight of 1.
+pub fn similarity(pattern: &str, raw: &str, token_weights: &HashMap<String, f64>) -> f64 {
+    let pattern_tokens = normalize_tokens(pattern);
+    let raw_tokens = normalize_tokens(raw);
+    if pattern_tokens.is_empty() || raw_tokens.is_empty() {
+        return 0.0;
+    }
+
+    let mut total_weight = 0.0;
+    let mut matched_weight = 0.0;
+    for pattern_token in &pattern_tokens {
+        let weight = token_weight(token_weights, pattern_token);
+        total_weight += weight;
+        let best = raw_tokens
+            .iter()
+            .enumerate()
+            .map(|(i, raw_token)| {
+                token_similarity(pattern_token, raw_token, i == raw_tokens.len() - 1)
+            })
+            .fold(0.0, f64::max);
+        matched_weight += weight * best;
+    }
+
+    if total_weight <= 0.0 {
+        return 0.0;
+    }
+    matched_weight / total_weight
+}
+
+fn token_weight(token_weights: &HashMap<String, f64>, token: &str) -> f64 {
+    token_weights
+        .iter()
+        .find(|(k, _)| k.to_lowercase() == token)
+        .map(|(_, w)| *w)
+        .unwrap_or(1.0)
+}
+
+fn token_similarity(pattern_token: &str, raw_token: &str, raw_token_is_last: bool) -> f64 {
+    if pattern_token == raw_token {
+        return 1.0;
+    }
+    // Banks cut off long payee names, so the last word may be incomplete
+    if raw_token_is_last
+        && raw_token.chars().count() >= MIN_TRUNCATED_TOKEN_LEN
+        && pattern_token.starts_with(raw_token)
+    {
+        return 1.0;
+    }
+    let max_len = pattern_token.chars().count().max(raw_token.chars().count());
+    if max_len == 0 {
+        return 0.0;
+    }
+    1.0 - (levenshtein(pattern_token, raw_token) as f64 / max_len as f64)
+}
+
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    let mut cur = vec![0; b.len() + 1];
+    for (i, ca) in a.chars().enumerate() {
+        cur[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == *cb { 0 } else { 1 };
+            cur[j + 1] = (prev[j + 1] + 1).min(cur[j] + 1).min(prev[j] + cost);
+        }
+        std::mem::swap(&mut prev, &mut cur);
+    }
+    prev[b.len()]
+}
+
+#[cfg(test)]
+mod test {
+    use crate::transaction::fuzzy::{levenshtein, normalize_tokens, similarity};
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_normalize_tokens() {
+        assert_eq!(
+            normalize_tokens("STARBUCKS #12345 SEATTLE WA"),
+            vec!["starbucks", "seattle"]
+        );
+        assert_eq!(normalize_tokens("STARBUCKS STORE 998"), vec!["starbucks"]);
+        assert_eq!(
+            normalize_tokens("SQ *BLUE BOTTLE"),
+            vec!["sq", "blue", "bottle"]
+        );
+        // A lone state code is kept
+        assert_eq!(normalize_tokens("WA"), vec!["wa"]);
+    }
+
+    #[test]
+    fn test_levenshtein() {
+        assert_eq!(levenshtein("kitten", "sitting"), 3);
+        assert_eq!(levenshtein("", "abc"), 3);
+        assert_eq!(levenshtein("abc", "abc"), 0);
+    }
+
+    #[test]
+    fn test_similarity() {
+        let weights = HashMap::new();
+        assert_eq!(
+            similarity("Starbucks", "STARBUCKS #12345 SEATTLE WA", &weights),
+            1.0
+        );
+        assert_eq!(
+            similarity("Starbucks", "STARBUCKS STORE 998", &weights),
+            1.0
+        );
+        // Truncated last token
+        assert_eq!(
+            similarity("Whole Foods Market", "WHOLE FOODS MARK", &weights),
+            1.0
+        );
+        // Truncation is only allowed at the end of the raw string
+        assert!(similarity("Starbucks", "STAR MARKET", &weights) < 0.8);
+        assert_eq!(similarity("Starbucks", "", &weights), 0.0);
+    }
+
+    #[test]
+    fn test_similarity_token_weights() {
+        let mut weights = HashMap::new();
+        weights.insert(String::from("market"), 0.0);
+        assert_eq!(
+            similarity("Whole Foods Market", "WHOLE FOODS", &weights),
+            1.0
+        );
+
+        let weights = HashMap::new();
+        assert!(similarity("Whole Foods Market", "WHOLE FOODS", &weights) < 1.0);
+    }
+}
@@ -1,16 +1,24 @@
 use crate::config::Config;
+use crate::diagnostics::Diagnostic;
 use crate::transaction::payee::PayeeNormalizer;
-use crate::util::currency_to_string_without_delim;
+use crate::util::{currency_to_string_without_delim, stable_hash};
 use chrono::prelude::*;
 use currency::Currency;
 use num::Signed;
 use std::ops::Neg;
 use typed_builder::TypedBuilder;
 
+pub mod explain;
+pub mod fuzzy;
+pub mod normalizer_test;
 pub mod payee;
+pub mod payee_creator;
+pub mod reconcile;
+pub mod report;
+pub mod review;
 pub mod transaction_io;
 
-#[derive(Debug, TypedBuilder)]
+#[derive(Debug, Clone, TypedBuilder)]
 pub struct Transaction {
     date: NaiveDateTime,
     #[builder(setter(transform = |value: String| InputCleaner::clean(value) ))]
@@ -28,24 +36,88 @@ pub struct Transaction {
     status: TransactionStatus,
     #[builder(default, setter(transform = |value: Option<String>| value.map(InputCleaner::clean )))]
     memo: Option<String>,
+    // The source file the transaction was imported from
+    #[builder(default)]
+    source_file: Option<String>,
+    // The line of the source file the transaction was imported from
+    #[builder(default)]
+    line: Option<u64>,
+    // Counts earlier transactions in the same import with the same fingerprint
+    #[builder(default)]
+    occurrence: usize,
+    // How the transaction changes what a previous run exported
+    #[builder(default)]
+    change: ChangeType,
+    // The state key of the previously exported pending transaction this one replaces
+    #[builder(default)]
+    supersedes: Option<String>,
+    // The state key of the imported transaction this one was split from
+    #[builder(default)]
+    split_from: Option<String>,
+    // The balance of the account after the transaction, imported from the statement and
+    // recomputed just before exporting
+    #[builder(default)]
+    balance: Option<Currency>,
+    // Set if the transaction is one side of a transfer between two accounts in the same run
+    #[builder(default)]
+    transfer: Option<Transfer>,
+}
+
+/// Links a transaction to the opposite transaction in another account
+#[derive(Debug, Clone)]
+pub struct Transfer {
+    account_name: String,
+    // Shared by both sides of the transfer
+    id: String,
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq)]
 enum TransactionType {
     Debit,
     Credit,
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq)]
 enum TransactionStatus {
     Pending,
     Cleared,
 }
 
+#[derive(Debug, Clone, Eq, PartialEq, Default)]
+enum ChangeType {
+    /// A new transaction
+    #[default]
+    Add,
+    /// Replaces a pending transaction exported by a previous run
+    Update,
+    /// Removes a pending transaction exported by a previous run
+    Remove,
+}
+
 impl Transaction {
     pub fn normalize_payee(&mut self, config: &Config) {
-        self.normalized_payee_id =
-            PayeeNormalizer::normalized_payee_id(config, &self.raw_payee_name);
+        let matched = PayeeNormalizer::match_payee(config, &self.raw_payee_name);
+        // Fuzzy matches are shown by default, so they can be promoted to exact normalizers
+        let diagnostic = match matched {
+            Option::Some((payee_id, Option::Some(score))) => {
+                Option::Some(Diagnostic::info(format!(
+                    "Payee was fuzzy matched: [raw payee: {}], [payee: {}], [score: {:.2}]",
+                    self.raw_payee_name, payee_id, score
+                )))
+            }
+            Option::Some(_) => Option::None,
+            Option::None => Option::Some(Diagnostic::debug(format!(
+                "Payee was not normalized: {}",
+                self.raw_payee_name
+            ))),
+        };
+        if let Option::Some(diagnostic) = diagnostic {
+            diagnostic
+                .account(&config.account().name)
+                .transaction(self)
+                .emit();
+        }
+        self.normalized_payee_id = matched.map(|(payee_id, _)| payee_id.to_owned());
         self.normalized_payee_name = self
             .normalized_payee_id
             .as_ref()
@@ -53,16 +125,86 @@ impl Transaction {
             .map(|x| x.name.to_owned());
     }
 
-    pub fn categorize(&mut self, config: &Config) {
-        self.category = PayeeNormalizer::category_for_transaction(config, self);
+    pub fn categorize(&mut self, config: &mut Config) -> anyhow::Result<()> {
+        self.category = PayeeNormalizer::category_for_transaction(config, self)?;
         if self.category.is_none() {
-            println!(
+            Diagnostic::debug(format!(
                 "Transaction was not categorized: [payee: {}], [amount: {}], [type: {:?}], [date: {}]",
                 self.payee(),
                 currency_to_string_without_delim(&self.amount),
                 self.transaction_type,
                 self.date
-            );
+            ))
+            .account(&config.account().name)
+            .transaction(self)
+            .emit();
+        }
+        Ok(())
+    }
+
+    /// A stable identifier for the transaction, based on its date, amount, raw payee and memo.
+    pub fn fingerprint(&self) -> String {
+        let s = format!(
+            "{}|{}|{}|{}",
+            self.date.format("%Y-%m-%d %H:%M:%S"),
+            currency_to_string_without_delim(&self.signed_amount()),
+            self.raw_payee_name,
+            self.memo.as_deref().unwrap_or_default()
+        );
+        format!("{:016x}", stable_hash(&s))
+    }
+
+    /// Identifies the transaction across runs. Unlike the fingerprint, this is different for
+    /// identical transactions in the same import.
+    pub fn state_key(&self) -> String {
+        format!("{}#{}", self.fingerprint(), self.occurrence)
+    }
+
+    /// The state key of the transaction as it was imported, before it was split
+    pub fn imported_key(&self) -> String {
+        match self.split_from {
+            Option::Some(ref key) => key.to_owned(),
+            Option::None => self.state_key(),
+        }
+    }
+
+    /// Identifies the exported row the transaction adds, updates or removes
+    pub fn export_id(&self) -> String {
+        match self.supersedes {
+            Option::Some(ref key) => key.to_owned(),
+            Option::None => self.state_key(),
+        }
+    }
+
+    /// Where the transaction was imported from, for messages
+    pub fn source_line(&self) -> String {
+        let line = match self.line {
+            Option::Some(line) => format!("line {}", line),
+            Option::None => String::from("unknown line"),
+        };
+        match self.source_file {
+            Option::Some(ref f) => format!("{} {}", f, line),
+            Option::None => line,
+        }
+    }
+
+    /// Describes the transaction as imported, for messages
+    pub fn describe(&self) -> String {
+        format!(
+            "[{}], [raw payee: {}], [amount: {}], [date: {}], [memo: {:?}]",
+            self.source_line(),
+            self.raw_payee_name,
+            currency_to_string_without_delim(&self.signed_amount()),
+            self.date,
+            self.memo
+        )
+    }
+
+    /// The amount of the transaction, negative for debits
+    fn signed_amount(&self) -> Currency {
+        match self.transaction_type {
+            TransactionType::Debit => self.amount.to_owned().neg(),
+            TransactionType::Credit => self.amount.to_owned(),
         }
     }
 
@@ -70,6 +212,16 @@ impl Transaction {
         &self.date
     }
 
+    /// The source file the transaction was imported from
+    pub fn source_file(&self) -> Option<&str> {
+        self.source_file.as_deref()
+    }
+
+    /// The line of the source file the transaction was imported from
+    pub fn line(&self) -> Option<u64> {
+        self.line
+    }
+
     // Get the name of the payee for this transaction. Either the raw payee name, or the
     // normalized name if it has been normalized.
     pub fn payee(&self) -> &str {
@@ -101,3 +253,71 @@ impl Clean<Option<String>> for InputCleaner {
         s.map(Self::clean)
     }
 }
+
+#[cfg(test)]
+pub mod test {
+    use crate::transaction::{Transaction, TransactionStatus, TransactionType};
+    use chrono::NaiveDate;
+    use currency::Currency;
+
+    /// A cleared transaction on a day of January 2024. Negative amounts are debits.
+    pub fn transaction(day: u32, payee: &str, amount: &str) -> Transaction {
+        let amount = Currency::from_str(amount).unwrap();
+        let transaction_type = if amount.value() < &0.into() {
+            TransactionType::Debit
+        } else {
+            TransactionType::Credit
+        };
+        Transaction::builder()
+            .date(
+                NaiveDate::from_ymd_opt(2024, 1, day)
+                    .unwrap()
+                    .and_hms_opt(0, 0, 0)
+                    .unwrap(),
+            )
+            .raw_payee_name(String::from(payee))
+            .transaction_type(transaction_type)
+            .amount(amount)
+            .status(TransactionStatus::Cleared)
+            .build()
+    }
+
+    /// A pending transaction on a day of January 2024
+    pub fn pending(day: u32, payee: &str, amount: &str) -> Transaction {
+        Transaction {
+            status: TransactionStatus::Pending,
+            ..transaction(day, payee, amount)
+        }
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        let t = transaction(2, "STARBUCKS", "-4.10");
+        // Stable across runs, so decisions and previous exports keep matching
+        assert_eq!(t.fingerprint(), "f575a09ff8c68230");
+        assert_eq!(
+            t.fingerprint(),
+            transaction(2, "STARBUCKS", "-4.10").fingerprint()
+        );
+
+        assert_ne!(
+            t.fingerprint(),
+            transaction(2, "STARBUCKS", "-4.11").fingerprint()
+        );
+        assert_ne!(
+            t.fingerprint(),
+            transaction(2, "STARBUCKS", "4.10").fingerprint()
+        );
+        assert_ne!(
+            t.fingerprint(),
+            transaction(3, "STARBUCKS", "-4.10").fingerprint()
+        );
+
+        // A second identical transaction in the same import has the same fingerprint, but its
+        // own state key
+        let mut second = transaction(2, "STARBUCKS", "-4.10");
+        second.occurrence = 1;
+        assert_eq!(t.fingerprint(), second.fingerprint());
+        assert_ne!(t.state_key(), second.state_key());
+    }
+}
@@ -0,0 +1,190 @@
+use crate::config::{Config, MatcherType, PayeeNormalizerConfig, validate_normalizer};
+use crate::transaction::payee::{Matcher, PayeeNormalizer};
+use crate::transaction::transaction_io::TransactionIO;
+use anyhow::anyhow;
+use std::collections::HashMap;
+
+/// Tries a candidate normalizer on the source files before it's added to a payee, to show which
+/// rows it would cover.
+#[derive(Debug)]
+pub struct NormalizerTest {}
+
+/// A raw payee the candidate normalizer matches
+#[derive(Debug)]
+struct Match<'a> {
+    raw_payee: &'a str,
+    rows: usize,
+    score: f64,
+    // The payee that currently normalizes the raw payee
+    claimed_by: Option<&'a String>,
+}
+
+impl NormalizerTest {
+    /// Print every raw payee the normalizer matches, with the number of rows and the payee that
+    /// currently claims it.
+    pub fn run(config: &Config, normalizer: &PayeeNormalizerConfig) -> anyhow::Result<()> {
+        let matcher = Matcher::new(normalizer)?;
+        let rows = TransactionIO::import_rows(config)?;
+        let matches = matches(
+            &matcher,
+            rows.iter().map(|r| r.transaction.raw_payee_name.as_str()),
+            |raw_payee| PayeeNormalizer::match_payee(config, raw_payee).map(|(id, _)| id),
+        );
+
+        println!("{}", PayeeNormalizer::describe(normalizer));
+        let is_fuzzy = matches!(normalizer.normalizer_type, MatcherType::Fuzzy { .. });
+        for m in &matches {
+            let claimed_by = match m.claimed_by {
+                Option::Some(payee_id) => format!("claimed by [{}]", payee_id),
+                Option::None => String::from("not normalized"),
+            };
+            let score = if is_fuzzy {
+                format!(" (score {:.2})", m.score)
+            } else {
+                String::new()
+            };
+            println!(
+                "  {:>4}  {:<40}{}{}",
+                m.rows, m.raw_payee, claimed_by, score
+            );
+        }
+        let (new_rows, claimed_rows) = row_counts(&matches);
+        println!(
+            "Matches {} of {} row(s) and {} raw payee(s). {} row(s) aren't normalized yet and {} are claimed by existing payees.",
+            new_rows + claimed_rows,
+            rows.len(),
+            matches.len(),
+            new_rows,
+            claimed_rows
+        );
+        Ok(())
+    }
+
+    /// Check the candidate the way the account config is validated, since a bad regex would panic
+    pub fn check(normalizer: &PayeeNormalizerConfig) -> anyhow::Result<()> {
+        let problems: Vec<String> = validate_normalizer(normalizer)
+            .into_iter()
+            .map(|(field, message)| format!("{}: {}", field, message))
+            .collect();
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow!(problems.join("\n")))
+        }
+    }
+}
+
+/// The raw payees the normalizer matches, with the most rows first
+fn matches<'a, 'c>(
+    matcher: &Matcher,
+    raw_payees: impl Iterator<Item = &'a str>,
+    claimed_by: impl Fn(&str) -> Option<&'c String>,
+) -> Vec<Match<'a>>
+where
+    'c: 'a,
+{
+    let mut counts: HashMap<&str, (usize, f64)> = HashMap::new();
+    for raw_payee in raw_payees {
+        if let Option::Some(score) = matcher.score(raw_payee) {
+            counts.entry(raw_payee).or_insert((0, score)).0 += 1;
+        }
+    }
+    let mut matches: Vec<Match> = counts
+        .into_iter()
+        .map(|(raw_payee, (rows, score))| Match {
+            raw_payee,
+            rows,
+            score,
+            claimed_by: claimed_by(raw_payee),
+        })
+        .collect();
+    matches.sort_by(|a, b| b.rows.cmp(&a.rows).then(a.raw_payee.cmp(b.raw_payee)));
+    matches
+}
+
+/// The number of matched rows that aren't normalized yet, and that existing payees claim
+fn row_counts(matches: &[Match]) -> (usize, usize) {
+    matches
+        .iter()
+        .fold((0, 0), |(new, claimed), m| match m.claimed_by {
+            Option::Some(_) => (new, claimed + m.rows),
+            Option::None => (new + m.rows, claimed),
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config::{MatcherType, PayeeNormalizerConfig};
+    use crate::transaction::normalizer_test::{NormalizerTest, matches, row_counts};
+    use crate::transaction::payee::Matcher;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_check() {
+        let normalizer = |normalizer_type| PayeeNormalizerConfig {
+            normalizer_type,
+            ignore_case: true,
+        };
+        assert!(
+            NormalizerTest::check(&normalizer(MatcherType::Regex {
+                regex_string: String::from("^STARBUCKS")
+            }))
+            .is_ok()
+        );
+        assert!(
+            NormalizerTest::check(&normalizer(MatcherType::Regex {
+                regex_string: String::from("(")
+            }))
+            .is_err()
+        );
+        assert!(
+            NormalizerTest::check(&normalizer(MatcherType::Fuzzy {
+                fuzzy_match_string: String::from("#123"),
+                threshold: 0.8,
+                token_weights: HashMap::new(),
+            }))
+            .is_err()
+        );
+        assert!(
+            NormalizerTest::check(&normalizer(MatcherType::Contains {
+                contains_string: String::from(" ")
+            }))
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_matches() {
+        let normalizer = PayeeNormalizerConfig {
+            normalizer_type: MatcherType::Contains {
+                contains_string: String::from("starbucks"),
+            },
+            ignore_case: true,
+        };
+        let starbucks = String::from("starbucks");
+        let raw_payees = [
+            "STARBUCKS 12",
+            "COSTCO WHSE",
+            "STARBUCKS 99",
+            "Starbucks Reserve",
+            "STARBUCKS 99",
+        ];
+        let matcher = Matcher::new(&normalizer).unwrap();
+        let matches = matches(&matcher, raw_payees.into_iter(), |raw_payee| {
+            Option::Some(&starbucks).filter(|_| raw_payee.ends_with("99"))
+        });
+        // The raw payees with the most rows come first, then by name
+        assert_eq!(
+            matches
+                .iter()
+                .map(|m| (m.raw_payee, m.rows, m.claimed_by.map(String::as_str)))
+                .collect::<Vec<_>>(),
+            vec![
+                ("STARBUCKS 99", 2, Option::Some("starbucks")),
+                ("STARBUCKS 12", 1, Option::None),
+                ("Starbucks Reserve", 1, Option::None),
+            ]
+        );
+        assert_eq!(row_counts(&matches), (2, 2));
+    }
+}
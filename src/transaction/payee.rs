@@ -1,90 +1,264 @@
-use crate::config::{Config, MatcherType};
+use crate::config::{CategoryRule, Config, Decision, MatcherType, Payee, PayeeNormalizerConfig};
 use crate::transaction::Transaction;
-use crate::util::currency_to_string_without_delim;
-use regex::RegexBuilder;
+use crate::transaction::fuzzy;
+use crate::util::{currency_to_cents, currency_to_string_without_delim, read_choice, read_line};
+use anyhow::anyhow;
+use currency::Currency;
+use regex::{Regex, RegexBuilder};
+use std::cmp::Reverse;
+use std::collections::HashMap;
 
 #[derive(Debug)]
 pub struct PayeeNormalizer {}
 
+/// Where the category of a transaction comes from
+#[derive(Debug)]
+pub enum CategorySource<'a> {
+    /// Transfers between accounts are not categorized; holds the other account's name
+    Transfer(&'a String),
+    /// The most specific matching category rule, along with its index
+    Rule(usize, &'a CategoryRule),
+    /// The payee isn't normalized
+    NoPayee,
+    NoCategories(&'a Payee),
+    OnlyCategory(&'a Payee, &'a String),
+    /// The decision made for the transaction's fingerprint
+    Decision(String, Option<&'a String>),
+    /// The payee has several categories and no decision was made for the fingerprint
+    Undecided(&'a Payee, String),
+}
+
 impl PayeeNormalizer {
-    pub fn normalized_payee_id(config: &Config, s: &str) -> Option<String> {
-        for (payee_id, payee) in &config.account().payees {
+    /// Find the payee whose normalizers match the raw payee string. Fuzzy matches are returned
+    /// along with their score.
+    pub fn match_payee<'a>(config: &'a Config, s: &str) -> Option<(&'a String, Option<f64>)> {
+        PayeeNormalizer::match_payee_in(&config.account().payees, s)
+    }
+
+    fn match_payee_in<'a>(
+        payees: &'a HashMap<String, Payee>,
+        s: &str,
+    ) -> Option<(&'a String, Option<f64>)> {
+        // Exact, contains and regex matches always take precedence over fuzzy matches, so only
+        // return the best fuzzy match once every payee has been checked.
+        let mut best_fuzzy_match: Option<(&String, f64)> = Option::None;
+        for (payee_id, payee) in payees {
             for normalizer in &payee.normalizers {
-                match &normalizer.normalizer_type {
-                    MatcherType::Exact { exact_match_string } => {
-                        let cmp_string = PayeeNormalizer::maybe_to_lower(normalizer.ignore_case, s);
-                        let exact_match_string = PayeeNormalizer::maybe_to_lower(
-                            normalizer.ignore_case,
-                            exact_match_string,
-                        );
-                        if exact_match_string == cmp_string {
-                            return Option::Some(payee_id.to_owned());
-                        }
-                    }
-                    MatcherType::Contains { contains_string } => {
-                        let cmp_string = PayeeNormalizer::maybe_to_lower(normalizer.ignore_case, s);
-                        let contains_string = PayeeNormalizer::maybe_to_lower(
-                            normalizer.ignore_case,
-                            contains_string,
-                        );
-                        if cmp_string.contains(&contains_string) {
-                            return Option::Some(payee_id.to_owned());
-                        }
-                    }
-                    MatcherType::Regex { regex_string } => {
-                        let re = RegexBuilder::new(regex_string)
-                            .case_insensitive(normalizer.ignore_case)
-                            .build()
-                            .unwrap_or_else(|_| panic!("[{}] is not a valid regex", regex_string));
-                        if re.is_match(s) {
-                            return Option::Some(payee_id.to_owned());
-                        }
+                // Invalid regexes are rejected when the config is validated
+                let score = match Matcher::new(normalizer).ok().and_then(|m| m.score(s)) {
+                    Option::Some(score) => score,
+                    Option::None => continue,
+                };
+                if let MatcherType::Fuzzy { .. } = normalizer.normalizer_type {
+                    // Equal scores go to the first payee ID, so the winner doesn't depend on the
+                    // order of the map
+                    let is_better = best_fuzzy_match
+                        .map(|(best_id, best_score)| {
+                            score > best_score || (score == best_score && payee_id < best_id)
+                        })
+                        .unwrap_or(true);
+                    if is_better {
+                        best_fuzzy_match = Option::Some((payee_id, score));
                     }
+                } else {
+                    return Option::Some((payee_id, Option::None));
                 }
             }
         }
-        println!("Payee was not normalized: {}", s);
-        Option::None
+        best_fuzzy_match.map(|(payee_id, score)| (payee_id, Option::Some(score)))
     }
 
-    fn maybe_to_lower(ignore_case: bool, s: &str) -> String {
-        if ignore_case {
-            s.to_lowercase()
-        } else {
-            s.to_owned()
+    /// Every normalizer that matches the raw payee string, as the payee ID, the index of the
+    /// normalizer in the payee and the match score, in the order `match_payee` checks them.
+    pub fn matching_normalizers<'a>(config: &'a Config, s: &str) -> Vec<(&'a String, usize, f64)> {
+        let mut matches = Vec::new();
+        for (payee_id, payee) in &config.account().payees {
+            for (i, normalizer) in payee.normalizers.iter().enumerate() {
+                if let Option::Some(score) = Matcher::new(normalizer).ok().and_then(|m| m.score(s))
+                {
+                    matches.push((payee_id, i, score));
+                }
+            }
         }
+        matches
     }
 
-    pub fn category_for_transaction(config: &Config, transaction: &Transaction) -> Option<String> {
-        transaction
+    /// The matcher type, match string and case sensitivity of the normalizer, for messages
+    pub fn describe(normalizer: &PayeeNormalizerConfig) -> String {
+        let (matcher, pattern) = match normalizer.normalizer_type {
+            MatcherType::Exact {
+                ref exact_match_string,
+            } => ("Exact", exact_match_string),
+            MatcherType::Contains {
+                ref contains_string,
+            } => ("Contains", contains_string),
+            MatcherType::Regex { ref regex_string } => ("Regex", regex_string),
+            MatcherType::Fuzzy {
+                ref fuzzy_match_string,
+                ..
+            } => ("Fuzzy", fuzzy_match_string),
+        };
+        format!(
+            "{} [{}], ignoreCase {}",
+            matcher, pattern, normalizer.ignore_case
+        )
+    }
+
+    pub fn category_for_transaction(
+        config: &mut Config,
+        transaction: &Transaction,
+    ) -> anyhow::Result<Option<String>> {
+        let category_id = match PayeeNormalizer::category_source(config, transaction) {
+            CategorySource::Rule(_, rule) => Option::Some(rule.category_id.to_owned()),
+            CategorySource::OnlyCategory(_, id) => Option::Some(id.to_owned()),
+            CategorySource::Decision(_, id) => id.cloned(),
+            CategorySource::Transfer(_)
+            | CategorySource::NoPayee
+            | CategorySource::NoCategories(_) => Option::None,
+            CategorySource::Undecided(payee, fingerprint) => {
+                let category_ids = payee.category_ids.to_owned().unwrap_or_default();
+                PayeeNormalizer::decide_category_id(config, transaction, fingerprint, category_ids)?
+            }
+        };
+        Ok(category_id
+            .and_then(|x| config.category(&x))
+            .map(|c| c.name.to_owned()))
+    }
+
+    /// Where the category of the transaction comes from, without prompting for anything
+    pub fn category_source<'a>(
+        config: &'a Config,
+        transaction: &'a Transaction,
+    ) -> CategorySource<'a> {
+        if let Option::Some(ref transfer) = transaction.transfer {
+            return CategorySource::Transfer(&transfer.account_name);
+        }
+        if let Option::Some((i, rule)) =
+            PayeeNormalizer::category_rule_for_transaction(config, transaction)
+        {
+            return CategorySource::Rule(i, rule);
+        }
+        let Option::Some(payee) = transaction
             .normalized_payee_id
             .as_ref()
             .and_then(|p| config.account().payees.get(p))
-            .and_then(|p| p.category_ids.as_ref())
-            .and_then(|c| {
-                if c.is_empty() {
-                    return Option::None;
-                }
-                if c.len() == 1 {
-                    return c.first();
-                }
-                if !config.skip_prompts() {
-                    PayeeNormalizer::prompt_select_category_id(config, transaction, c)
-                } else {
-                    Option::None
+        else {
+            return CategorySource::NoPayee;
+        };
+        match payee.category_ids.as_deref().unwrap_or_default() {
+            [] => CategorySource::NoCategories(payee),
+            [id] => CategorySource::OnlyCategory(payee, id),
+            _ => {
+                let fingerprint = transaction.fingerprint();
+                match config.decision(&fingerprint) {
+                    Option::Some(decision) => {
+                        CategorySource::Decision(fingerprint, decision.category_id.as_ref())
+                    }
+                    Option::None => CategorySource::Undecided(payee, fingerprint),
                 }
-            })
-            .and_then(|x| config.category(x))
-            .map(|c| c.name.to_owned())
+            }
+        }
     }
 
-    fn prompt_select_category_id<'a>(
+    /// Choose one of several categories of the payee when no decision has been made for the
+    /// transaction, by adding it to the decisions template or prompting for it
+    fn decide_category_id(
+        config: &mut Config,
+        transaction: &Transaction,
+        fingerprint: String,
+        category_ids: Vec<String>,
+    ) -> anyhow::Result<Option<String>> {
+        if config.write_decisions_template() {
+            config.add_pending_decision(Decision {
+                fingerprint,
+                category_id: Option::None,
+                date: Option::Some(transaction.date.format("%Y-%m-%d").to_string()),
+                payee: Option::Some(transaction.payee().to_owned()),
+                raw_payee: Option::Some(transaction.raw_payee_name.to_owned()),
+                amount: Option::Some(currency_to_string_without_delim(
+                    &transaction.signed_amount(),
+                )),
+                memo: transaction.memo.to_owned(),
+                options: category_ids,
+            });
+            return Ok(Option::None);
+        }
+        if config.skip_prompts() {
+            return Ok(Option::None);
+        }
+        let category_id =
+            PayeeNormalizer::prompt_select_category_id(config, transaction, &category_ids);
+        if let Option::Some(ref category_id) = category_id {
+            if let Option::Some(rule) =
+                PayeeNormalizer::prompt_remember_category(transaction, category_id)
+            {
+                config.add_category_rule(rule)?;
+            }
+        }
+        Ok(category_id)
+    }
+
+    /// Find the most specific remembered category rule that matches the transaction, along with
+    /// its index.
+    pub fn category_rule_for_transaction<'a>(
         config: &'a Config,
         transaction: &Transaction,
-        category_ids: &'a [String],
-    ) -> Option<&'a String> {
-        println!();
-        println!(
+    ) -> Option<(usize, &'a CategoryRule)> {
+        PayeeNormalizer::most_specific_category_rule(config.category_rules(), transaction)
+    }
+
+    fn most_specific_category_rule<'a>(
+        rules: &'a [CategoryRule],
+        transaction: &Transaction,
+    ) -> Option<(usize, &'a CategoryRule)> {
+        rules
+            .iter()
+            .enumerate()
+            .filter(|(_, r)| PayeeNormalizer::category_rule_matches(r, transaction))
+            // Prefer rules with more conditions; the earliest rule wins a tie
+            .max_by_key(|(i, r)| {
+                let conditions = [
+                    r.payee_id.is_some(),
+                    r.raw_payee.is_some(),
+                    r.min_amount.is_some(),
+                    r.max_amount.is_some(),
+                ];
+                (conditions.iter().filter(|c| **c).count(), Reverse(*i))
+            })
+    }
+
+    fn category_rule_matches(rule: &CategoryRule, transaction: &Transaction) -> bool {
+        if let Option::Some(ref payee_id) = rule.payee_id {
+            if transaction.normalized_payee_id.as_ref() != Option::Some(payee_id) {
+                return false;
+            }
+        }
+        if let Option::Some(ref raw_payee) = rule.raw_payee {
+            if raw_payee != &transaction.raw_payee_name {
+                return false;
+            }
+        }
+        let amount = currency_to_cents(&transaction.amount);
+        let min = rule
+            .min_amount
+            .as_ref()
+            .and_then(|a| Currency::from_str(a).ok())
+            .map(|a| currency_to_cents(&a).abs());
+        let max = rule
+            .max_amount
+            .as_ref()
+            .and_then(|a| Currency::from_str(a).ok())
+            .map(|a| currency_to_cents(&a).abs());
+        min.is_none_or(|min| amount >= min) && max.is_none_or(|max| amount <= max)
+    }
+
+    fn prompt_select_category_id(
+        config: &Config,
+        transaction: &Transaction,
+        category_ids: &[String],
+    ) -> Option<String> {
+        eprintln!();
+        eprintln!(
             "Multiple categories available for transaction: [payee: {}], [amount: {}], [type: {:?}], [date: {}], [raw payee: {}], [memo: {:?}], [status: {:?}]",
             transaction.payee(),
             currency_to_string_without_delim(&transaction.amount),
@@ -94,19 +268,242 @@ impl PayeeNormalizer {
             transaction.memo,
             transaction.status
         );
-        println!("Please select an option:");
+        eprintln!("Please select an option:");
 
-        println!("{}. (skip)", 0);
+        eprintln!("{}. (skip)", 0);
         for (i, category_id) in category_ids.iter().enumerate() {
             if let Option::Some(c) = config.category(category_id) {
-                println!("{}. {}", i + 1, c.name);
+                eprintln!("{}. {}", i + 1, c.name);
             }
         }
-        let num: usize = read!();
+        let num = read_choice(category_ids.len());
         if num == 0 {
             Option::None
         } else {
-            category_ids.get(num - 1)
+            category_ids.get(num - 1).cloned()
         }
     }
+
+    fn prompt_remember_category(
+        transaction: &Transaction,
+        category_id: &str,
+    ) -> Option<CategoryRule> {
+        let rule = CategoryRule {
+            category_id: category_id.to_owned(),
+            payee_id: Option::None,
+            raw_payee: Option::None,
+            min_amount: Option::None,
+            max_amount: Option::None,
+        };
+        eprintln!("Remember this choice?");
+        eprintln!("0. (don't remember)");
+        eprintln!("1. Remember for this payee");
+        eprintln!(
+            "2. Remember for this raw payee: [{}]",
+            transaction.raw_payee_name
+        );
+        eprintln!("3. Remember for this payee with amounts in a range");
+        match read_choice(3) {
+            1 => Option::Some(CategoryRule {
+                payee_id: transaction.normalized_payee_id.to_owned(),
+                ..rule
+            }),
+            2 => Option::Some(CategoryRule {
+                raw_payee: Option::Some(transaction.raw_payee_name.to_owned()),
+                ..rule
+            }),
+            3 => {
+                eprintln!("Minimum amount:");
+                let min_amount = PayeeNormalizer::read_amount()?;
+                eprintln!("Maximum amount:");
+                let max_amount = PayeeNormalizer::read_amount()?;
+                Option::Some(CategoryRule {
+                    payee_id: transaction.normalized_payee_id.to_owned(),
+                    min_amount: Option::Some(min_amount),
+                    max_amount: Option::Some(max_amount),
+                    ..rule
+                })
+            }
+            _ => Option::None,
+        }
+    }
+
+    fn read_amount() -> Option<String> {
+        loop {
+            let s = read_line()?;
+            match Currency::from_str(s.trim()) {
+                Ok(c) => return Option::Some(currency_to_string_without_delim(&c)),
+                Err(_) => eprintln!("[{}] is not a valid amount, please try again:", s),
+            }
+        }
+    }
+}
+
+/// A payee normalizer ready to match raw payees. A regex is compiled once, when the matcher is
+/// created, so an invalid regex is an error rather than a panic.
+#[derive(Debug)]
+pub struct Matcher<'a> {
+    normalizer: &'a PayeeNormalizerConfig,
+    regex: Option<Regex>,
+}
+
+impl<'a> Matcher<'a> {
+    pub fn new(normalizer: &'a PayeeNormalizerConfig) -> anyhow::Result<Matcher<'a>> {
+        let regex = match normalizer.normalizer_type {
+            MatcherType::Regex { ref regex_string } => Option::Some(
+                RegexBuilder::new(regex_string)
+                    .case_insensitive(normalizer.ignore_case)
+                    .build()
+                    .map_err(|e| anyhow!("Invalid regex string [{}]: {}", regex_string, e))?,
+            ),
+            _ => Option::None,
+        };
+        Ok(Matcher { normalizer, regex })
+    }
+
+    /// Check the normalizer against the raw payee string. Returns the match score if the
+    /// normalizer matches; normalizers other than `Fuzzy` always have a score of 1.
+    pub fn score(&self, s: &str) -> Option<f64> {
+        let ignore_case = self.normalizer.ignore_case;
+        let is_match = match &self.normalizer.normalizer_type {
+            MatcherType::Exact { exact_match_string } => {
+                maybe_to_lower(ignore_case, exact_match_string) == maybe_to_lower(ignore_case, s)
+            }
+            MatcherType::Contains { contains_string } => maybe_to_lower(ignore_case, s)
+                .contains(&maybe_to_lower(ignore_case, contains_string)),
+            MatcherType::Regex { .. } => self.regex.as_ref().is_some_and(|re| re.is_match(s)),
+            MatcherType::Fuzzy {
+                fuzzy_match_string,
+                threshold,
+                token_weights,
+            } => {
+                let score = fuzzy::similarity(fuzzy_match_string, s, token_weights);
+                return if score >= *threshold {
+                    Option::Some(score)
+                } else {
+                    Option::None
+                };
+            }
+        };
+        if is_match {
+            Option::Some(1.0)
+        } else {
+            Option::None
+        }
+    }
+}
+
+fn maybe_to_lower(ignore_case: bool, s: &str) -> String {
+    if ignore_case {
+        s.to_lowercase()
+    } else {
+        s.to_owned()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config::{CategoryRule, Payee, PayeeNormalizerConfig};
+    use crate::transaction::payee::{Matcher, PayeeNormalizer};
+    use crate::transaction::test::transaction;
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    fn payees(payees: serde_json::Value) -> HashMap<String, Payee> {
+        serde_json::from_value::<Vec<Payee>>(payees)
+            .unwrap()
+            .into_iter()
+            .map(|p| (p.id.to_owned(), p))
+            .collect()
+    }
+
+    #[test]
+    fn test_category_rules() {
+        let rules: Vec<CategoryRule> = serde_json::from_value(json!([
+            {"categoryId": "coffee", "payeeId": "starbucks"},
+            {"categoryId": "gifts", "payeeId": "starbucks", "minAmount": "50.00"},
+            {"categoryId": "snacks", "rawPayee": "STARBUCKS #42", "maxAmount": "5.00"},
+        ]))
+        .unwrap();
+        let category = |raw_payee: &str, payee_id: Option<&str>, amount: &str| {
+            let mut t = transaction(2, raw_payee, amount);
+            t.normalized_payee_id = payee_id.map(str::to_owned);
+            PayeeNormalizer::most_specific_category_rule(&rules, &t)
+                .map(|(_, r)| r.category_id.to_owned())
+        };
+
+        assert_eq!(
+            category("STARBUCKS #1", Option::Some("starbucks"), "-4.10").as_deref(),
+            Option::Some("coffee")
+        );
+        // Bounds are inclusive and compare the absolute amount
+        assert_eq!(
+            category("STARBUCKS #1", Option::Some("starbucks"), "-50.00").as_deref(),
+            Option::Some("gifts")
+        );
+        assert_eq!(
+            category("STARBUCKS #1", Option::Some("starbucks"), "49.99").as_deref(),
+            Option::Some("coffee")
+        );
+        // A raw payee rule applies whether or not the payee was normalized
+        assert_eq!(
+            category("STARBUCKS #42", Option::None, "-5.00").as_deref(),
+            Option::Some("snacks")
+        );
+        assert_eq!(
+            category("STARBUCKS #42", Option::None, "-5.01"),
+            Option::None
+        );
+        assert_eq!(
+            category("STARBUCKS #42", Option::Some("starbucks"), "-4.10").as_deref(),
+            Option::Some("snacks")
+        );
+        assert_eq!(
+            category("DUNKIN", Option::Some("dunkin"), "-4.10"),
+            Option::None
+        );
+    }
+
+    #[test]
+    fn test_match_payee_fuzzy_ties() {
+        let config = json!([
+            {"id": "whole-foods", "name": "Whole Foods", "normalizers": [{"type": "Fuzzy", "matchString": "Whole Foods"}]},
+            {"id": "foods-whole", "name": "Foods Whole", "normalizers": [{"type": "Fuzzy", "matchString": "Whole Foods"}]},
+            {"id": "market", "name": "Market", "normalizers": [{"type": "Contains", "matchString": "MARKET"}]}
+        ]);
+        // Each map iterates in a different order
+        for _ in 0..10 {
+            let payees = payees(config.to_owned());
+            assert_eq!(
+                PayeeNormalizer::match_payee_in(&payees, "WHOLE FOODS #123"),
+                Option::Some((&String::from("foods-whole"), Option::Some(1.0)))
+            );
+            // Other matchers still win over fuzzy matches
+            assert_eq!(
+                PayeeNormalizer::match_payee_in(&payees, "WHOLE FOODS MARKET"),
+                Option::Some((&String::from("market"), Option::None))
+            );
+        }
+    }
+
+    #[test]
+    fn test_matcher() {
+        let normalizer = |config: serde_json::Value| {
+            serde_json::from_value::<PayeeNormalizerConfig>(config).unwrap()
+        };
+        let regex = normalizer(json!({"type": "Regex", "matchString": "^sq \\*blue"}));
+        let m = Matcher::new(&regex).unwrap();
+        assert_eq!(m.score("SQ *BLUE BOTTLE"), Option::Some(1.0));
+        assert_eq!(m.score("BLUE BOTTLE"), Option::None);
+
+        let exact =
+            normalizer(json!({"type": "Exact", "matchString": "Costco", "ignoreCase": false}));
+        let m = Matcher::new(&exact).unwrap();
+        assert_eq!(m.score("Costco"), Option::Some(1.0));
+        assert_eq!(m.score("COSTCO"), Option::None);
+
+        // An invalid regex is an error, not a panic when matching
+        let invalid = normalizer(json!({"type": "Regex", "matchString": "("}));
+        assert!(Matcher::new(&invalid).is_err());
+    }
 }
@@ -0,0 +1,315 @@
+use crate::config::{Config, MatcherType, Payee, PayeeNormalizerConfig};
+use crate::transaction::Transaction;
+use crate::transaction::payee::{Matcher, PayeeNormalizer};
+use crate::util::{currency_to_string_without_delim, read_choice, read_line};
+
+/// Interactively assigns payees to raw payee strings that weren't normalized, creating new payees
+/// and normalizers as needed.
+#[derive(Debug)]
+pub struct PayeeCreator {}
+
+impl PayeeCreator {
+    /// Prompt for each distinct raw payee in the batch that wasn't normalized. New payees and
+    /// normalizers are saved to the account config file, and are immediately applied to the rest
+    /// of the batch.
+    pub fn prompt_for_unnormalized(
+        config: &mut Config,
+        transactions: &mut [Transaction],
+    ) -> anyhow::Result<()> {
+        let mut skipped: Vec<String> = Vec::new();
+        while let Option::Some(t) = transactions
+            .iter()
+            .find(|t| t.normalized_payee_id.is_none() && !skipped.contains(&t.raw_payee_name))
+        {
+            let raw_payee = t.raw_payee_name.to_owned();
+            if !PayeeCreator::prompt_for_transaction(config, t, transactions)? {
+                skipped.push(raw_payee);
+                continue;
+            }
+            // Apply the new rule to everything that hasn't been normalized yet
+            for t in transactions
+                .iter_mut()
+                .filter(|t| t.normalized_payee_id.is_none())
+            {
+                if PayeeNormalizer::match_payee(config, &t.raw_payee_name).is_some() {
+                    t.normalize_payee(config);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns true if a normalizer was saved for the transaction's raw payee.
+    fn prompt_for_transaction(
+        config: &mut Config,
+        transaction: &Transaction,
+        transactions: &[Transaction],
+    ) -> anyhow::Result<bool> {
+        eprintln!();
+        eprintln!(
+            "Payee was not normalized for transaction: [raw payee: {}], [amount: {}], [type: {:?}], [date: {}], [memo: {:?}]",
+            transaction.raw_payee_name,
+            currency_to_string_without_delim(&transaction.amount),
+            transaction.transaction_type,
+            transaction.date,
+            transaction.memo
+        );
+        eprintln!("Please select an option:");
+        eprintln!("0. (skip)");
+        eprintln!("1. Use an existing payee");
+        eprintln!("2. Create a new payee");
+        match read_choice(2) {
+            1 => {
+                let payee_id = match PayeeCreator::prompt_select_payee(config) {
+                    Option::Some(p) => p,
+                    Option::None => return Ok(false),
+                };
+                match PayeeCreator::prompt_normalizer(config, transaction, transactions) {
+                    Option::Some(normalizer) => {
+                        config.add_payee_normalizer(&payee_id, normalizer)?;
+                        Ok(true)
+                    }
+                    Option::None => Ok(false),
+                }
+            }
+            2 => {
+                let mut payee = match PayeeCreator::prompt_new_payee(config) {
+                    Option::Some(p) => p,
+                    Option::None => return Ok(false),
+                };
+                match PayeeCreator::prompt_normalizer(config, transaction, transactions) {
+                    Option::Some(normalizer) => {
+                        payee.normalizers.push(normalizer);
+                        config.add_payee(payee)?;
+                        Ok(true)
+                    }
+                    Option::None => Ok(false),
+                }
+            }
+            _ => Ok(false),
+        }
+    }
+
+    fn prompt_select_payee(config: &Config) -> Option<String> {
+        let mut payees: Vec<&Payee> = config.account().payees.values().collect();
+        payees.sort_by(|a, b| a.name.cmp(&b.name));
+        eprintln!("Please select a payee:");
+        eprintln!("0. (skip)");
+        for (i, p) in payees.iter().enumerate() {
+            eprintln!("{}. {}", i + 1, p.name);
+        }
+        let num = read_choice(payees.len());
+        if num == 0 {
+            Option::None
+        } else {
+            payees.get(num - 1).map(|p| p.id.to_owned())
+        }
+    }
+
+    fn prompt_new_payee(config: &Config) -> Option<Payee> {
+        let name = loop {
+            eprintln!("Payee name:");
+            let name = read_line()?.trim().to_owned();
+            if !name.is_empty() {
+                break name;
+            }
+        };
+
+        let suggested_id = suggest_payee_id(&name);
+        let id = loop {
+            if suggested_id.is_empty() {
+                eprintln!("Payee ID:");
+            } else {
+                eprintln!("Payee ID (leave empty to use [{}]):", suggested_id);
+            }
+            let id = match read_line()?.trim() {
+                "" => suggested_id.to_owned(),
+                id => id.to_owned(),
+            };
+            if id.is_empty() {
+                continue;
+            }
+            if config.account().payees.contains_key(&id) {
+                eprintln!("Payee [{}] already exists, please try again.", id);
+            } else {
+                break id;
+            }
+        };
+
+        let mut categories: Vec<_> = config.categories().collect();
+        categories.sort_by(|a, b| a.name.cmp(&b.name));
+        eprintln!(
+            "Please select categories for the payee (space separated, leave empty for none):"
+        );
+        for (i, c) in categories.iter().enumerate() {
+            eprintln!("{}. {}", i + 1, c.name);
+        }
+        let selection = read_line()?;
+        let category_ids: Vec<String> = selection
+            .split_whitespace()
+            .filter_map(|n| n.parse::<usize>().ok())
+            .filter_map(|n| n.checked_sub(1).and_then(|i| categories.get(i)))
+            .map(|c| c.id.to_owned())
+            .collect();
+
+        Option::Some(Payee {
+            id,
+            name,
+            category_ids: if category_ids.is_empty() {
+                Option::None
+            } else {
+                Option::Some(category_ids)
+            },
+            normalizers: Vec::new(),
+        })
+    }
+
+    /// Prompt for a normalizer for the transaction's raw payee, showing which other raw payees in
+    /// the batch it would match before it's accepted.
+    fn prompt_normalizer(
+        config: &Config,
+        transaction: &Transaction,
+        transactions: &[Transaction],
+    ) -> Option<PayeeNormalizerConfig> {
+        let raw_payee = &transaction.raw_payee_name;
+        let prefix = suggest_contains_string(raw_payee);
+        loop {
+            eprintln!("Please select a normalizer:");
+            eprintln!("0. (skip)");
+            eprintln!("1. Contains: [{}]", prefix);
+            eprintln!("2. Exact: [{}]", raw_payee);
+            eprintln!("3. Regex: [^{}]", regex::escape(&prefix));
+            eprintln!("4. Contains (enter a custom string)");
+            eprintln!("5. Regex (enter a custom pattern)");
+            let normalizer_type = match read_choice(5) {
+                1 => MatcherType::Contains {
+                    contains_string: prefix.to_owned(),
+                },
+                2 => MatcherType::Exact {
+                    exact_match_string: raw_payee.to_owned(),
+                },
+                3 => MatcherType::Regex {
+                    regex_string: format!("^{}", regex::escape(&prefix)),
+                },
+                4 => {
+                    eprintln!("String to match:");
+                    let s = read_line()?.trim().to_owned();
+                    if s.is_empty() {
+                        eprintln!("The string to match can't be empty, please try again.");
+                        continue;
+                    }
+                    MatcherType::Contains { contains_string: s }
+                }
+                5 => {
+                    eprintln!("Regex pattern:");
+                    let s = read_line()?.trim().to_owned();
+                    MatcherType::Regex { regex_string: s }
+                }
+                _ => return Option::None,
+            };
+            let normalizer = PayeeNormalizerConfig {
+                normalizer_type,
+                ignore_case: true,
+            };
+
+            let matcher = match Matcher::new(&normalizer) {
+                Ok(m) => m,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    continue;
+                }
+            };
+            if matcher.score(raw_payee).is_none() {
+                eprintln!(
+                    "The normalizer does not match the raw payee [{}], please try again.",
+                    raw_payee
+                );
+                continue;
+            }
+            print_batch_matches(config, &matcher, transactions);
+            eprintln!("Save this normalizer?");
+            eprintln!("0. No, choose again");
+            eprintln!("1. Yes");
+            if read_choice(1) == 1 {
+                return Option::Some(normalizer);
+            }
+        }
+    }
+}
+
+/// Print every distinct raw payee in the batch that the normalizer matches, along with the payee
+/// that currently claims it, if any.
+fn print_batch_matches(config: &Config, matcher: &Matcher, transactions: &[Transaction]) {
+    let mut raw_payees: Vec<&String> = transactions
+        .iter()
+        .map(|t| &t.raw_payee_name)
+        .filter(|s| matcher.score(s).is_some())
+        .collect();
+    raw_payees.sort();
+    raw_payees.dedup();
+    eprintln!(
+        "The normalizer matches {} raw payee(s) in this batch:",
+        raw_payees.len()
+    );
+    for raw_payee in raw_payees {
+        let count = transactions
+            .iter()
+            .filter(|t| &t.raw_payee_name == raw_payee)
+            .count();
+        match PayeeNormalizer::match_payee(config, raw_payee) {
+            Option::Some((payee_id, _)) => eprintln!(
+                "  {} ({} transaction(s)), currently normalized to [{}]",
+                raw_payee, count, payee_id
+            ),
+            Option::None => eprintln!("  {} ({} transaction(s))", raw_payee, count),
+        }
+    }
+}
+
+/// Suggest a string for a `Contains` normalizer: the start of the raw payee, up to the first
+/// word that looks like a store or reference number.
+pub fn suggest_contains_string(raw_payee: &str) -> String {
+    let words: Vec<&str> = raw_payee
+        .split_whitespace()
+        .take_while(|w| !w.starts_with('#') && !w.chars().any(|c| c.is_ascii_digit()))
+        .collect();
+    if words.is_empty() {
+        raw_payee.trim().to_owned()
+    } else {
+        words.join(" ")
+    }
+}
+
+pub fn suggest_payee_id(name: &str) -> String {
+    name.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(|w| w.to_lowercase())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+#[cfg(test)]
+mod test {
+    use crate::transaction::payee_creator::{suggest_contains_string, suggest_payee_id};
+
+    #[test]
+    fn test_suggest_contains_string() {
+        assert_eq!(
+            suggest_contains_string("SQ *BLUE BOTTLE 0231"),
+            "SQ *BLUE BOTTLE"
+        );
+        assert_eq!(suggest_contains_string("TARGET #1234 SEATTLE"), "TARGET");
+        assert_eq!(suggest_contains_string("AMAZON.COM"), "AMAZON.COM");
+        // Nothing before the first number, so the whole raw payee is used
+        assert_eq!(suggest_contains_string("  #1234 "), "#1234");
+    }
+
+    #[test]
+    fn test_suggest_payee_id() {
+        assert_eq!(suggest_payee_id("Blue Bottle Coffee"), "blue-bottle-coffee");
+        assert_eq!(suggest_payee_id("SQ *BLUE BOTTLE"), "sq-blue-bottle");
+        assert_eq!(suggest_payee_id("Trader Joe's"), "trader-joe-s");
+        // A name without letters or digits has no ID to suggest
+        assert_eq!(suggest_payee_id("* & *"), "");
+    }
+}
@@ -0,0 +1,190 @@
+use crate::config::Config;
+use crate::diagnostics::Diagnostic;
+use crate::transaction::{ChangeType, Transaction, TransactionStatus};
+use crate::util::{cents_to_currency, currency_to_cents, currency_to_string_without_delim};
+use anyhow::anyhow;
+
+/// Checks an imported batch against the opening and closing balances of its statement: the
+/// opening balance plus the credits minus the debits should equal the closing balance.
+#[derive(Debug)]
+pub struct Reconciliation {
+    opening: i64,
+    credits: i64,
+    debits: i64,
+    closing: i64,
+}
+
+impl Reconciliation {
+    /// Print the reconciliation of the batch. Returns an error if it doesn't reconcile, listing
+    /// the likely causes.
+    pub fn run(config: &Config, transactions: &[Transaction]) -> anyhow::Result<()> {
+        let opening = config.opening_balance().ok_or_else(|| {
+            anyhow!(
+                "An opening balance is required to reconcile. Use --opening-balance, or configure the balance field or the preamble of the source format."
+            )
+        })?;
+        let closing = config.closing_balance().ok_or_else(|| {
+            anyhow!(
+                "A closing balance is required to reconcile. Use --closing-balance, or configure the balance field or the preamble of the source format."
+            )
+        })?;
+        let r = Reconciliation::new(
+            currency_to_cents(&opening),
+            currency_to_cents(&closing),
+            transactions,
+        );
+        r.print(&config.account().name, transactions);
+        if r.discrepancy() == 0 {
+            Diagnostic::info("The statement reconciles.")
+                .account(&config.account().name)
+                .emit();
+            return Ok(());
+        }
+        let causes = r.suggest_causes(config, transactions);
+        Err(anyhow!(
+            "The statement does not reconcile, the closing balance differs by [{}]. Likely causes:\n{}",
+            format_cents(r.discrepancy()),
+            causes
+                .iter()
+                .map(|c| format!("  - {}", c))
+                .collect::<Vec<_>>()
+                .join("\n")
+        ))
+    }
+
+    fn new(opening: i64, closing: i64, transactions: &[Transaction]) -> Reconciliation {
+        let amounts: Vec<i64> = amounts(transactions).map(|(_, a)| a).collect();
+        Reconciliation {
+            opening,
+            credits: amounts.iter().filter(|a| **a > 0).sum(),
+            debits: amounts.iter().filter(|a| **a < 0).sum(),
+            closing,
+        }
+    }
+
+    fn expected_closing(&self) -> i64 {
+        self.opening + self.credits + self.debits
+    }
+
+    /// How much the imported transactions would need to change by to reconcile
+    fn discrepancy(&self) -> i64 {
+        self.closing - self.expected_closing()
+    }
+
+    fn print(&self, account: &str, transactions: &[Transaction]) {
+        let count = |credit: bool| {
+            amounts(transactions)
+                .filter(|(_, a)| (*a > 0) == credit && *a != 0)
+                .count()
+        };
+        let lines = [
+            format!("Opening balance: {}", format_cents(self.opening)),
+            format!(
+                "Credits: {} ({} transaction(s))",
+                format_cents(self.credits),
+                count(true)
+            ),
+            format!(
+                "Debits: {} ({} transaction(s))",
+                format_cents(self.debits),
+                count(false)
+            ),
+            format!(
+                "Expected closing balance: {}",
+                format_cents(self.expected_closing())
+            ),
+            format!("Statement closing balance: {}", format_cents(self.closing)),
+        ];
+        for line in lines {
+            Diagnostic::info(line).account(account).emit();
+        }
+    }
+
+    fn suggest_causes(&self, config: &Config, transactions: &[Transaction]) -> Vec<String> {
+        let d = self.discrepancy();
+        let mut causes = Vec::new();
+        if self.credits + self.debits != 0
+            && self.opening - self.credits - self.debits == self.closing
+        {
+            causes.push(String::from(
+                "The sign of every transaction appears to be flipped. Check the amount config of the source format, e.g. `debitIsNegative`, or swapped debit and credit fields.",
+            ));
+        }
+        let amounts: Vec<(&Transaction, i64)> = amounts(transactions).collect();
+        for (i, &(t, a)) in amounts.iter().enumerate() {
+            if a == -d {
+                if t.status == TransactionStatus::Pending {
+                    causes.push(format!(
+                        "Pending transaction {} may not be included in the closing balance yet.",
+                        t.describe()
+                    ));
+                } else if let Option::Some((original, _)) = amounts[..i]
+                    .iter()
+                    .find(|(o, b)| *b == a && o.raw_payee_name == t.raw_payee_name)
+                {
+                    causes.push(format!(
+                        "Transaction {} may be a duplicate of {}.",
+                        t.describe(),
+                        original.source_line()
+                    ));
+                } else {
+                    causes.push(format!(
+                        "Transaction {} may not belong to this statement.",
+                        t.describe()
+                    ));
+                }
+            } else if 2 * a == -d {
+                causes.push(format!(
+                    "The sign of transaction {} may be flipped.",
+                    t.describe()
+                ));
+            }
+        }
+        let mut missing = format!(
+            "A transaction of [{}] may be missing from the import, e.g. a duplicate that was dropped",
+            format_cents(d)
+        );
+        if config.ignore_pending() {
+            missing.push_str(" or a pending transaction that was ignored");
+        }
+        if config.since_last_run() {
+            missing.push_str(" or a transaction that was exported by a previous run");
+        }
+        missing.push('.');
+        causes.push(missing);
+        causes
+    }
+}
+
+/// The signed amount of each transaction that changes the balance
+fn amounts(transactions: &[Transaction]) -> impl Iterator<Item = (&Transaction, i64)> {
+    transactions
+        .iter()
+        .filter(|t| t.change != ChangeType::Remove)
+        .map(|t| (t, currency_to_cents(&t.signed_amount())))
+}
+
+fn format_cents(cents: i64) -> String {
+    currency_to_string_without_delim(&cents_to_currency(cents))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::transaction::reconcile::Reconciliation;
+    use crate::transaction::test::transaction;
+
+    #[test]
+    fn test_discrepancy() {
+        let transactions = vec![
+            transaction(9, "STARBUCKS", "100.00"),
+            transaction(9, "STARBUCKS", "-4.10"),
+        ];
+        let r = Reconciliation::new(1000, 10590, &transactions);
+        assert_eq!(r.credits, 10000);
+        assert_eq!(r.debits, -410);
+        assert_eq!(r.discrepancy(), 0);
+
+        let r = Reconciliation::new(1000, 10180, &transactions);
+        assert_eq!(r.discrepancy(), -410);
+    }
+}
@@ -0,0 +1,427 @@
+use crate::config::{Config, MatcherType, Payee, PayeeNormalizerConfig};
+use crate::diagnostics::Diagnostic;
+use crate::transaction::payee::{Matcher, PayeeNormalizer};
+use crate::transaction::payee_creator::{suggest_contains_string, suggest_payee_id};
+use crate::transaction::{ChangeType, Transaction};
+use crate::util::{cents_to_currency, currency_to_cents, currency_to_string_without_delim};
+use serde_json::json;
+use std::collections::HashMap;
+use std::fmt;
+
+/// What a run couldn't normalize or categorize, with suggested payees for the raw payees that
+/// weren't normalized.
+#[derive(Debug)]
+pub struct Report {
+    account_name: String,
+    unnormalized: Vec<Group>,
+    // Raw payee, payee ID and score
+    fuzzy_matched: Vec<(String, String, f64)>,
+    uncategorized: Vec<Group>,
+    suggestions: Vec<Suggestion>,
+}
+
+/// The transactions of one payee or raw payee
+#[derive(Debug)]
+struct Group {
+    name: String,
+    count: usize,
+    // Signed, in cents
+    total: i64,
+}
+
+#[derive(Debug)]
+struct Suggestion {
+    payee: Payee,
+    raw_payees: Vec<String>,
+}
+
+impl Report {
+    pub fn new(config: &Config, transactions: &[Transaction]) -> Report {
+        let transactions: Vec<&Transaction> = transactions
+            .iter()
+            .filter(|t| t.change != ChangeType::Remove)
+            .collect();
+        let unnormalized = group(
+            transactions
+                .iter()
+                .filter(|t| t.normalized_payee_id.is_none())
+                .map(|t| (t.raw_payee_name.as_str(), *t)),
+        );
+        let uncategorized = group(
+            transactions
+                .iter()
+                .filter(|t| t.category.is_none() && t.transfer.is_none())
+                .map(|t| (t.payee(), *t)),
+        );
+
+        let mut raw_payees: Vec<&str> = transactions
+            .iter()
+            .filter(|t| t.normalized_payee_id.is_some())
+            .map(|t| t.raw_payee_name.as_str())
+            .collect();
+        raw_payees.sort();
+        raw_payees.dedup();
+        let fuzzy_matched = raw_payees
+            .iter()
+            .filter_map(|s| match PayeeNormalizer::match_payee(config, s) {
+                Option::Some((payee_id, Option::Some(score))) => {
+                    Option::Some((s.to_string(), payee_id.to_owned(), score))
+                }
+                _ => Option::None,
+            })
+            .collect();
+
+        let all_raw_payees: Vec<&str> = transactions
+            .iter()
+            .map(|t| t.raw_payee_name.as_str())
+            .collect();
+        let suggestions = suggest_payees(
+            config.account().payees.keys().cloned().collect(),
+            &unnormalized
+                .iter()
+                .map(|g| g.name.as_str())
+                .collect::<Vec<_>>(),
+            &all_raw_payees,
+        );
+
+        Report {
+            account_name: config.account().name.to_owned(),
+            unnormalized,
+            fuzzy_matched,
+            uncategorized,
+            suggestions,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.unnormalized.is_empty()
+            && self.fuzzy_matched.is_empty()
+            && self.uncategorized.is_empty()
+    }
+
+    /// One diagnostic per raw payee or payee in the report, so that each can be filtered or read
+    /// as JSON on its own
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        let mut diagnostics = Vec::new();
+        for g in &self.unnormalized {
+            diagnostics.push(Diagnostic::info(format!(
+                "Raw payee wasn't normalized: [{}], {}",
+                g.name,
+                g.summary()
+            )));
+        }
+        for (raw_payee, payee_id, score) in &self.fuzzy_matched {
+            diagnostics.push(Diagnostic::info(format!(
+                "Raw payee was fuzzy matched, promote it to an exact normalizer: [{}] => [{}], score {:.2}",
+                raw_payee, payee_id, score
+            )));
+        }
+        for g in &self.uncategorized {
+            diagnostics.push(Diagnostic::info(format!(
+                "Payee wasn't categorized: [{}], {}",
+                g.name,
+                g.summary()
+            )));
+        }
+        for s in &self.suggestions {
+            diagnostics.push(Diagnostic::info(format!(
+                "Suggested payee: [{}] {}: {}, matches {}",
+                s.payee.id,
+                s.payee.name,
+                PayeeNormalizer::describe(&s.payee.normalizers[0]),
+                s.raw_payees.join(", ")
+            )));
+        }
+        if !self.suggestions.is_empty() {
+            diagnostics.push(Diagnostic::info(
+                "Run the `report` subcommand with `--json-patch` to get the suggested payees as a patch for the account config.",
+            ));
+        }
+        diagnostics
+            .into_iter()
+            .map(|d| d.account(&self.account_name))
+            .collect()
+    }
+
+    /// The suggested payees as a JSON patch (RFC 6902) that adds them to the account config file
+    pub fn json_patch(&self) -> serde_json::Value {
+        serde_json::Value::Array(
+            self.suggestions
+                .iter()
+                .map(|s| json!({"op": "add", "path": "/payees/-", "value": s.payee}))
+                .collect(),
+        )
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Report for account [{}]", self.account_name)?;
+        if self.is_empty() {
+            return writeln!(f, "  Every transaction was normalized and categorized.");
+        }
+        if !self.unnormalized.is_empty() {
+            writeln!(f, "  Raw payees that weren't normalized:")?;
+            for g in &self.unnormalized {
+                writeln!(f, "{}", g)?;
+            }
+        }
+        if !self.fuzzy_matched.is_empty() {
+            writeln!(
+                f,
+                "  Raw payees that were fuzzy matched, to promote to exact normalizers:"
+            )?;
+            for (raw_payee, payee_id, score) in &self.fuzzy_matched {
+                writeln!(f, "    {} => [{}], score {:.2}", raw_payee, payee_id, score)?;
+            }
+        }
+        if !self.uncategorized.is_empty() {
+            writeln!(f, "  Payees that weren't categorized:")?;
+            for g in &self.uncategorized {
+                writeln!(f, "{}", g)?;
+            }
+        }
+        if !self.suggestions.is_empty() {
+            writeln!(f, "  Suggested payees:")?;
+            for s in &self.suggestions {
+                let normalizer = &s.payee.normalizers[0];
+                writeln!(
+                    f,
+                    "    [{}] {}: {}, matches {}",
+                    s.payee.id,
+                    s.payee.name,
+                    PayeeNormalizer::describe(normalizer),
+                    s.raw_payees.join(", ")
+                )?;
+            }
+            writeln!(
+                f,
+                "  Run the `report` subcommand with `--json-patch` to get the suggested payees as a patch for the account config."
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Group {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "    {:>4}  {:>12}  {}",
+            self.count,
+            currency_to_string_without_delim(&cents_to_currency(self.total)),
+            self.name
+        )
+    }
+}
+
+impl Group {
+    fn summary(&self) -> String {
+        format!(
+            "{} transaction(s) totalling {}",
+            self.count,
+            currency_to_string_without_delim(&cents_to_currency(self.total))
+        )
+    }
+}
+
+/// Group the transactions by name, most frequent first, then by the largest total
+fn group<'a>(transactions: impl Iterator<Item = (&'a str, &'a Transaction)>) -> Vec<Group> {
+    let mut groups: HashMap<&str, Group> = HashMap::new();
+    for (name, t) in transactions {
+        let g = groups.entry(name).or_insert_with(|| Group {
+            name: name.to_owned(),
+            count: 0,
+            total: 0,
+        });
+        g.count += 1;
+        g.total += currency_to_cents(&t.signed_amount());
+    }
+    let mut groups: Vec<Group> = groups.into_values().collect();
+    groups.sort_by(|a, b| {
+        b.count
+            .cmp(&a.count)
+            .then(b.total.abs().cmp(&a.total.abs()))
+            .then(a.name.cmp(&b.name))
+    });
+    groups
+}
+
+/// Suggest a payee for each set of unnormalized raw payees that start with the same words,
+/// ignoring store numbers. The normalizer is a `Contains` matcher if that matches nothing else in
+/// the batch, or else a `Regex` anchored at the start. Sets that neither would match alone are
+/// left out.
+fn suggest_payees(
+    mut ids: Vec<String>,
+    unnormalized: &[&str],
+    raw_payees: &[&str],
+) -> Vec<Suggestion> {
+    // Shorter prefixes first, so longer ones that start with them are merged into them
+    let mut prefixes: Vec<(String, &str)> = unnormalized
+        .iter()
+        .map(|s| (suggest_contains_string(s), *s))
+        // Raw payees that are only a reference number have no name to suggest
+        .filter(|(prefix, _)| prefix.chars().any(char::is_alphabetic))
+        .collect();
+    prefixes.sort_by(|(a, _), (b, _)| a.len().cmp(&b.len()).then(a.cmp(b)));
+    let mut groups: Vec<(String, Vec<String>)> = Vec::new();
+    for (prefix, raw_payee) in prefixes {
+        let words = |s: &str| s.split_whitespace().map(str::to_owned).collect::<Vec<_>>();
+        match groups
+            .iter_mut()
+            .find(|(p, _)| words(&prefix).starts_with(&words(p)))
+        {
+            Option::Some((_, members)) => members.push(raw_payee.to_owned()),
+            Option::None => groups.push((prefix, vec![raw_payee.to_owned()])),
+        }
+    }
+
+    let mut suggestions = Vec::new();
+    for (prefix, mut members) in groups {
+        let matches_only_members = |normalizer: &PayeeNormalizerConfig| {
+            Matcher::new(normalizer).is_ok_and(|m| {
+                raw_payees
+                    .iter()
+                    .all(|s| m.score(s).is_none() || members.iter().any(|m| m == s))
+            })
+        };
+        let candidates = [
+            MatcherType::Contains {
+                contains_string: prefix.to_owned(),
+            },
+            MatcherType::Regex {
+                regex_string: format!("^{}", regex::escape(&prefix)),
+            },
+        ];
+        let Option::Some(normalizer) = candidates
+            .into_iter()
+            .map(|normalizer_type| PayeeNormalizerConfig {
+                normalizer_type,
+                ignore_case: true,
+            })
+            .find(|n| matches_only_members(n))
+        else {
+            continue;
+        };
+
+        let base_id = suggest_payee_id(&prefix);
+        let mut id = base_id.to_owned();
+        let mut n = 1;
+        while ids.contains(&id) {
+            n += 1;
+            id = format!("{}-{}", base_id, n);
+        }
+        ids.push(id.to_owned());
+        members.sort();
+        suggestions.push(Suggestion {
+            payee: Payee {
+                id,
+                name: title_case(&prefix),
+                category_ids: Option::None,
+                normalizers: vec![normalizer],
+            },
+            raw_payees: members,
+        });
+    }
+    suggestions
+}
+
+fn title_case(s: &str) -> String {
+    s.split_whitespace()
+        .map(|w| {
+            let lower = w.to_lowercase();
+            let mut chars = lower.chars();
+            match chars.next() {
+                Option::Some(c) => c.to_uppercase().chain(chars).collect(),
+                Option::None => String::new(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod test {
+    use crate::transaction::payee::PayeeNormalizer;
+    use crate::transaction::report::{Group, Report, suggest_payees};
+
+    #[test]
+    fn test_suggest_payees() {
+        let raw_payees = [
+            "SQ *BLUE BOTTLE 0231",
+            "SQ *BLUE BOTTLE COFFEE",
+            "SHELL OIL 5744",
+            "PAYPAL *SHELL OIL",
+            "#1234",
+        ];
+        let unnormalized = [
+            "SQ *BLUE BOTTLE 0231",
+            "SQ *BLUE BOTTLE COFFEE",
+            "SHELL OIL 5744",
+            "#1234",
+        ];
+        let suggestions =
+            suggest_payees(vec![String::from("shell-oil")], &unnormalized, &raw_payees);
+        let described: Vec<_> = suggestions
+            .iter()
+            .map(|s| {
+                (
+                    s.payee.id.as_str(),
+                    PayeeNormalizer::describe(&s.payee.normalizers[0]),
+                    s.raw_payees.len(),
+                )
+            })
+            .collect();
+        // Contains [SHELL OIL] would also match the PayPal payment
+        assert_eq!(
+            described,
+            vec![
+                (
+                    "shell-oil-2",
+                    String::from("Regex [^SHELL OIL], ignoreCase true"),
+                    1
+                ),
+                (
+                    "sq-blue-bottle",
+                    String::from("Contains [SQ *BLUE BOTTLE], ignoreCase true"),
+                    2
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        let report = Report {
+            account_name: String::from("Checking"),
+            unnormalized: vec![Group {
+                name: String::from("SHELL OIL 5744"),
+                count: 2,
+                total: -8050,
+            }],
+            fuzzy_matched: vec![(
+                String::from("STARBUCKS 55"),
+                String::from("starbucks"),
+                0.875,
+            )],
+            uncategorized: Vec::new(),
+            suggestions: Vec::new(),
+        };
+        let diagnostics = report.diagnostics();
+        assert_eq!(
+            diagnostics
+                .iter()
+                .map(|d| d.message.as_str())
+                .collect::<Vec<_>>(),
+            vec![
+                "Raw payee wasn't normalized: [SHELL OIL 5744], 2 transaction(s) totalling -80.50",
+                "Raw payee was fuzzy matched, promote it to an exact normalizer: [STARBUCKS 55] => [starbucks], score 0.88",
+            ]
+        );
+        assert!(
+            diagnostics
+                .iter()
+                .all(|d| d.account.as_deref() == Option::Some("Checking"))
+        );
+    }
+}
@@ -0,0 +1,548 @@
+use crate::config::{Category, Config};
+use crate::transaction::{Transaction, TransactionStatus, TransactionType};
+use crate::util::{cents_to_currency, currency_to_cents, currency_to_string_without_delim};
+use currency::Currency;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Flex, Layout, Rect};
+use ratatui::style::{Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{
+    Block, Clear, List, ListItem, ListState, Paragraph, Row, Table, TableState,
+};
+use ratatui::{DefaultTerminal, Frame};
+
+const HELP: &str =
+    "↑/↓ move  f filter  c category  m memo  s split  e exclude  u undo  w export  q quit";
+
+/// Full screen review of an imported batch before it's exported.
+#[derive(Debug)]
+pub struct Review {}
+
+impl Review {
+    /// Show the review screen. Returns the transactions to export once the review is confirmed,
+    /// or `None` if the review was cancelled. Excluded transactions are not returned, so they
+    /// aren't recorded as exported either: excluding a transaction only postpones it, and a later
+    /// run with `--since-last-run` offers it again.
+    pub fn run(
+        config: &Config,
+        transactions: Vec<Transaction>,
+    ) -> anyhow::Result<Option<Vec<Transaction>>> {
+        let mut state = ReviewState::new(config.categories().collect(), transactions);
+        let mut terminal = ratatui::try_init()?;
+        let r = state.event_loop(&mut terminal);
+        ratatui::restore();
+        r?;
+
+        match state.outcome {
+            Outcome::Export => Ok(Option::Some(
+                state
+                    .rows
+                    .into_iter()
+                    .filter(|r| !r.excluded)
+                    .map(|r| r.transaction)
+                    .collect(),
+            )),
+            _ => Ok(Option::None),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct ReviewRow {
+    transaction: Transaction,
+    excluded: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Filter {
+    All,
+    Uncategorized,
+    Unnormalized,
+    Pending,
+}
+
+impl Filter {
+    fn next(self) -> Filter {
+        match self {
+            Filter::All => Filter::Uncategorized,
+            Filter::Uncategorized => Filter::Unnormalized,
+            Filter::Unnormalized => Filter::Pending,
+            Filter::Pending => Filter::All,
+        }
+    }
+
+    fn includes(self, t: &Transaction) -> bool {
+        match self {
+            Filter::All => true,
+            Filter::Uncategorized => t.category.is_none(),
+            Filter::Unnormalized => t.normalized_payee_id.is_none(),
+            Filter::Pending => t.status == TransactionStatus::Pending,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Filter::All => "all",
+            Filter::Uncategorized => "uncategorized",
+            Filter::Unnormalized => "unnormalized",
+            Filter::Pending => "pending",
+        }
+    }
+}
+
+enum Mode {
+    Browse,
+    SelectCategory(ListState),
+    EditMemo(String),
+    Split(String),
+    Confirm(Outcome),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Outcome {
+    Pending,
+    Export,
+    Quit,
+}
+
+struct ReviewState<'a> {
+    categories: Vec<&'a Category>,
+    rows: Vec<ReviewRow>,
+    history: Vec<Vec<ReviewRow>>,
+    filter: Filter,
+    table: TableState,
+    mode: Mode,
+    message: Option<String>,
+    outcome: Outcome,
+}
+
+impl<'a> ReviewState<'a> {
+    fn new(mut categories: Vec<&'a Category>, transactions: Vec<Transaction>) -> ReviewState<'a> {
+        categories.sort_by(|a, b| a.name.cmp(&b.name));
+        ReviewState {
+            categories,
+            rows: transactions
+                .into_iter()
+                .map(|transaction| ReviewRow {
+                    transaction,
+                    excluded: false,
+                })
+                .collect(),
+            history: Vec::new(),
+            filter: Filter::All,
+            table: TableState::default().with_selected(0),
+            mode: Mode::Browse,
+            message: Option::None,
+            outcome: Outcome::Pending,
+        }
+    }
+
+    fn event_loop(&mut self, terminal: &mut DefaultTerminal) -> anyhow::Result<()> {
+        while self.outcome == Outcome::Pending {
+            terminal.draw(|f| self.draw(f))?;
+            if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press {
+                    self.handle_key(key);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Indices into `rows` of the rows shown with the current filter
+    fn visible(&self) -> Vec<usize> {
+        self.rows
+            .iter()
+            .enumerate()
+            .filter(|(_, r)| self.filter.includes(&r.transaction))
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    fn selected_row(&self) -> Option<usize> {
+        self.table
+            .selected()
+            .and_then(|i| self.visible().get(i).copied())
+    }
+
+    /// Save the current rows so the next change can be undone
+    fn checkpoint(&mut self) {
+        self.history.push(self.rows.clone());
+    }
+
+    fn handle_key(&mut self, key: KeyEvent) {
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            self.outcome = Outcome::Quit;
+            return;
+        }
+        self.message = Option::None;
+        let mode = std::mem::replace(&mut self.mode, Mode::Browse);
+        self.mode = match mode {
+            Mode::Browse => self.handle_browse_key(key),
+            Mode::SelectCategory(list) => self.handle_category_key(key, list),
+            Mode::EditMemo(input) => self.handle_memo_key(key, input),
+            Mode::Split(input) => self.handle_split_key(key, input),
+            Mode::Confirm(outcome) => {
+                if let KeyCode::Char('y') | KeyCode::Enter = key.code {
+                    self.outcome = outcome;
+                }
+                Mode::Browse
+            }
+        };
+    }
+
+    fn handle_browse_key(&mut self, key: KeyEvent) -> Mode {
+        let visible_len = self.visible().len();
+        match key.code {
+            KeyCode::Down | KeyCode::Char('j') => {
+                let i = self.table.selected().map_or(0, |i| i + 1);
+                self.table
+                    .select(Option::Some(i.min(visible_len.saturating_sub(1))));
+            }
+            KeyCode::Up | KeyCode::Char('k') => {
+                let i = self.table.selected().map_or(0, |i| i.saturating_sub(1));
+                self.table.select(Option::Some(i));
+            }
+            KeyCode::Char('f') => {
+                self.filter = self.filter.next();
+                self.table.select(Option::Some(0));
+            }
+            KeyCode::Char('u') => match self.history.pop() {
+                Option::Some(rows) => self.rows = rows,
+                Option::None => self.message = Option::Some(String::from("Nothing to undo")),
+            },
+            KeyCode::Char('w') => return Mode::Confirm(Outcome::Export),
+            KeyCode::Char('q') | KeyCode::Esc => return Mode::Confirm(Outcome::Quit),
+            KeyCode::Char('e') => {
+                if let Option::Some(i) = self.selected_row() {
+                    self.checkpoint();
+                    self.rows[i].excluded = !self.rows[i].excluded;
+                }
+            }
+            KeyCode::Char('c') if self.selected_row().is_some() => {
+                return Mode::SelectCategory(ListState::default().with_selected(Some(0)));
+            }
+            KeyCode::Char('m') => {
+                if let Option::Some(i) = self.selected_row() {
+                    let memo = self.rows[i].transaction.memo.to_owned();
+                    return Mode::EditMemo(memo.unwrap_or_default());
+                }
+            }
+            KeyCode::Char('s') if self.selected_row().is_some() => {
+                return Mode::Split(String::new());
+            }
+            _ => {}
+        }
+        Mode::Browse
+    }
+
+    fn handle_category_key(&mut self, key: KeyEvent, mut list: ListState) -> Mode {
+        // The first option clears the category
+        let len = self.categories.len() + 1;
+        match key.code {
+            KeyCode::Esc => return Mode::Browse,
+            KeyCode::Down | KeyCode::Char('j') => {
+                let i = list.selected().map_or(0, |i| (i + 1).min(len - 1));
+                list.select(Option::Some(i));
+            }
+            KeyCode::Up | KeyCode::Char('k') => {
+                let i = list.selected().map_or(0, |i| i.saturating_sub(1));
+                list.select(Option::Some(i));
+            }
+            KeyCode::Enter => {
+                if let (Option::Some(row), Option::Some(choice)) =
+                    (self.selected_row(), list.selected())
+                {
+                    self.checkpoint();
+                    self.rows[row].transaction.category = choice
+                        .checked_sub(1)
+                        .and_then(|i| self.categories.get(i))
+                        .map(|c| c.name.to_owned());
+                }
+                return Mode::Browse;
+            }
+            _ => {}
+        }
+        Mode::SelectCategory(list)
+    }
+
+    fn handle_memo_key(&mut self, key: KeyEvent, mut input: String) -> Mode {
+        match key.code {
+            KeyCode::Esc => return Mode::Browse,
+            KeyCode::Enter => {
+                if let Option::Some(row) = self.selected_row() {
+                    self.checkpoint();
+                    self.rows[row].transaction.memo = if input.trim().is_empty() {
+                        Option::None
+                    } else {
+                        Option::Some(input.trim().to_owned())
+                    };
+                }
+                return Mode::Browse;
+            }
+            KeyCode::Backspace => {
+                input.pop();
+            }
+            KeyCode::Char(c) => input.push(c),
+            _ => {}
+        }
+        Mode::EditMemo(input)
+    }
+
+    fn handle_split_key(&mut self, key: KeyEvent, mut input: String) -> Mode {
+        match key.code {
+            KeyCode::Esc => return Mode::Browse,
+            KeyCode::Enter => {
+                if let Option::Some(row) = self.selected_row() {
+                    if let Err(e) = self.split(row, &input) {
+                        self.message = Option::Some(e);
+                        return Mode::Split(input);
+                    }
+                }
+                return Mode::Browse;
+            }
+            KeyCode::Backspace => {
+                input.pop();
+            }
+            KeyCode::Char(c) if c.is_ascii_digit() || c == '.' => input.push(c),
+            _ => {}
+        }
+        Mode::Split(input)
+    }
+
+    /// Move `amount` out of the transaction at `row` into a new transaction directly after it
+    fn split(&mut self, row: usize, amount: &str) -> Result<(), String> {
+        let split_cents = Currency::from_str(amount)
+            .map(|c| currency_to_cents(&c))
+            .map_err(|_| format!("[{}] is not a valid amount", amount))?;
+        let original_cents = currency_to_cents(&self.rows[row].transaction.amount);
+        if split_cents <= 0 || split_cents >= original_cents {
+            return Err(format!(
+                "The split amount must be more than 0 and less than {}",
+                currency_to_string_without_delim(&self.rows[row].transaction.amount)
+            ));
+        }
+
+        self.checkpoint();
+        // Both parts are recorded as the imported transaction once they're exported
+        let key = self.rows[row].transaction.imported_key();
+        self.rows[row].transaction.split_from = Option::Some(key);
+        let mut split = self.rows[row].clone();
+        split.transaction.amount = cents_to_currency(split_cents);
+        split.transaction.category = Option::None;
+        self.rows[row].transaction.amount = cents_to_currency(original_cents - split_cents);
+        self.rows.insert(row + 1, split);
+        Ok(())
+    }
+
+    fn draw(&mut self, f: &mut Frame) {
+        let [title_area, table_area, help_area] = Layout::vertical([
+            Constraint::Length(1),
+            Constraint::Min(1),
+            Constraint::Length(1),
+        ])
+        .areas(f.area());
+
+        let visible = self.visible();
+        let excluded = self.rows.iter().filter(|r| r.excluded).count();
+        f.render_widget(
+            Paragraph::new(format!(
+                "Review: {} transaction(s), {} excluded, showing {} ({})",
+                self.rows.len(),
+                excluded,
+                visible.len(),
+                self.filter.name()
+            )),
+            title_area,
+        );
+
+        let rows: Vec<Row> = visible
+            .iter()
+            .map(|i| {
+                let r = &self.rows[*i];
+                let t = &r.transaction;
+                let amount = match t.transaction_type {
+                    TransactionType::Debit => {
+                        format!("-{}", currency_to_string_without_delim(&t.amount))
+                    }
+                    TransactionType::Credit => currency_to_string_without_delim(&t.amount),
+                };
+                let status = match t.status {
+                    TransactionStatus::Pending => "pending",
+                    TransactionStatus::Cleared => "",
+                };
+                let row = Row::new(vec![
+                    t.date.format("%Y-%m-%d").to_string(),
+                    t.payee().to_owned(),
+                    t.category.to_owned().unwrap_or_default(),
+                    amount,
+                    status.to_owned(),
+                    t.memo.to_owned().unwrap_or_default(),
+                ]);
+                if r.excluded {
+                    row.style(Style::new().add_modifier(Modifier::CROSSED_OUT | Modifier::DIM))
+                } else {
+                    row
+                }
+            })
+            .collect();
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Length(10),
+                Constraint::Percentage(30),
+                Constraint::Percentage(20),
+                Constraint::Length(12),
+                Constraint::Length(7),
+                Constraint::Fill(1),
+            ],
+        )
+        .header(
+            Row::new(vec![
+                "Date", "Payee", "Category", "Amount", "Status", "Memo",
+            ])
+            .style(Style::new().add_modifier(Modifier::BOLD)),
+        )
+        .row_highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+        f.render_stateful_widget(table, table_area, &mut self.table);
+
+        let help = self.message.as_deref().unwrap_or(HELP);
+        f.render_widget(Paragraph::new(help), help_area);
+
+        match self.mode {
+            Mode::Browse => {}
+            Mode::SelectCategory(ref mut list) => {
+                let items: Vec<ListItem> = std::iter::once(String::from("(none)"))
+                    .chain(self.categories.iter().map(|c| c.name.to_owned()))
+                    .map(ListItem::new)
+                    .collect();
+                let area = popup_area(f.area(), 40, items.len() as u16 + 2);
+                f.render_widget(Clear, area);
+                f.render_stateful_widget(
+                    List::new(items)
+                        .block(Block::bordered().title("Category"))
+                        .highlight_style(Style::new().add_modifier(Modifier::REVERSED)),
+                    area,
+                    list,
+                );
+            }
+            Mode::EditMemo(ref input) => render_input(f, "Memo", input),
+            Mode::Split(ref input) => render_input(f, "Amount to split off", input),
+            Mode::Confirm(outcome) => {
+                let question = match outcome {
+                    Outcome::Export => format!(
+                        "Export {} transaction(s)? (y/n)",
+                        self.rows.len() - excluded
+                    ),
+                    _ => String::from("Quit without exporting? (y/n)"),
+                };
+                render_input(f, "Confirm", &question);
+            }
+        }
+    }
+}
+
+fn render_input(f: &mut Frame, title: &str, input: &str) {
+    let area = popup_area(f.area(), 60, 3);
+    f.render_widget(Clear, area);
+    f.render_widget(
+        Paragraph::new(Line::from(input)).block(Block::bordered().title(title.to_owned())),
+        area,
+    );
+}
+
+fn popup_area(area: Rect, width: u16, height: u16) -> Rect {
+    let [area] = Layout::horizontal([Constraint::Length(width)])
+        .flex(Flex::Center)
+        .areas(area);
+    let [area] = Layout::vertical([Constraint::Length(height.min(area.height))])
+        .flex(Flex::Center)
+        .areas(area);
+    area
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config::Category;
+    use crate::transaction::review::ReviewState;
+    use crate::transaction::test::transaction;
+    use crate::util::currency_to_cents;
+    use ratatui::crossterm::event::{KeyCode, KeyEvent};
+    use serde_json::json;
+
+    fn press(state: &mut ReviewState, keys: &str) {
+        for c in keys.chars() {
+            let code = match c {
+                '\n' => KeyCode::Enter,
+                c => KeyCode::Char(c),
+            };
+            state.handle_key(KeyEvent::from(code));
+        }
+    }
+
+    fn amounts(state: &ReviewState) -> Vec<i64> {
+        state
+            .rows
+            .iter()
+            .map(|r| currency_to_cents(&r.transaction.amount))
+            .collect()
+    }
+
+    #[test]
+    fn test_split_and_undo() {
+        let mut state = ReviewState::new(
+            Vec::new(),
+            vec![
+                transaction(2, "COSTCO", "-100.00"),
+                transaction(3, "SHELL OIL", "-40.00"),
+            ],
+        );
+        press(&mut state, "s35.25\n");
+        assert_eq!(amounts(&state), vec![6475, 3525, 4000]);
+        assert_eq!(amounts(&state)[..2].iter().sum::<i64>(), 10000);
+        assert_eq!(state.rows[1].transaction.raw_payee_name, "COSTCO");
+        let key = transaction(2, "COSTCO", "-100.00").state_key();
+        assert_eq!(state.rows[0].transaction.imported_key(), key);
+        assert_eq!(state.rows[1].transaction.imported_key(), key);
+
+        // The split must leave some of the amount in the original transaction
+        press(&mut state, "s64.75\n");
+        assert!(state.message.is_some());
+        state.handle_key(KeyEvent::from(KeyCode::Esc));
+        assert_eq!(amounts(&state), vec![6475, 3525, 4000]);
+
+        press(&mut state, "u");
+        assert_eq!(amounts(&state), vec![10000, 4000]);
+        press(&mut state, "u");
+        assert_eq!(state.message.as_deref(), Option::Some("Nothing to undo"));
+    }
+
+    #[test]
+    fn test_exclude_and_filter() {
+        let categories: Vec<Category> =
+            serde_json::from_value(json!([{"id": "gas", "name": "Gas"}])).unwrap();
+        let mut state = ReviewState::new(
+            categories.iter().collect(),
+            vec![
+                transaction(2, "COSTCO", "-100.00"),
+                transaction(3, "SHELL OIL", "-40.00"),
+            ],
+        );
+        // Categorize the second row with the first category, after the option to clear it
+        press(&mut state, "jcj\n");
+        assert_eq!(
+            state.rows[1].transaction.category.as_deref(),
+            Option::Some("Gas")
+        );
+
+        // Only the first row is uncategorized, so it's the one excluded
+        press(&mut state, "fke");
+        assert_eq!(state.visible(), vec![0]);
+        assert!(state.rows[0].excluded && !state.rows[1].excluded);
+
+        press(&mut state, "u");
+        assert!(!state.rows[0].excluded);
+        press(&mut state, "u");
+        assert_eq!(state.rows[1].transaction.category, Option::None);
+    }
+}
@@ -0,0 +1,78 @@
+use crate::config::{Config, SortOrder};
+use crate::transaction::{ChangeType, Transaction};
+use crate::util::{cents_to_currency, currency_to_cents};
+use anyhow::anyhow;
+
+/// Set the running balance of each transaction, starting from the opening balance. Must be
+/// called after the transactions are sorted for export.
+pub fn compute_balances(
+    config: &Config,
+    mut transactions: Vec<Transaction>,
+) -> anyhow::Result<Vec<Transaction>> {
+    let opening_balance = match config.opening_balance() {
+        Option::Some(b) => b,
+        Option::None => {
+            if let Option::Some(c) = config.dst_format().and_then(|f| f.balance_config.as_ref()) {
+                return Err(anyhow!(
+                    "An opening balance is required to export the balance field [{}].",
+                    c.field_name
+                ));
+            }
+            return Ok(transactions);
+        }
+    };
+    let sort_order = match config.sort_by() {
+        Option::Some(_) => config.sort_order(),
+        Option::None => Option::None,
+    };
+    let mut balance = currency_to_cents(&opening_balance);
+    for i in chronological_order(&transactions, sort_order) {
+        let t = &mut transactions[i];
+        // Removals of previously exported transactions don't change the balance
+        if t.change != ChangeType::Remove {
+            balance += currency_to_cents(&t.signed_amount());
+        }
+        t.balance = Option::Some(cents_to_currency(balance));
+    }
+    Ok(transactions)
+}
+
+/// The indexes of the transactions, oldest first. Sorted transactions keep their relative order,
+/// so that the balances read in sequence down the exported file.
+fn chronological_order(transactions: &[Transaction], sort_order: Option<SortOrder>) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..transactions.len()).collect();
+    match sort_order {
+        Option::Some(SortOrder::Ascending) => {}
+        Option::Some(SortOrder::Descending) => order.reverse(),
+        Option::None => order.sort_by_key(|i| transactions[*i].date),
+    }
+    order
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config::SortOrder;
+    use crate::transaction::test::transaction;
+    use crate::transaction::transaction_io::balance::chronological_order;
+
+    #[test]
+    fn test_chronological_order() {
+        let transactions = vec![
+            transaction(9, "STARBUCKS", "-4.10"),
+            transaction(8, "STARBUCKS", "-4.10"),
+            transaction(9, "STARBUCKS", "-4.10"),
+        ];
+        assert_eq!(
+            chronological_order(&transactions, Option::Some(SortOrder::Ascending)),
+            vec![0, 1, 2]
+        );
+        assert_eq!(
+            chronological_order(&transactions, Option::Some(SortOrder::Descending)),
+            vec![2, 1, 0]
+        );
+        assert_eq!(
+            chronological_order(&transactions, Option::None),
+            vec![1, 0, 2]
+        );
+    }
+}
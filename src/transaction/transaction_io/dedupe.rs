@@ -0,0 +1,135 @@
+use crate::config::{Config, DedupeConfig, DedupeField, DedupePolicy};
+use crate::diagnostics::Diagnostic;
+use crate::transaction::Transaction;
+use crate::util::currency_to_string_without_delim;
+use anyhow::anyhow;
+use std::collections::HashMap;
+
+/// A transaction that duplicates an earlier transaction in the same import
+struct Duplicate {
+    index: usize,
+    original: usize,
+}
+
+pub fn dedupe(config: &Config, transactions: Vec<Transaction>) -> anyhow::Result<Vec<Transaction>> {
+    let dedupe_config = match config.dedupe() {
+        Option::Some(c) => c,
+        Option::None => return Ok(transactions),
+    };
+    let duplicates = find_duplicates(&dedupe_config, &transactions);
+    if duplicates.is_empty() {
+        return Ok(transactions);
+    }
+
+    let descriptions: Vec<String> = duplicates
+        .iter()
+        .map(|d| {
+            format!(
+                "{} (duplicate of {})",
+                transactions[d.index].describe(),
+                transactions[d.original].source_line()
+            )
+        })
+        .collect();
+    match dedupe_config.policy {
+        DedupePolicy::Fail => Err(anyhow!(
+            "Found {} duplicate transaction(s):\n{}",
+            duplicates.len(),
+            descriptions.join("\n")
+        )),
+        DedupePolicy::Warn => {
+            for (d, description) in duplicates.iter().zip(descriptions) {
+                Diagnostic::warning(format!("Possible duplicate transaction: {}", description))
+                    .account(&config.account().name)
+                    .transaction(&transactions[d.index])
+                    .emit();
+            }
+            Ok(transactions)
+        }
+        DedupePolicy::KeepFirst => {
+            for (d, description) in duplicates.iter().zip(descriptions) {
+                Diagnostic::info(format!("Dropped duplicate transaction: {}", description))
+                    .account(&config.account().name)
+                    .transaction(&transactions[d.index])
+                    .emit();
+            }
+            let mut is_duplicate = vec![false; transactions.len()];
+            for d in &duplicates {
+                is_duplicate[d.index] = true;
+            }
+            Ok(transactions
+                .into_iter()
+                .zip(is_duplicate)
+                .filter(|(_, is_duplicate)| !is_duplicate)
+                .map(|(t, _)| t)
+                .collect())
+        }
+    }
+}
+
+fn find_duplicates(c: &DedupeConfig, transactions: &[Transaction]) -> Vec<Duplicate> {
+    let compare_dates = c.key_fields.contains(&DedupeField::Date);
+    // Transactions that match on everything other than the date
+    let mut groups: HashMap<String, Vec<usize>> = HashMap::new();
+    let mut duplicates = Vec::new();
+    for (i, t) in transactions.iter().enumerate() {
+        let group = groups.entry(key(c, t)).or_default();
+        let original = group.iter().copied().find(|j| {
+            let days = (t.date.date() - transactions[*j].date.date()).num_days();
+            !compare_dates || days.unsigned_abs() <= u64::from(c.date_tolerance_days)
+        });
+        match original {
+            Option::Some(original) => duplicates.push(Duplicate { index: i, original }),
+            Option::None => group.push(i),
+        }
+    }
+    duplicates
+}
+
+fn key(c: &DedupeConfig, t: &Transaction) -> String {
+    c.key_fields
+        .iter()
+        .map(|f| match f {
+            // Dates are compared separately to allow for the tolerance
+            DedupeField::Date => String::new(),
+            DedupeField::Amount => currency_to_string_without_delim(&t.signed_amount()),
+            DedupeField::Payee => t.raw_payee_name.to_owned(),
+            DedupeField::Memo => t.memo.to_owned().unwrap_or_default(),
+            DedupeField::Status => format!("{:?}", t.status),
+        })
+        .collect::<Vec<_>>()
+        .join("|")
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config::{DedupeConfig, DedupeField};
+    use crate::transaction::test::transaction;
+    use crate::transaction::transaction_io::dedupe::find_duplicates;
+
+    #[test]
+    fn test_find_duplicates() {
+        let transactions = vec![
+            transaction(9, "STARBUCKS", "-4.10"),
+            transaction(9, "STARBUCKS", "-4.10"),
+            transaction(10, "STARBUCKS", "-4.10"),
+            transaction(9, "COSTCO", "-4.10"),
+        ];
+        let c = DedupeConfig::default();
+        let duplicates = find_duplicates(&c, &transactions);
+        assert_eq!(duplicates.len(), 1);
+        assert_eq!((duplicates[0].index, duplicates[0].original), (1, 0));
+
+        let c = DedupeConfig {
+            date_tolerance_days: 1,
+            ..DedupeConfig::default()
+        };
+        assert_eq!(find_duplicates(&c, &transactions).len(), 2);
+
+        let c = DedupeConfig {
+            key_fields: vec![DedupeField::Date, DedupeField::Amount],
+            ..DedupeConfig::default()
+        };
+        assert_eq!(find_duplicates(&c, &transactions).len(), 2);
+    }
+}
@@ -1,37 +1,120 @@
-use crate::config::{AccountConfigFile, Config};
+use crate::config::{AccountConfigFile, Config, StatementBalances, TransferConfig};
 use crate::config::{AmountFormat, FormatConfigFile};
-use crate::parser::{create_csv_writer, parse_csv_from_reader};
-use crate::transaction::{Transaction, TransactionStatus, TransactionType};
-use crate::util::{currency_to_string_without_delim, get_optional_string};
+use crate::parser::{create_csv_writer, parse_csv_with_lines_from_reader};
+use crate::transaction::{ChangeType, Transaction, TransactionStatus, TransactionType};
+use crate::util::{
+    cents_to_currency, currency_to_cents, currency_to_string_without_delim, get_optional_string,
+};
 use anyhow::anyhow;
 use chrono::NaiveDateTime;
 use csv::Writer;
 use currency::Currency;
 use num::Signed;
+use regex::RegexBuilder;
 use std::collections::HashMap;
 use std::io;
+use std::io::BufRead;
 use std::ops::Neg;
 
 pub fn import_from_configurable_format(
     r: Box<dyn io::Read>,
     f: &FormatConfigFile,
-) -> anyhow::Result<Vec<Transaction>> {
-    let unmapped_transactions: Vec<HashMap<String, String>> = parse_csv_from_reader(r)?;
+) -> anyhow::Result<(Vec<Transaction>, StatementBalances)> {
+    let (rows, balances) = import_rows_from_configurable_format(r, f)?;
+    Ok((rows.into_iter().map(|(t, _)| t).collect(), balances))
+}
+
+/// A transaction and the raw values of the row it was read from, keyed by header
+pub type Row = (Transaction, HashMap<String, String>);
+
+/// Import the transactions along with the raw values of their rows
+pub fn import_rows_from_configurable_format(
+    r: Box<dyn io::Read>,
+    f: &FormatConfigFile,
+) -> anyhow::Result<(Vec<Row>, StatementBalances)> {
+    let (mut balances, line_count, r) = read_preamble(r, f)?;
+    let unmapped_transactions: Vec<(u64, HashMap<String, String>)> =
+        parse_csv_with_lines_from_reader(r, f.delimiter())?;
+
+    let mut rows = Vec::new();
+    for (line, unmapped) in unmapped_transactions {
+        let line = line + line_count as u64;
+        let t = convert_to_transaction(line, &unmapped, f)
+            .map_err(|e| anyhow!("Unable to import line {}: {}", line, e))?;
+        rows.push((t, unmapped));
+    }
+    let (opening, closing) = balances_from_transactions(rows.iter().map(|(t, _)| t));
+    balances.opening = balances.opening.or(opening);
+    balances.closing = balances.closing.or(closing);
+    Ok((rows, balances))
+}
+
+/// Read the lines before the header row. Returns the balances found in the preamble, the number
+/// of lines read, and the rest of the input.
+fn read_preamble(
+    r: Box<dyn io::Read>,
+    f: &FormatConfigFile,
+) -> anyhow::Result<(StatementBalances, usize, Box<dyn io::Read>)> {
+    let c = match f.preamble_config {
+        Option::Some(ref c) => c,
+        Option::None => return Ok((StatementBalances::default(), 0, r)),
+    };
+    let mut r = io::BufReader::new(r);
+    let mut lines = String::new();
+    for _ in 0..c.line_count {
+        r.read_line(&mut lines)
+            .map_err(|e| anyhow!("Unable to read the statement preamble: {}", e))?;
+    }
+    let mut balances = StatementBalances::default();
+    if let Option::Some(ref regex_string) = c.opening_balance_regex {
+        balances.opening = find_amount(regex_string, &lines)?;
+    }
+    if let Option::Some(ref regex_string) = c.closing_balance_regex {
+        balances.closing = find_amount(regex_string, &lines)?;
+    }
+    Ok((balances, c.line_count, Box::new(r)))
+}
+
+/// Find the opening and closing balances from the balance of each transaction. Statements may
+/// list the newest transaction first.
+fn balances_from_transactions<'a>(
+    transactions: impl Iterator<Item = &'a Transaction>,
+) -> (Option<Currency>, Option<Currency>) {
+    let mut with_balance: Vec<&Transaction> =
+        transactions.filter(|t| t.balance.is_some()).collect();
+    if let (Option::Some(first), Option::Some(last)) = (with_balance.first(), with_balance.last()) {
+        if first.date > last.date {
+            with_balance.reverse();
+        }
+    }
+    let opening = with_balance.first().and_then(|t| {
+        t.balance.as_ref().map(|b| {
+            cents_to_currency(currency_to_cents(b) - currency_to_cents(&t.signed_amount()))
+        })
+    });
+    let closing = with_balance.last().and_then(|t| t.balance.to_owned());
+    (opening, closing)
+}
 
-    let mut transactions = Vec::new();
-    for unmapped in unmapped_transactions {
-        let t = convert_to_transaction(unmapped, f)?;
-        transactions.push(t);
+fn find_amount(regex_string: &str, preamble: &str) -> anyhow::Result<Option<Currency>> {
+    let regex = RegexBuilder::new(regex_string).multi_line(true).build()?;
+    let amount = match regex.captures(preamble).and_then(|c| c.get(1)) {
+        Option::Some(m) => m.as_str().trim(),
+        Option::None => return Ok(Option::None),
+    };
+    match get_currency_from_str(amount) {
+        Option::Some(a) => a.map(Option::Some),
+        Option::None => Ok(Option::None),
     }
-    Ok(transactions)
 }
 
 fn convert_to_transaction(
-    unmapped: HashMap<String, String>,
+    line: u64,
+    unmapped: &HashMap<String, String>,
     f: &FormatConfigFile,
 ) -> anyhow::Result<Transaction> {
-    let (amount, transaction_type) = get_amount_and_transaction_type(&unmapped, f)?;
-    let (date_time_string, date_time_format) = get_date(&unmapped, f)?;
+    let (amount, transaction_type) = get_amount_and_transaction_type(unmapped, f)?;
+    let (date_time_string, date_time_format) = get_date(unmapped, f)?;
     let date = match NaiveDateTime::parse_from_str(&date_time_string, &date_time_format) {
         Ok(date) => Ok(date),
         Err(e) => Err(anyhow!(
@@ -44,12 +127,14 @@ fn convert_to_transaction(
 
     Ok(Transaction::builder()
         .date(date)
-        .raw_payee_name(get_raw_payee_name(&unmapped, f)?)
-        .category(get_category(&unmapped, f))
+        .raw_payee_name(get_raw_payee_name(unmapped, f)?)
+        .category(get_category(unmapped, f))
         .transaction_type(transaction_type)
         .amount(amount)
-        .status(get_transaction_status(&unmapped, f)?)
-        .memo(get_memo(&unmapped, f))
+        .status(get_transaction_status(unmapped, f)?)
+        .memo(get_memo(unmapped, f))
+        .balance(get_balance(unmapped, f)?)
+        .line(Option::Some(line))
         .build())
 }
 
@@ -243,6 +328,21 @@ fn get_memo(unmapped: &HashMap<String, String>, f: &FormatConfigFile) -> Option<
         .map(|x| x.to_owned())
 }
 
+fn get_balance(
+    unmapped: &HashMap<String, String>,
+    f: &FormatConfigFile,
+) -> anyhow::Result<Option<Currency>> {
+    match f
+        .balance_config
+        .as_ref()
+        .and_then(|c| unmapped.get(&c.field_name))
+        .and_then(|b| get_currency_from_str(b))
+    {
+        Option::Some(b) => b.map(Option::Some),
+        Option::None => Ok(Option::None),
+    }
+}
+
 fn get_category(unmapped: &HashMap<String, String>, f: &FormatConfigFile) -> Option<String> {
     f.category_config
         .as_ref()
@@ -251,18 +351,21 @@ fn get_category(unmapped: &HashMap<String, String>, f: &FormatConfigFile) -> Opt
 }
 
 /// Assumes CSV
-pub fn export_to_configurable_format(
+/// Each row is a transaction and the account it belongs to.
+pub fn export_to_configurable_format<'a, I>(
     w: Box<dyn io::Write>,
     c: &Config,
-    a: &AccountConfigFile,
     f: &FormatConfigFile,
-    transactions: Vec<Transaction>,
-) -> anyhow::Result<()> {
-    let mut w = create_csv_writer(c.include_header(), w);
+    rows: I,
+) -> anyhow::Result<()>
+where
+    I: IntoIterator<Item = (&'a AccountConfigFile, &'a Transaction)>,
+{
+    let mut w = create_csv_writer(c.include_header(), f.delimiter(), w);
     if c.include_header() {
         write_record(&mut w, &f.field_order)?;
     }
-    for t in &transactions {
+    for (a, t) in rows {
         write_record(&mut w, &convert_to_configurable_format(a, f, t))?;
     }
     Ok(())
@@ -306,14 +409,36 @@ fn convert_to_configurable_format(
     }
 
     // Payee
-    fields.insert(f.payee_config.field_name.to_owned(), t.payee().to_owned());
+    let payee = match t.transfer {
+        Option::Some(ref transfer) => f
+            .transfer_config
+            .as_ref()
+            .map_or_else(
+                || TransferConfig::default().payee_format,
+                |c| c.payee_format.to_owned(),
+            )
+            .replace("{account}", &transfer.account_name),
+        Option::None => t.payee().to_owned(),
+    };
+    fields.insert(f.payee_config.field_name.to_owned(), payee);
 
-    // Category
+    // Category. Transfers aren't income or spending.
     if let Option::Some(ref c) = f.category_config {
-        fields.insert(
-            c.field_name.to_owned(),
-            t.category.to_owned().unwrap_or_default(),
-        );
+        let category = match t.transfer {
+            Option::Some(_) => String::new(),
+            Option::None => t.category.to_owned().unwrap_or_default(),
+        };
+        fields.insert(c.field_name.to_owned(), category);
+    }
+
+    // Transfer
+    if let (Option::Some(c), Option::Some(transfer)) = (&f.transfer_config, &t.transfer) {
+        if let Option::Some(ref field_name) = c.field_name {
+            fields.insert(field_name.to_owned(), transfer.account_name.to_owned());
+        }
+        if let Option::Some(ref field_name) = c.id_field_name {
+            fields.insert(field_name.to_owned(), transfer.id.to_owned());
+        }
     }
 
     // Transaction status
@@ -343,6 +468,32 @@ fn convert_to_configurable_format(
         fields.insert(config.field_name.to_owned(), a.name.to_owned());
     }
 
+    // Balance
+    if let Option::Some(ref c) = f.balance_config {
+        fields.insert(
+            c.field_name.to_owned(),
+            t.balance
+                .as_ref()
+                .map(currency_to_string_without_delim)
+                .unwrap_or_default(),
+        );
+    }
+
+    // ID
+    if let Option::Some(ref c) = f.id_config {
+        fields.insert(c.field_name.to_owned(), t.export_id());
+    }
+
+    // Change type
+    if let Option::Some(ref c) = f.change_config {
+        let change = match t.change {
+            ChangeType::Add => c.add_string.to_owned(),
+            ChangeType::Update => c.update_string.to_owned(),
+            ChangeType::Remove => c.remove_string.to_owned(),
+        };
+        fields.insert(c.field_name.to_owned(), change);
+    }
+
     // Put the fields in the correct order
     let mut r = Vec::new();
     for field in &f.field_order {
@@ -397,3 +548,62 @@ fn get_amount_fields(f: &FormatConfigFile, t: &Transaction) -> Vec<(String, Stri
     }
     r
 }
+
+#[cfg(test)]
+mod test {
+    use crate::config::FormatConfigFile;
+    use crate::config::presets::{PRESETS, preset};
+    use crate::transaction::transaction_io::formats::{
+        convert_to_configurable_format, import_from_configurable_format,
+    };
+    use serde_json::json;
+    use std::fs;
+    use std::path::Path;
+
+    /// Shows every value a preset imports from its sample file
+    fn golden_format() -> FormatConfigFile {
+        serde_json::from_value(json!({
+            "id": "golden", "name": "Golden", "dataFormat": "csv",
+            "fieldOrder": ["Date", "Payee", "Amount", "Status", "Category", "Memo"],
+            "dateTimeConfig": {"dateField": "Date", "dateFormat": "%Y-%m-%d"},
+            "payeeConfig": {"fieldName": "Payee"},
+            "amountConfig": {"format": {"type": "SingleAmountField", "fieldName": "Amount"}},
+            "statusConfig": {"fieldName": "Status", "pendingString": "pending", "clearedString": "cleared"},
+            "categoryConfig": {"fieldName": "Category"},
+            "memoConfig": {"fieldName": "Memo"}
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_presets_import_their_samples() {
+        let golden = golden_format();
+        let account = serde_json::from_value(json!({
+            "id": "checking", "name": "Checking", "formatId": "golden", "payees": []
+        }))
+        .unwrap();
+        for p in PRESETS {
+            let f: FormatConfigFile =
+                serde_json::from_value(preset(p.id).unwrap().value().unwrap()).unwrap();
+            let (transactions, _) =
+                import_from_configurable_format(Box::new(p.sample.as_bytes()), &f).unwrap();
+            let mut w = csv::Writer::from_writer(Vec::new());
+            w.write_record(&golden.field_order).unwrap();
+            for t in &transactions {
+                w.write_record(convert_to_configurable_format(&account, &golden, t))
+                    .unwrap();
+            }
+            let actual = String::from_utf8(w.into_inner().unwrap()).unwrap();
+            let expected = Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("presets")
+                .join(format!("{}.expected.csv", p.id));
+            assert_eq!(
+                actual,
+                fs::read_to_string(&expected).unwrap_or_default(),
+                "{} does not match the import of the {} sample",
+                expected.display(),
+                p.id
+            );
+        }
+    }
+}
@@ -1,59 +1,248 @@
-use crate::config::{Config, SortBy, SortOrder};
-use crate::transaction::{Transaction, TransactionStatus};
+use crate::config::{Config, FormatConfigFile, SortBy, SortOrder, StatementBalances};
+use crate::transaction::payee_creator::PayeeCreator;
+use crate::transaction::{ChangeType, Transaction, TransactionStatus};
 use anyhow::anyhow;
+use std::collections::HashMap;
 use std::fs::File;
 use std::io;
+use std::io::{Cursor, Read};
+use std::path::PathBuf;
 
+mod balance;
+mod dedupe;
 mod formats;
+mod pending;
+mod routing;
+mod state;
+mod transfers;
+
+pub use state::StateFile;
 
 pub struct TransactionIO {}
 
+/// A transaction as it was read from a source file
+pub struct SourceRow<'a> {
+    pub transaction: Transaction,
+    /// The format the file was imported with
+    pub format: &'a FormatConfigFile,
+    /// The raw values of the row, keyed by header
+    pub values: HashMap<String, String>,
+}
+
 impl TransactionIO {
-    pub fn import(config: &Config) -> anyhow::Result<Vec<Transaction>> {
-        let r: Box<dyn io::Read> = match config.src_file() {
-            Option::Some(f) => {
-                let f = File::open(f).map_err(|e| {
-                    anyhow!(
-                        "An error occurred while trying to open file [{}]: {}",
-                        f.to_str().unwrap_or("Invalid file name"),
-                        e
-                    )
-                })?;
-                Box::new(io::BufReader::new(f))
-            }
-            Option::None => Box::new(io::stdin()),
-        };
-        let transactions = formats::import_from_configurable_format(r, config.src_format())?;
+    pub fn import(config: &mut Config) -> anyhow::Result<Vec<Transaction>> {
+        let transactions = TransactionIO::import_uncategorized(config)?;
+        TransactionIO::categorize(config, transactions)
+    }
+
+    /// Import and normalize the transactions without categorizing them, so that transfers can be
+    /// detected across accounts first.
+    pub fn import_uncategorized(config: &mut Config) -> anyhow::Result<Vec<Transaction>> {
+        let mut transactions = Vec::new();
+        let mut balances = StatementBalances::default();
+        for path in sources(config) {
+            let name = source_name(path);
+            let contents = read_src_file(path)?;
+            let (imported, b) = routing::format_for_file(
+                config.src_formats(),
+                &config.account().format_id,
+                path.map(PathBuf::as_path),
+                &contents,
+            )
+            .and_then(|f| {
+                formats::import_from_configurable_format(Box::new(Cursor::new(contents)), f)
+            })
+            .map_err(|e| anyhow!("Unable to import file [{}]: {}", name, e))?;
+            transactions.extend(imported.into_iter().map(|mut t| {
+                t.source_file = Option::Some(name.to_owned());
+                t
+            }));
+            // The statements are expected to be in order
+            balances.opening = balances.opening.or(b.opening);
+            balances.closing = b.closing.or(balances.closing);
+        }
+        config.set_statement_balances(balances);
+        state::assign_occurrences(&mut transactions);
         let transactions = filter(config, transactions);
-        let transactions = normalize_and_categorize(config, transactions);
+        let transactions = dedupe::dedupe(config, transactions)?;
+        let transactions = pending::remove_superseded(config, transactions);
+        let transactions = state::remove_exported(config, transactions)?;
+        // Removals of previously exported transactions don't need a payee
+        let (removals, transactions): (Vec<_>, Vec<_>) = transactions
+            .into_iter()
+            .partition(|t| t.change == ChangeType::Remove);
+        let mut transactions = normalize(config, transactions)?;
+        transactions.extend(removals);
+        Ok(transactions)
+    }
+
+    /// The raw payees of the source files, before anything is filtered or normalized. Nothing is
+    /// read from stdin.
+    pub fn raw_payees(config: &Config) -> anyhow::Result<Vec<String>> {
+        if config.src_files().is_empty() {
+            return Ok(Vec::new());
+        }
+        let rows = TransactionIO::import_rows(config)?;
+        Ok(rows
+            .into_iter()
+            .map(|r| r.transaction.raw_payee_name)
+            .collect())
+    }
+
+    /// The transactions of the source files as they were read, before anything is filtered or
+    /// normalized, with the format and raw values of each row. Reads stdin if there are no source
+    /// files.
+    pub fn import_rows(config: &Config) -> anyhow::Result<Vec<SourceRow<'_>>> {
+        let mut rows = Vec::new();
+        for path in sources(config) {
+            let name = source_name(path);
+            let contents = read_src_file(path)?;
+            let format = routing::format_for_file(
+                config.src_formats(),
+                &config.account().format_id,
+                path.map(PathBuf::as_path),
+                &contents,
+            )
+            .map_err(|e| anyhow!("Unable to import file [{}]: {}", name, e))?;
+            let (imported, _) = formats::import_rows_from_configurable_format(
+                Box::new(Cursor::new(contents)),
+                format,
+            )
+            .map_err(|e| anyhow!("Unable to import file [{}]: {}", name, e))?;
+            rows.extend(imported.into_iter().map(|(mut transaction, values)| {
+                transaction.source_file = Option::Some(name.to_owned());
+                SourceRow {
+                    transaction,
+                    format,
+                    values,
+                }
+            }));
+        }
+        Ok(rows)
+    }
+
+    /// Mark the transactions that are transfers between the accounts. `batches` holds the
+    /// imported transactions of each account, in the same order as `configs`.
+    pub fn detect_transfers(configs: &[&Config], batches: &mut [Vec<Transaction>]) {
+        transfers::detect_transfers(configs, batches);
+    }
+
+    /// Categorize the imported transactions. Transfers and removals aren't categorized.
+    pub fn categorize(
+        config: &mut Config,
+        mut transactions: Vec<Transaction>,
+    ) -> anyhow::Result<Vec<Transaction>> {
+        for t in transactions
+            .iter_mut()
+            .filter(|t| t.transfer.is_none() && t.change != ChangeType::Remove)
+        {
+            t.categorize(config)?;
+        }
+        config.save_decisions_template()?;
         Ok(transactions)
     }
 
     pub fn export(config: &Config, transactions: Vec<Transaction>) -> anyhow::Result<()> {
         // Sort transactions just before exporting
         let transactions = sort(config, transactions);
-        let w: Box<dyn io::Write> = match config.dst_file() {
-            Option::Some(f) => {
-                let f = File::create(f).map_err(|e| {
-                    anyhow!(
-                        "An error occurred while trying to open file [{}]: {}",
-                        f.to_str().unwrap_or("Invalid file name"),
-                        e
-                    )
-                })?;
-                Box::new(io::BufWriter::new(f))
-            }
-            Option::None => Box::new(io::stdout()),
-        };
+        let transactions = balance::compute_balances(config, transactions)?;
+        let w = open_dst_file(config.dst_file())?;
         formats::export_to_configurable_format(
             w,
             config,
-            config.account(),
-            config.dst_format(),
-            transactions,
+            config.require_dst_format()?,
+            transactions.iter().map(|t| (config.account(), t)),
         )?;
+        state::record_exported(config, &transactions)?;
         Ok(())
     }
+
+    /// Export the transactions of several accounts to a single file, sorted together. The
+    /// destination format and sort order of the first account are used.
+    pub fn export_merged(
+        batches: Vec<(&Config, Vec<Transaction>)>,
+        dst_file: Option<&PathBuf>,
+    ) -> anyhow::Result<()> {
+        let first = match batches.first() {
+            Option::Some((c, _)) => *c,
+            Option::None => return Ok(()),
+        };
+        let mut rows = Vec::new();
+        for (config, transactions) in &batches {
+            // Balances are per account
+            let transactions = sort(config, transactions.to_owned());
+            let transactions = balance::compute_balances(config, transactions)?;
+            rows.extend(transactions.into_iter().map(|t| (*config, t)));
+        }
+        if let (Option::Some(_), Option::Some(sort_order)) = (first.sort_by(), first.sort_order()) {
+            rows.sort_by(|(_, a), (_, b)| match sort_order {
+                SortOrder::Ascending => a.date().cmp(b.date()),
+                SortOrder::Descending => a.date().cmp(b.date()).reverse(),
+            });
+        }
+        let w = open_dst_file(dst_file)?;
+        formats::export_to_configurable_format(
+            w,
+            first,
+            first.require_dst_format()?,
+            rows.iter().map(|(c, t)| (c.account(), t)),
+        )?;
+        for (config, transactions) in &batches {
+            state::record_exported(config, transactions)?;
+        }
+        Ok(())
+    }
+}
+
+/// The source files to import, or stdin if there are none
+fn sources(config: &Config) -> Vec<Option<&PathBuf>> {
+    if config.src_files().is_empty() {
+        vec![Option::None]
+    } else {
+        config.src_files().iter().map(Option::Some).collect()
+    }
+}
+
+fn source_name(path: Option<&PathBuf>) -> String {
+    path.map_or_else(
+        || String::from("stdin"),
+        |p| p.to_string_lossy().into_owned(),
+    )
+}
+
+fn read_src_file(f: Option<&PathBuf>) -> anyhow::Result<Vec<u8>> {
+    let mut contents = Vec::new();
+    match f {
+        Option::Some(f) => File::open(f)
+            .and_then(|mut r| r.read_to_end(&mut contents))
+            .map_err(|e| {
+                anyhow!(
+                    "An error occurred while trying to open file [{}]: {}",
+                    f.to_str().unwrap_or("Invalid file name"),
+                    e
+                )
+            })?,
+        Option::None => io::stdin()
+            .read_to_end(&mut contents)
+            .map_err(|e| anyhow!("An error occurred while reading stdin: {}", e))?,
+    };
+    Ok(contents)
+}
+
+fn open_dst_file(f: Option<&PathBuf>) -> anyhow::Result<Box<dyn io::Write>> {
+    match f {
+        Option::Some(f) => {
+            let f = File::create(f).map_err(|e| {
+                anyhow!(
+                    "An error occurred while trying to open file [{}]: {}",
+                    f.to_str().unwrap_or("Invalid file name"),
+                    e
+                )
+            })?;
+            Ok(Box::new(io::BufWriter::new(f)))
+        }
+        Option::None => Ok(Box::new(io::stdout())),
+    }
 }
 
 fn filter(config: &Config, mut transactions: Vec<Transaction>) -> Vec<Transaction> {
@@ -66,15 +255,17 @@ fn filter(config: &Config, mut transactions: Vec<Transaction>) -> Vec<Transactio
     transactions
 }
 
-fn normalize_and_categorize(
-    config: &Config,
+fn normalize(
+    config: &mut Config,
     mut transactions: Vec<Transaction>,
-) -> Vec<Transaction> {
-    transactions.iter_mut().for_each(|t| {
+) -> anyhow::Result<Vec<Transaction>> {
+    for t in transactions.iter_mut() {
         t.normalize_payee(config);
-        t.categorize(config);
-    });
-    transactions
+    }
+    if config.prompt_for_payees() {
+        PayeeCreator::prompt_for_unnormalized(config, &mut transactions)?;
+    }
+    Ok(transactions)
 }
 
 fn sort(config: &Config, mut transactions: Vec<Transaction>) -> Vec<Transaction> {
@@ -0,0 +1,237 @@
+use crate::config::{Config, PendingMatchConfig};
+use crate::diagnostics::Diagnostic;
+use crate::transaction::fuzzy::similarity;
+use crate::transaction::{Transaction, TransactionStatus};
+use crate::util::{currency_to_cents, currency_to_string_without_delim};
+use chrono::NaiveDate;
+use currency::Currency;
+use std::collections::HashMap;
+
+/// The parts of a pending transaction used to find the cleared transaction that replaces it
+pub struct PendingCandidate<'a> {
+    pub date: NaiveDate,
+    pub cents: i64,
+    pub raw_payee: &'a str,
+}
+
+impl<'a> PendingCandidate<'a> {
+    pub fn from_transaction(t: &'a Transaction) -> PendingCandidate<'a> {
+        PendingCandidate {
+            date: t.date.date(),
+            cents: currency_to_cents(&t.signed_amount()),
+            raw_payee: &t.raw_payee_name,
+        }
+    }
+}
+
+/// Find the pending transaction that the cleared transaction most likely replaces. The closest
+/// amount wins, then the closest date.
+pub fn best_match<'a, I>(c: &PendingMatchConfig, candidates: I, t: &Transaction) -> Option<usize>
+where
+    I: IntoIterator<Item = PendingCandidate<'a>>,
+{
+    candidates
+        .into_iter()
+        .enumerate()
+        .filter_map(|(i, p)| match_distance(c, &p, t).map(|d| (d, i)))
+        .min()
+        .map(|(_, i)| i)
+}
+
+/// Returns the difference in cents and days between the pending and cleared transactions, if the
+/// cleared transaction could replace the pending one.
+fn match_distance(
+    c: &PendingMatchConfig,
+    p: &PendingCandidate,
+    t: &Transaction,
+) -> Option<(i64, i64)> {
+    let cents = currency_to_cents(&t.signed_amount());
+    if cents.signum() != p.cents.signum() {
+        return Option::None;
+    }
+    let amount_diff = (cents - p.cents).abs();
+    if amount_diff > amount_tolerance(c, p.cents) {
+        return Option::None;
+    }
+    let days = (t.date.date() - p.date).num_days();
+    if !(0..=i64::from(c.date_window_days)).contains(&days) {
+        return Option::None;
+    }
+    if c.payee_similarity > 0.0
+        && payee_similarity(p.raw_payee, &t.raw_payee_name) < c.payee_similarity
+    {
+        return Option::None;
+    }
+    Option::Some((amount_diff, days))
+}
+
+fn amount_tolerance(c: &PendingMatchConfig, pending_cents: i64) -> i64 {
+    let absolute = c
+        .amount_tolerance
+        .as_ref()
+        .and_then(|a| Currency::from_str(a).ok())
+        .map(|a| currency_to_cents(&a).abs())
+        .unwrap_or_default();
+    let percent = (pending_cents.abs() as f64 * c.amount_tolerance_percent / 100.0).round() as i64;
+    absolute.max(percent)
+}
+
+/// Payees are often reworded once a transaction posts, in either direction, e.g. a pending
+/// `SQ *BLUE BOTTLE` may post as `BLUE BOTTLE COFFEE`.
+fn payee_similarity(a: &str, b: &str) -> f64 {
+    let weights = HashMap::new();
+    similarity(a, b, &weights).max(similarity(b, a, &weights))
+}
+
+/// Drop pending transactions that are replaced by a cleared transaction in the same import. Only
+/// done if the account configures pending matching.
+pub fn remove_superseded(config: &Config, transactions: Vec<Transaction>) -> Vec<Transaction> {
+    let c = match config.pending_match() {
+        Option::Some(c) => c,
+        Option::None => return transactions,
+    };
+    let pending: Vec<usize> = transactions
+        .iter()
+        .enumerate()
+        .filter(|(_, t)| t.status == TransactionStatus::Pending)
+        .map(|(i, _)| i)
+        .collect();
+    let mut superseded = vec![false; transactions.len()];
+    for t in transactions
+        .iter()
+        .filter(|t| t.status == TransactionStatus::Cleared)
+    {
+        let available: Vec<usize> = pending
+            .iter()
+            .copied()
+            .filter(|i| !superseded[*i])
+            .collect();
+        let candidates = available
+            .iter()
+            .map(|i| PendingCandidate::from_transaction(&transactions[*i]));
+        if let Option::Some(j) = best_match(c, candidates, t) {
+            let p = &transactions[available[j]];
+            Diagnostic::info(format!(
+                "Dropped pending transaction: [raw payee: {}], [amount: {}], [date: {}], cleared as [raw payee: {}], [amount: {}], [date: {}]",
+                p.raw_payee_name,
+                currency_to_string_without_delim(&p.signed_amount()),
+                p.date,
+                t.raw_payee_name,
+                currency_to_string_without_delim(&t.signed_amount()),
+                t.date
+            ))
+            .account(&config.account().name)
+            .transaction(p)
+            .emit();
+            superseded[available[j]] = true;
+        }
+    }
+    transactions
+        .into_iter()
+        .zip(superseded)
+        .filter(|(_, superseded)| !superseded)
+        .map(|(t, _)| t)
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config::PendingMatchConfig;
+    use crate::transaction::test::{pending, transaction};
+    use crate::transaction::transaction_io::pending::{PendingCandidate, best_match};
+
+    #[test]
+    fn test_best_match() {
+        let pending_transactions = [
+            pending(9, "SQ *BLUE BOTTLE", "-4.50"),
+            pending(10, "SHELL OIL 5744", "-1.00"),
+            pending(9, "STARBUCKS", "-5.00"),
+        ];
+        let candidates = || {
+            pending_transactions
+                .iter()
+                .map(PendingCandidate::from_transaction)
+        };
+
+        // Only exact amounts within a week by default
+        let c = PendingMatchConfig::default();
+        assert_eq!(
+            best_match(
+                &c,
+                candidates(),
+                &transaction(10, "BLUE BOTTLE COFFEE", "-4.50")
+            ),
+            Option::Some(0)
+        );
+        assert_eq!(
+            best_match(
+                &c,
+                candidates(),
+                &transaction(10, "BLUE BOTTLE COFFEE", "-5.40")
+            ),
+            Option::None
+        );
+        assert_eq!(
+            best_match(
+                &c,
+                candidates(),
+                &transaction(20, "BLUE BOTTLE COFFEE", "-4.50")
+            ),
+            Option::None
+        );
+        // Nor from another payee with the same amount
+        assert_eq!(
+            best_match(
+                &c,
+                candidates(),
+                &transaction(10, "SHELL OIL 5744", "-5.00")
+            ),
+            Option::None
+        );
+
+        // A tip was added; without a payee similarity, the closest amount wins
+        let c = PendingMatchConfig {
+            amount_tolerance_percent: 25.0,
+            payee_similarity: 0.0,
+            ..PendingMatchConfig::default()
+        };
+        assert_eq!(
+            best_match(
+                &c,
+                candidates(),
+                &transaction(10, "BLUE BOTTLE COFFEE", "-5.40")
+            ),
+            Option::Some(2)
+        );
+
+        // The payee disambiguates
+        let c = PendingMatchConfig {
+            amount_tolerance_percent: 25.0,
+            payee_similarity: 0.6,
+            ..PendingMatchConfig::default()
+        };
+        assert_eq!(
+            best_match(
+                &c,
+                candidates(),
+                &transaction(10, "BLUE BOTTLE COFFEE", "-5.40")
+            ),
+            Option::Some(0)
+        );
+
+        // Fuel pre-authorizations clear for a very different amount
+        let c = PendingMatchConfig {
+            amount_tolerance: Option::Some(String::from("100")),
+            payee_similarity: 0.6,
+            ..PendingMatchConfig::default()
+        };
+        assert_eq!(
+            best_match(
+                &c,
+                candidates(),
+                &transaction(11, "SHELL OIL 574400", "-48.12")
+            ),
+            Option::Some(1)
+        );
+    }
+}
@@ -0,0 +1,349 @@
+use crate::config::{AmountFormat, FormatConfigFile};
+use crate::diagnostics::Diagnostic;
+use anyhow::anyhow;
+use chrono::NaiveDate;
+use currency::Currency;
+use std::collections::HashMap;
+use std::path::Path;
+
+// How many rows are checked against the date and amount formats
+const SAMPLE_ROWS: usize = 20;
+// Formats with scores closer than this are ambiguous
+const AMBIGUOUS_SCORE: f64 = 0.01;
+
+type Row = HashMap<String, String>;
+
+/// How well a file matches a format
+struct Detection<'a> {
+    format: &'a FormatConfigFile,
+    /// Columns of the format's signature that aren't in the header row
+    missing: Vec<String>,
+    /// Overlap between the header row and the format's columns, between 0 and 1
+    columns: f64,
+    /// Sampled rows whose date parses with the format's date format, and the number sampled
+    dates: (usize, usize),
+    /// Sampled rows whose amounts parse, and the number sampled
+    amounts: (usize, usize),
+}
+
+impl Detection<'_> {
+    fn is_match(&self) -> bool {
+        self.missing.is_empty()
+    }
+
+    fn score(&self) -> f64 {
+        if !self.is_match() {
+            return 0.0;
+        }
+        let fraction = |(n, total): (usize, usize)| {
+            if total == 0 {
+                1.0
+            } else {
+                n as f64 / total as f64
+            }
+        };
+        (self.columns + fraction(self.dates) + fraction(self.amounts)) / 3.0
+    }
+
+    fn explain(&self) -> String {
+        if !self.is_match() {
+            return format!(
+                "{} (not a match): missing columns [{}]",
+                self.format.id,
+                self.missing.join(", ")
+            );
+        }
+        format!(
+            "{} (score {:.2}): {:.0}% of the columns match, {} of {} dates match [{}], {} of {} amounts parse",
+            self.format.id,
+            self.score(),
+            self.columns * 100.0,
+            self.dates.0,
+            self.dates.1,
+            self.format.date_time_config.date_format,
+            self.amounts.0,
+            self.amounts.1
+        )
+    }
+}
+
+/// Choose the format to import a file with. A format whose file name pattern matches wins.
+/// Otherwise, the file's header row and a sample of its values are compared with each format,
+/// and the best match wins; the account's own format breaks ties. With a single format, every
+/// file is imported with it.
+pub fn format_for_file<'a>(
+    formats: &'a [FormatConfigFile],
+    preferred_id: &str,
+    path: Option<&Path>,
+    contents: &[u8],
+) -> anyhow::Result<&'a FormatConfigFile> {
+    if let [format] = formats {
+        return Ok(format);
+    }
+    let file_name = path
+        .and_then(|p| p.file_name())
+        .and_then(|n| n.to_str())
+        .unwrap_or_default();
+    let by_name: Vec<&FormatConfigFile> = formats
+        .iter()
+        .filter(|f| file_name_matches(f, file_name))
+        .collect();
+    if let [format] = by_name[..] {
+        Diagnostic::info(format!(
+            "Using format [{}] for file [{}]: the file name matches [{}]",
+            format.id,
+            file_name,
+            format
+                .file_match
+                .as_ref()
+                .and_then(|c| c.file_name_pattern.as_deref())
+                .unwrap_or_default()
+        ))
+        .emit();
+        return Ok(format);
+    }
+    let candidates = if by_name.is_empty() {
+        formats.iter().collect()
+    } else {
+        by_name
+    };
+    let mut detections: Vec<Detection> = candidates
+        .into_iter()
+        .map(|f| detect(f, contents))
+        .collect();
+    detections.sort_by(|a, b| {
+        b.score()
+            .total_cmp(&a.score())
+            .then_with(|| (b.format.id == preferred_id).cmp(&(a.format.id == preferred_id)))
+    });
+    let ranking = || {
+        detections
+            .iter()
+            .enumerate()
+            .map(|(i, d)| format!("  {}. {}", i + 1, d.explain()))
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+    let best = match detections.first() {
+        Option::Some(d) if d.is_match() => d,
+        _ => {
+            return Err(anyhow!(
+                "None of the source formats match the file. Candidates:\n{}",
+                ranking()
+            ));
+        }
+    };
+    let ambiguous = detections[1..].iter().any(|d| {
+        d.is_match() && best.score() - d.score() < AMBIGUOUS_SCORE && best.format.id != preferred_id
+    });
+    if ambiguous {
+        return Err(anyhow!(
+            "Several source formats match the file equally well. Candidates:\n{}",
+            ranking()
+        ));
+    }
+    Diagnostic::info(format!(
+        "Detected format [{}] for file [{}]: {}",
+        best.format.id,
+        file_name,
+        best.explain()
+    ))
+    .emit();
+    Ok(best.format)
+}
+
+fn detect<'a>(f: &'a FormatConfigFile, contents: &[u8]) -> Detection<'a> {
+    let (headers, rows) = read_sample(f, contents).unwrap_or_default();
+    let missing: Vec<String> = signature(f)
+        .into_iter()
+        .filter(|s| !headers.contains(s))
+        .collect();
+    let in_both = headers.iter().filter(|h| f.field_order.contains(h)).count();
+    let in_either = headers.len() + f.field_order.len() - in_both;
+    let columns = if in_either == 0 {
+        0.0
+    } else {
+        in_both as f64 / in_either as f64
+    };
+    let value = |row: &Row, field: &str| {
+        row.get(field)
+            .map(|v| v.trim().to_owned())
+            .unwrap_or_default()
+    };
+    let dates = rows
+        .iter()
+        .filter(|r| {
+            NaiveDate::parse_from_str(
+                &value(r, &f.date_time_config.date_field),
+                &f.date_time_config.date_format,
+            )
+            .is_ok()
+        })
+        .count();
+    let amounts = rows
+        .iter()
+        .filter(|r| {
+            let parses = |field: &str| Currency::from_str(&value(r, field)).is_ok();
+            match f.amount_config.format {
+                AmountFormat::SingleAmountField(ref c) => parses(&c.field_name),
+                AmountFormat::SeparateDebitCreditFields(ref c) => {
+                    parses(&c.debit_field) || parses(&c.credit_field)
+                }
+                AmountFormat::TransactionTypeAndAmountFields(ref c) => {
+                    let t = value(r, &c.transaction_type_field);
+                    parses(&c.amount_field) && (t == c.credit_string || t == c.debit_string)
+                }
+            }
+        })
+        .count();
+    Detection {
+        format: f,
+        missing,
+        columns,
+        dates: (dates, rows.len()),
+        amounts: (amounts, rows.len()),
+    }
+}
+
+/// The header row and the first rows of the file, assuming it's in the format
+fn read_sample(f: &FormatConfigFile, contents: &[u8]) -> Option<(Vec<String>, Vec<Row>)> {
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(f.delimiter())
+        .from_reader(skip_preamble(f, contents)?);
+    let headers: Vec<String> = reader
+        .headers()
+        .ok()?
+        .iter()
+        .map(|h| h.trim().to_owned())
+        .collect();
+    let rows = reader
+        .records()
+        .take(SAMPLE_ROWS)
+        .filter_map(Result::ok)
+        .map(|r| {
+            headers
+                .iter()
+                .cloned()
+                .zip(r.iter().map(str::to_owned))
+                .collect()
+        })
+        .collect();
+    Option::Some((headers, rows))
+}
+
+fn skip_preamble<'a>(f: &FormatConfigFile, contents: &'a [u8]) -> Option<&'a [u8]> {
+    let preamble_lines = f.preamble_config.as_ref().map_or(0, |c| c.line_count);
+    let mut rest = contents;
+    for _ in 0..preamble_lines {
+        let end = rest.iter().position(|b| *b == b'\n')?;
+        rest = &rest[end + 1..];
+    }
+    Option::Some(rest)
+}
+
+fn file_name_matches(f: &FormatConfigFile, file_name: &str) -> bool {
+    f.file_match
+        .as_ref()
+        .and_then(|c| c.file_name_pattern.as_ref())
+        .and_then(|p| glob::Pattern::new(p).ok())
+        .is_some_and(|p| {
+            p.matches_with(
+                file_name,
+                glob::MatchOptions {
+                    case_sensitive: false,
+                    ..Default::default()
+                },
+            )
+        })
+}
+
+/// The columns that identify the format: the configured headers, or else the columns it imports
+fn signature(f: &FormatConfigFile) -> Vec<String> {
+    if let Option::Some(c) = f.file_match.as_ref().filter(|c| !c.headers.is_empty()) {
+        return c.headers.to_owned();
+    }
+    let mut fields = vec![
+        f.date_time_config.date_field.to_owned(),
+        f.payee_config.field_name.to_owned(),
+    ];
+    fields.extend(f.date_time_config.time_field.to_owned());
+    match f.amount_config.format {
+        AmountFormat::SingleAmountField(ref c) => fields.push(c.field_name.to_owned()),
+        AmountFormat::SeparateDebitCreditFields(ref c) => {
+            fields.push(c.debit_field.to_owned());
+            fields.push(c.credit_field.to_owned());
+        }
+        AmountFormat::TransactionTypeAndAmountFields(ref c) => {
+            fields.push(c.transaction_type_field.to_owned());
+            fields.push(c.amount_field.to_owned());
+        }
+    }
+    fields.extend(f.status_config.as_ref().map(|c| c.field_name.to_owned()));
+    fields
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config::FormatConfigFile;
+    use crate::transaction::transaction_io::routing::format_for_file;
+    use std::path::Path;
+
+    fn format(id: &str, date_field: &str, file_match: &str) -> FormatConfigFile {
+        serde_json::from_str(&format!(
+            r#"{{"id": "{}", "name": "{}", "dataFormat": "csv",
+                "fieldOrder": ["{}", "Description", "Amount"],
+                "dateTimeConfig": {{"dateField": "{}", "dateFormat": "%Y-%m-%d"}},
+                "payeeConfig": {{"fieldName": "Description"}},
+                "amountConfig": {{"format": {{"type": "SingleAmountField", "fieldName": "Amount", "debitIsNegative": true}}}},
+                "fileMatch": {}}}"#,
+            id, id, date_field, date_field, file_match
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_format_for_file() {
+        let formats = vec![
+            format("bank", "Date", "null"),
+            format(
+                "card",
+                "Posted Date",
+                r#"{"fileNamePattern": "card_*.csv"}"#,
+            ),
+        ];
+        let contents = b"Date,Description,Amount\n2024-01-09,STARBUCKS,-4.10\n";
+        let path = Path::new("dl/export.csv");
+        let f = format_for_file(&formats, "bank", Option::Some(path), contents).unwrap();
+        assert_eq!(f.id, "bank");
+
+        let contents = b"Posted Date,Description,Amount\n2024-01-09,STARBUCKS,-4.10\n";
+        let f = format_for_file(&formats, "bank", Option::None, contents).unwrap();
+        assert_eq!(f.id, "card");
+
+        // The file name wins over the headers
+        let contents = b"Date,Description,Amount\n2024-01-09,STARBUCKS,-4.10\n";
+        let path = Path::new("dl/CARD_jan.csv");
+        let f = format_for_file(&formats, "bank", Option::Some(path), contents).unwrap();
+        assert_eq!(f.id, "card");
+
+        let contents = b"Foo,Bar\n1,2\n";
+        assert!(format_for_file(&formats, "bank", Option::None, contents).is_err());
+    }
+
+    #[test]
+    fn test_format_for_file_by_values() {
+        let mut us = format("us", "Date", "null");
+        us.date_time_config.date_format = "%m/%d/%Y".to_owned();
+        let formats = vec![format("iso", "Date", "null"), us];
+        let contents = b"Date,Description,Amount\n01/09/2024,STARBUCKS,-4.10\n";
+        let f = format_for_file(&formats, "iso", Option::None, contents).unwrap();
+        assert_eq!(f.id, "us");
+
+        // Equally good matches are ambiguous, unless one of them is the account's format
+        let contents = b"Date,Description,Amount\n";
+        let f = format_for_file(&formats, "iso", Option::None, contents).unwrap();
+        assert_eq!(f.id, "iso");
+        let e = format_for_file(&formats, "other", Option::None, contents).unwrap_err();
+        assert!(e.to_string().contains("1. "));
+    }
+}
@@ -0,0 +1,366 @@
+use crate::config::{Config, PendingMatchConfig, SupersededPolicy};
+use crate::diagnostics::Diagnostic;
+use crate::transaction::transaction_io::pending::{PendingCandidate, best_match};
+use crate::transaction::{ChangeType, Transaction, TransactionStatus, TransactionType};
+use crate::util;
+use crate::util::{currency_to_cents, currency_to_string_without_delim};
+use chrono::NaiveDate;
+use currency::Currency;
+use num::Signed;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::path::Path;
+
+/// Transactions exported by previous runs, used to skip them when the same dates are imported
+/// again.
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct StateFile {
+    /// The state key of every exported transaction
+    #[serde(rename = "exported", default)]
+    exported: BTreeSet<String>,
+    /// Exported transactions that were still pending. Once posted, the description of a
+    /// transaction often changes, so these are matched by amount and date instead.
+    #[serde(rename = "pending", default)]
+    pending: Vec<PendingEntry>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct PendingEntry {
+    #[serde(rename = "key")]
+    key: String,
+    #[serde(rename = "date")]
+    date: NaiveDate,
+    #[serde(rename = "amount")]
+    amount: String,
+    #[serde(rename = "rawPayee")]
+    raw_payee: String,
+}
+
+impl StateFile {
+    pub fn from_file(filename: &Path) -> anyhow::Result<StateFile> {
+        // There's no state until the first export
+        if !filename.exists() {
+            return Ok(StateFile::default());
+        }
+        let r = util::reader_from_file_name(filename)?;
+        let state: StateFile = serde_json::from_reader(r)?;
+        Ok(state)
+    }
+
+    pub fn save(&self, filename: &Path) -> anyhow::Result<()> {
+        let s = serde_json::to_string_pretty(self)?;
+        util::write_file_with_backup(filename, &s)
+    }
+
+    /// Remove the transactions that were already exported by a previous run. Cleared
+    /// transactions that replace a previously exported pending transaction are handled according
+    /// to the superseded policy: skipped and recorded as exported in place of the pending
+    /// transaction, kept as an update of the pending transaction, or kept along with a removal of
+    /// the pending transaction.
+    pub fn remove_exported(
+        &mut self,
+        c: &PendingMatchConfig,
+        transactions: Vec<Transaction>,
+    ) -> Vec<Transaction> {
+        let before = transactions.len();
+        // The fingerprint leaves out the status, so a pending transaction that posted unchanged
+        // has the same key. It's done with, and mustn't be matched to another cleared transaction.
+        let posted: HashSet<String> = transactions
+            .iter()
+            .filter(|t| t.status == TransactionStatus::Cleared)
+            .map(Transaction::state_key)
+            .filter(|key| self.exported.contains(key))
+            .collect();
+        // Pending transactions that can be forgotten, and those matched to a cleared transaction
+        let mut done: Vec<bool> = self
+            .pending
+            .iter()
+            .map(|p| posted.contains(&p.key))
+            .collect();
+        let mut claimed = done.clone();
+        let mut r = Vec::new();
+        for mut t in transactions {
+            let key = t.state_key();
+            if self.exported.contains(&key) {
+                continue;
+            }
+            if t.status != TransactionStatus::Cleared {
+                r.push(t);
+                continue;
+            }
+            let available: Vec<usize> = (0..self.pending.len()).filter(|i| !claimed[*i]).collect();
+            let candidates = available.iter().map(|i| self.pending[*i].candidate());
+            let i = match best_match(c, candidates, &t) {
+                Option::Some(j) => available[j],
+                Option::None => {
+                    r.push(t);
+                    continue;
+                }
+            };
+            claimed[i] = true;
+            let p = &self.pending[i];
+            match c.superseded_policy {
+                SupersededPolicy::Skip => {
+                    self.exported.insert(key);
+                    done[i] = true;
+                }
+                SupersededPolicy::Update => {
+                    t.change = ChangeType::Update;
+                    t.supersedes = Option::Some(p.key.to_owned());
+                    r.push(t);
+                }
+                SupersededPolicy::Remove => {
+                    r.push(p.removal());
+                    r.push(t);
+                }
+            }
+        }
+        let mut done = done.into_iter();
+        self.pending.retain(|_| !done.next().unwrap_or_default());
+        let skipped = before - r.iter().filter(|t| t.change != ChangeType::Remove).count();
+        if skipped > 0 {
+            Diagnostic::info(format!(
+                "Skipped {} transaction(s) that were exported by a previous run",
+                skipped
+            ))
+            .emit();
+        }
+        r
+    }
+
+    /// Remember the exported transactions so they're skipped by future runs
+    pub fn record_exported(&mut self, transactions: &[Transaction]) {
+        for t in transactions {
+            if let Option::Some(ref supersedes) = t.supersedes {
+                self.pending.retain(|p| &p.key != supersedes);
+            }
+            if t.change == ChangeType::Remove {
+                continue;
+            }
+            // The parts of a split transaction are recorded as the transaction that was imported
+            let key = t.imported_key();
+            if t.status == TransactionStatus::Pending && !self.exported.contains(&key) {
+                self.pending.push(PendingEntry {
+                    key: key.to_owned(),
+                    date: t.date.date(),
+                    amount: currency_to_string_without_delim(&t.signed_amount()),
+                    raw_payee: t.raw_payee_name.to_owned(),
+                });
+            }
+            self.exported.insert(key);
+        }
+    }
+}
+
+impl PendingEntry {
+    fn candidate(&self) -> PendingCandidate<'_> {
+        PendingCandidate {
+            date: self.date,
+            cents: self
+                .amount()
+                .map(|a| currency_to_cents(&a))
+                .unwrap_or_default(),
+            raw_payee: &self.raw_payee,
+        }
+    }
+
+    fn amount(&self) -> Option<Currency> {
+        Currency::from_str(&self.amount).ok()
+    }
+
+    /// A transaction that removes the exported pending transaction
+    fn removal(&self) -> Transaction {
+        let amount = self.amount().unwrap_or_default();
+        let transaction_type = if amount.value().is_negative() {
+            TransactionType::Debit
+        } else {
+            TransactionType::Credit
+        };
+        Transaction::builder()
+            .date(self.date.and_time(Default::default()))
+            .raw_payee_name(self.raw_payee.to_owned())
+            .transaction_type(transaction_type)
+            .amount(amount)
+            .status(TransactionStatus::Pending)
+            .change(ChangeType::Remove)
+            .supersedes(Option::Some(self.key.to_owned()))
+            .build()
+    }
+}
+
+/// Number the transactions that share a fingerprint, in the order they were imported, so that
+/// legitimate repeats (e.g. two identical purchases on the same day) get different state keys.
+pub fn assign_occurrences(transactions: &mut [Transaction]) {
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for t in transactions.iter_mut() {
+        let count = counts.entry(t.fingerprint()).or_insert(0);
+        t.occurrence = *count;
+        *count += 1;
+    }
+}
+
+/// Remove the transactions exported by a previous run. Nothing is saved until the transactions
+/// are exported, so commands that only read the source files leave the state file unchanged.
+pub fn remove_exported(
+    config: &mut Config,
+    transactions: Vec<Transaction>,
+) -> anyhow::Result<Vec<Transaction>> {
+    if !config.since_last_run() {
+        return Ok(transactions);
+    }
+    let mut state = StateFile::from_file(&config.state_file())?;
+    let pending_match = config.pending_match().cloned().unwrap_or_default();
+    let transactions = state.remove_exported(&pending_match, transactions);
+    // Posted versions of pending transactions that were skipped are recorded along with the
+    // exported transactions
+    config.set_state(state);
+    Ok(transactions)
+}
+
+pub fn record_exported(config: &Config, transactions: &[Transaction]) -> anyhow::Result<()> {
+    if !config.since_last_run() {
+        return Ok(());
+    }
+    let filename = config.state_file();
+    let mut state = match config.state() {
+        Option::Some(state) => state.to_owned(),
+        Option::None => StateFile::from_file(&filename)?,
+    };
+    state.record_exported(transactions);
+    state.save(&filename)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config::{PendingMatchConfig, SupersededPolicy};
+    use crate::transaction::test::{pending, transaction};
+    use crate::transaction::transaction_io::state::{StateFile, assign_occurrences};
+    use crate::transaction::{ChangeType, Transaction};
+
+    fn imported(mut transactions: Vec<Transaction>) -> Vec<Transaction> {
+        assign_occurrences(&mut transactions);
+        transactions
+    }
+
+    fn payees(transactions: &[Transaction]) -> Vec<(&str, &ChangeType)> {
+        transactions
+            .iter()
+            .map(|t| (t.raw_payee_name.as_str(), &t.change))
+            .collect()
+    }
+
+    #[test]
+    fn test_remove_exported() {
+        let c = PendingMatchConfig::default();
+        let mut state = StateFile::default();
+        state.record_exported(&imported(vec![
+            transaction(2, "STARBUCKS", "-4.10"),
+            transaction(3, "COSTCO", "-120.00"),
+        ]));
+
+        // An identical re-import is skipped, new transactions are kept
+        let r = state.remove_exported(
+            &c,
+            imported(vec![
+                transaction(2, "STARBUCKS", "-4.10"),
+                transaction(3, "COSTCO", "-120.00"),
+                transaction(4, "SHELL OIL", "-40.00"),
+            ]),
+        );
+        assert_eq!(payees(&r), vec![("SHELL OIL", &ChangeType::Add)]);
+
+        // A second identical purchase on the same day is a new occurrence
+        let r = state.remove_exported(
+            &c,
+            imported(vec![
+                transaction(2, "STARBUCKS", "-4.10"),
+                transaction(2, "STARBUCKS", "-4.10"),
+            ]),
+        );
+        assert_eq!(payees(&r), vec![("STARBUCKS", &ChangeType::Add)]);
+        assert_eq!(r[0].occurrence, 1);
+
+        // The parts of a split transaction are recorded as the imported transaction
+        let original = transaction(4, "SHELL OIL", "-40.00");
+        let parts: Vec<Transaction> = ["-30.00", "-10.00"]
+            .iter()
+            .map(|amount| Transaction {
+                split_from: Option::Some(original.state_key()),
+                ..transaction(4, "SHELL OIL", amount)
+            })
+            .collect();
+        state.record_exported(&parts);
+        assert!(
+            state
+                .remove_exported(&c, imported(vec![original]))
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_superseded_policy() {
+        let posted = || imported(vec![transaction(10, "BLUE BOTTLE COFFEE", "-4.50")]);
+        let exported = |policy| {
+            let mut state = StateFile::default();
+            state.record_exported(&[pending(9, "SQ *BLUE BOTTLE", "-4.50")]);
+            let c = PendingMatchConfig {
+                superseded_policy: policy,
+                ..PendingMatchConfig::default()
+            };
+            (state, c)
+        };
+        let pending_key = pending(9, "SQ *BLUE BOTTLE", "-4.50").state_key();
+
+        // Skipped, and recorded in place of the pending transaction
+        let (mut state, c) = exported(SupersededPolicy::Skip);
+        assert!(state.remove_exported(&c, posted()).is_empty());
+        assert!(state.pending.is_empty());
+        assert!(state.remove_exported(&c, posted()).is_empty());
+
+        // Exported as an update; the pending transaction is forgotten once that's recorded
+        let (mut state, c) = exported(SupersededPolicy::Update);
+        let r = state.remove_exported(&c, posted());
+        assert_eq!(
+            payees(&r),
+            vec![("BLUE BOTTLE COFFEE", &ChangeType::Update)]
+        );
+        assert_eq!(r[0].supersedes.as_ref(), Option::Some(&pending_key));
+        assert_eq!(state.pending.len(), 1);
+        state.record_exported(&r);
+        assert!(state.pending.is_empty());
+
+        // Exported along with a removal of the pending transaction
+        let (mut state, c) = exported(SupersededPolicy::Remove);
+        let r = state.remove_exported(&c, posted());
+        assert_eq!(
+            payees(&r),
+            vec![
+                ("SQ *BLUE BOTTLE", &ChangeType::Remove),
+                ("BLUE BOTTLE COFFEE", &ChangeType::Add)
+            ]
+        );
+        assert_eq!(r[0].supersedes.as_ref(), Option::Some(&pending_key));
+        state.record_exported(&r);
+        assert!(state.pending.is_empty());
+    }
+
+    #[test]
+    fn test_posted_unchanged() {
+        // Match any payee, so only the key keeps the pending transaction from being reused
+        let c = PendingMatchConfig {
+            payee_similarity: 0.0,
+            ..PendingMatchConfig::default()
+        };
+        let mut state = StateFile::default();
+        state.record_exported(&[pending(9, "STARBUCKS", "-5.00")]);
+
+        let r = state.remove_exported(
+            &c,
+            imported(vec![
+                transaction(9, "STARBUCKS", "-5.00"),
+                transaction(11, "SHELL OIL", "-5.00"),
+            ]),
+        );
+        assert_eq!(payees(&r), vec![("SHELL OIL", &ChangeType::Add)]);
+        assert!(state.pending.is_empty());
+    }
+}
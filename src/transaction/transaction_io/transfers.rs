@@ -0,0 +1,265 @@
+use crate::config::{Config, TransferMatchConfig, TransferRule};
+use crate::diagnostics::Diagnostic;
+use crate::transaction::{ChangeType, Transaction, Transfer};
+use crate::util::{currency_to_cents, stable_hash};
+use std::collections::HashSet;
+
+/// The parts of an account's config that transfer matching uses
+struct Account<'a> {
+    id: &'a str,
+    name: &'a str,
+    transfer_match: Option<&'a TransferMatchConfig>,
+}
+
+impl Account<'_> {
+    fn rules(&self) -> &[TransferRule] {
+        self.transfer_match.map_or(&[], |m| &m.rules)
+    }
+}
+
+/// A possible transfer between transaction `a.1` of account `a.0` and transaction `b.1` of
+/// account `b.0`
+struct Candidate {
+    a: (usize, usize),
+    b: (usize, usize),
+    // Whether a transfer rule points at the other account
+    guided: bool,
+    days: i64,
+}
+
+/// Pair opposite transactions with equal amounts in different accounts, and mark both sides as
+/// transfers. `batches` holds the transactions of each account, in the same order as `configs`.
+/// Only accounts with a `transferMatch` config take part.
+pub fn detect_transfers(configs: &[&Config], batches: &mut [Vec<Transaction>]) {
+    let accounts: Vec<Account> = configs
+        .iter()
+        .map(|c| Account {
+            id: &c.account().id,
+            name: &c.account().name,
+            transfer_match: c.transfer_match(),
+        })
+        .collect();
+    pair_transfers(&accounts, batches);
+}
+
+fn pair_transfers(accounts: &[Account], batches: &mut [Vec<Transaction>]) {
+    let mut candidates = Vec::new();
+    for a in 0..batches.len() {
+        for b in (a + 1)..batches.len() {
+            if accounts[a].id == accounts[b].id {
+                continue;
+            }
+            find_candidates(accounts, batches, a, b, &mut candidates);
+        }
+    }
+    // Pairs guided by a rule win, then the pairs closest in time
+    candidates.sort_by_key(|c| (!c.guided, c.days));
+
+    let mut paired: HashSet<(usize, usize)> = HashSet::new();
+    for c in candidates {
+        if paired.contains(&c.a) || paired.contains(&c.b) {
+            continue;
+        }
+        paired.insert(c.a);
+        paired.insert(c.b);
+        let id = format!(
+            "{:016x}",
+            stable_hash(&format!(
+                "{}|{}",
+                batches[c.a.0][c.a.1].state_key(),
+                batches[c.b.0][c.b.1].state_key()
+            ))
+        );
+        Diagnostic::info(format!(
+            "Matched transfer: [account: {}] {} <-> [account: {}] {}",
+            accounts[c.a.0].name,
+            batches[c.a.0][c.a.1].describe(),
+            accounts[c.b.0].name,
+            batches[c.b.0][c.b.1].describe()
+        ))
+        .account(accounts[c.a.0].name)
+        .transaction(&batches[c.a.0][c.a.1])
+        .emit();
+        batches[c.a.0][c.a.1].transfer = Option::Some(transfer_to(&accounts[c.b.0], &id));
+        batches[c.b.0][c.b.1].transfer = Option::Some(transfer_to(&accounts[c.a.0], &id));
+    }
+}
+
+fn find_candidates(
+    accounts: &[Account],
+    batches: &[Vec<Transaction>],
+    a: usize,
+    b: usize,
+    candidates: &mut Vec<Candidate>,
+) {
+    let windows: Vec<u32> = [&accounts[a], &accounts[b]]
+        .iter()
+        .filter_map(|account| account.transfer_match)
+        .map(|m| m.date_window_days)
+        .collect();
+    let Option::Some(window) = windows.into_iter().max().map(i64::from) else {
+        return;
+    };
+    // Pairs no rule points at are only considered if both accounts ask for them
+    let match_unguided = [&accounts[a], &accounts[b]]
+        .iter()
+        .all(|account| account.transfer_match.is_some_and(|m| m.match_unguided));
+    let (rules_a, rules_b) = (accounts[a].rules(), accounts[b].rules());
+
+    for (i, t) in batches[a].iter().enumerate() {
+        let cents = currency_to_cents(&t.signed_amount());
+        if cents == 0 || t.change == ChangeType::Remove {
+            continue;
+        }
+        let rule_t = rule_account_id(rules_a, t);
+        if rule_t.is_some_and(|id| id != accounts[b].id) {
+            continue;
+        }
+        for (j, u) in batches[b].iter().enumerate() {
+            if u.change == ChangeType::Remove || currency_to_cents(&u.signed_amount()) != -cents {
+                continue;
+            }
+            let days = (t.date.date() - u.date.date()).num_days().abs();
+            if days > window {
+                continue;
+            }
+            let rule_u = rule_account_id(rules_b, u);
+            if rule_u.is_some_and(|id| id != accounts[a].id) {
+                continue;
+            }
+            let guided = rule_t.is_some() || rule_u.is_some();
+            if !guided && !match_unguided {
+                continue;
+            }
+            candidates.push(Candidate {
+                a: (a, i),
+                b: (b, j),
+                guided,
+                days,
+            });
+        }
+    }
+}
+
+/// The account that a transfer rule for the transaction's payee points at, if any
+fn rule_account_id<'a>(rules: &'a [TransferRule], t: &Transaction) -> Option<&'a str> {
+    let payee_id = t.normalized_payee_id.as_ref()?;
+    rules
+        .iter()
+        .find(|r| &r.payee_id == payee_id)
+        .map(|r| r.account_id.as_str())
+}
+
+fn transfer_to(account: &Account, id: &str) -> Transfer {
+    Transfer {
+        account_name: account.name.to_owned(),
+        id: id.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config::TransferMatchConfig;
+    use crate::transaction::test::transaction;
+    use crate::transaction::transaction_io::transfers::{Account, pair_transfers};
+    use crate::transaction::{ChangeType, Transaction};
+    use serde_json::json;
+
+    fn transfer_match(config: serde_json::Value) -> TransferMatchConfig {
+        serde_json::from_value(config).unwrap()
+    }
+
+    fn paired(accounts: &[Account], batches: &mut [Vec<Transaction>]) -> Vec<Vec<Option<String>>> {
+        pair_transfers(accounts, batches);
+        batches
+            .iter()
+            .map(|b| {
+                b.iter()
+                    .map(|t| t.transfer.as_ref().map(|x| x.account_name.to_owned()))
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_pair_transfers() {
+        let guided = transfer_match(json!({
+            "matchUnguided": true,
+            "rules": [{"payeeId": "card-payment", "accountId": "card"}]
+        }));
+        let unguided = transfer_match(json!({"matchUnguided": true, "dateWindowDays": 2}));
+        let accounts = |checking, card| {
+            [
+                Account {
+                    id: "checking",
+                    name: "Checking",
+                    transfer_match: checking,
+                },
+                Account {
+                    id: "card",
+                    name: "Card",
+                    transfer_match: card,
+                },
+            ]
+        };
+        // An ATM withdrawal and a card payment from checking, and the payment on the card
+        let batches = |withdrawal_day, payment_day| {
+            let mut payment = transaction(payment_day, "CARD PAYMENT", "-100.00");
+            payment.normalized_payee_id = Option::Some(String::from("card-payment"));
+            vec![
+                vec![
+                    transaction(withdrawal_day, "ATM WITHDRAWAL", "-100.00"),
+                    payment,
+                ],
+                vec![transaction(6, "PAYMENT THANK YOU", "100.00")],
+            ]
+        };
+        let none = || vec![vec![Option::None, Option::None], vec![Option::None]];
+        let withdrawal_paired = || {
+            vec![
+                vec![Option::Some(String::from("Card")), Option::None],
+                vec![Option::Some(String::from("Checking"))],
+            ]
+        };
+        let payment_paired = || {
+            vec![
+                vec![Option::None, Option::Some(String::from("Card"))],
+                vec![Option::Some(String::from("Checking"))],
+            ]
+        };
+
+        // Nothing is paired without transferMatch
+        let mut b = batches(6, 5);
+        assert_eq!(
+            paired(&accounts(Option::None, Option::None), &mut b),
+            none()
+        );
+
+        // The pair a rule points at wins over a closer unguided pair
+        let mut b = batches(6, 5);
+        let a = accounts(Option::Some(&guided), Option::Some(&unguided));
+        assert_eq!(paired(&a, &mut b), payment_paired());
+        // Without the rule, the closest pair wins
+        let mut b = batches(6, 5);
+        b[0][1].normalized_payee_id = Option::None;
+        assert_eq!(paired(&a, &mut b), withdrawal_paired());
+
+        // Unguided pairs need both accounts to opt in
+        let mut b = batches(6, 5);
+        b[0][1].normalized_payee_id = Option::None;
+        let a = accounts(Option::Some(&guided), Option::None);
+        assert_eq!(paired(&a, &mut b), none());
+
+        // Pairs must be within the larger of the two date windows
+        let mut b = batches(2, 3);
+        b[0][1].normalized_payee_id = Option::None;
+        let a = accounts(Option::Some(&guided), Option::Some(&unguided));
+        assert_eq!(paired(&a, &mut b), payment_paired());
+
+        // Rows removed since the last run are never paired
+        let mut b = batches(6, 5);
+        b[1][0].change = ChangeType::Remove;
+        let a = accounts(Option::Some(&guided), Option::Some(&unguided));
+        assert_eq!(paired(&a, &mut b), none());
+    }
+}
@@ -1,8 +1,11 @@
 use anyhow::anyhow;
 use currency::Currency;
+use num::ToPrimitive;
+use std::fs;
 use std::fs::File;
 use std::io;
-use std::path::Path;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
 
 pub fn get_optional_string(s: &str) -> Option<String> {
     if !s.trim().is_empty() {
@@ -23,6 +26,91 @@ pub fn reader_from_file_name(filename: &Path) -> anyhow::Result<Box<dyn io::Read
     }
 }
 
+/// Replace the contents of a file, keeping a copy of the previous contents in `<filename>.bak`.
+/// The new contents are written to a temporary file first so the original is never left
+/// partially written.
+pub fn write_file_with_backup(filename: &Path, contents: &str) -> anyhow::Result<()> {
+    let display_name = filename.to_str().unwrap_or("Invalid file name");
+    if filename.exists() {
+        fs::copy(filename, path_with_suffix(filename, ".bak")).map_err(|e| {
+            anyhow!(
+                "Unable to create a backup of file [{}]: {}",
+                display_name,
+                e
+            )
+        })?;
+    }
+    let tmp = path_with_suffix(filename, ".tmp");
+    fs::write(&tmp, contents)
+        .and_then(|_| fs::rename(&tmp, filename))
+        .map_err(|e| anyhow!("Unable to write file [{}]: {}", display_name, e))
+}
+
+/// Append a suffix to the full file name, e.g. `account.json` -> `account.json.bak`
+pub fn path_with_suffix(filename: &Path, suffix: &str) -> PathBuf {
+    let mut s = filename.as_os_str().to_owned();
+    s.push(suffix);
+    PathBuf::from(s)
+}
+
+/// The value of the currency in cents
+pub fn currency_to_cents(c: &Currency) -> i64 {
+    c.value().to_i64().unwrap_or_default()
+}
+
+/// Read a line from stdin, without the trailing newline. Returns `None` once stdin is closed.
+/// Prompts are written to stderr, so they never mix with transactions exported to stdout.
+pub fn read_line() -> Option<String> {
+    // Make sure the prompt is visible before waiting for input
+    let _ = io::stderr().flush();
+    let mut s = String::new();
+    match io::stdin().lock().read_line(&mut s) {
+        Ok(0) | Err(_) => Option::None,
+        Ok(_) => Option::Some(s.trim_end_matches(['\r', '\n']).to_owned()),
+    }
+}
+
+/// Read the number of a menu option between 0 and `max`, asking again if the input isn't valid.
+/// An empty line or the end of the input selects option 0.
+pub fn read_choice(max: usize) -> usize {
+    loop {
+        let s = match read_line() {
+            Option::Some(s) => s,
+            Option::None => return 0,
+        };
+        if s.trim().is_empty() {
+            return 0;
+        }
+        match s.trim().parse::<usize>() {
+            Ok(n) if n <= max => return n,
+            // Part of the prompt, so it isn't a diagnostic that --quiet would hide
+            _ => eprintln!(
+                "[{}] is not a valid option, please enter a number between 0 and {}:",
+                s.trim(),
+                max
+            ),
+        }
+    }
+}
+
+/// Build a currency value from an amount in cents
+pub fn cents_to_currency(cents: i64) -> Currency {
+    let sign = if cents < 0 { "-" } else { "" };
+    let cents = cents.unsigned_abs();
+    Currency::from_str(&format!("{}{}.{:02}", sign, cents / 100, cents % 100)).unwrap_or_default()
+}
+
+/// 64-bit FNV-1a hash. Unlike `DefaultHasher`, the result is guaranteed to be the same across
+/// runs and Rust versions, so it's safe to save.
+pub fn stable_hash(s: &str) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for b in s.bytes() {
+        hash ^= u64::from(b);
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
 // For some reason, the Currency type prepends a ',' to values in the hundreds, so just remove
 // all ',' from the string generated by Currency to avoid such silliness.
 pub fn currency_to_string_without_delim(c: &Currency) -> String {
@@ -32,7 +120,10 @@ pub fn currency_to_string_without_delim(c: &Currency) -> String {
 
 #[cfg(test)]
 mod test {
-    use crate::util::{currency_to_string_without_delim, get_optional_string};
+    use crate::util::{
+        cents_to_currency, currency_to_cents, currency_to_string_without_delim,
+        get_optional_string, stable_hash,
+    };
     use currency::Currency;
 
     #[test]
@@ -46,6 +137,22 @@ mod test {
         assert_eq!(s, "10.00");
     }
 
+    #[test]
+    fn test_cents_to_currency() {
+        let c = cents_to_currency(-123456);
+        assert_eq!(currency_to_string_without_delim(&c), "-1234.56");
+        assert_eq!(currency_to_cents(&c), -123456);
+
+        let c = cents_to_currency(5);
+        assert_eq!(currency_to_string_without_delim(&c), "0.05");
+    }
+
+    #[test]
+    fn test_stable_hash() {
+        assert_eq!(stable_hash(""), 0xcbf29ce484222325);
+        assert_eq!(stable_hash("a"), 0xaf63dc4c8601ec8c);
+    }
+
     #[test]
     fn test_get_optional_string() {
         let s = "";